@@ -0,0 +1,3535 @@
+use indexmap::IndexMap;
+use num_bigint::BigInt as BigIntValue;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use swc_core::common::{FileName, SourceMap, SourceMapper, Span, Spanned, SyntaxContext, DUMMY_SP};
+use swc_core::ecma::ast::*;
+use swc_core::ecma::parser::lexer::Lexer;
+use swc_core::ecma::parser::{Parser, StringInput};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::value::{
+  date_to_iso_string, date_year, js_number_to_string, time_clip, to_int32, to_uint32,
+};
+use crate::{
+  eval_hint, globals, JsValue, MacroError, DEFAULT_MAX_ARRAY_LENGTH, DEFAULT_MAX_STRING_LENGTH,
+};
+
+/// Statically evaluates an expression, using the given constants to resolve identifiers.
+///
+/// Supports literals, template literals, arrays and objects (including spreads and computed
+/// keys), unary, binary, and conditional operators, member access on constants, and calls
+/// to a small set of global functions such as `encodeURIComponent`. Other function calls,
+/// functions, classes, and references to anything other than the given constants and
+/// `undefined` cannot be evaluated, and return an `EvaluationError` pointing at the
+/// unsupported sub-expression.
+pub fn eval_expr(expr: &Expr, constants: &HashMap<Id, JsValue>) -> Result<JsValue, MacroError> {
+  let evaluator = Evaluator {
+    constants,
+    cache: None,
+    cache_hits: None,
+    shared_spans: None,
+    unresolved_ctxt: None,
+    build_time: None,
+    source_map: None,
+    env: None,
+    missing_env_undefined: false,
+    global_types: None,
+    max_string_length: DEFAULT_MAX_STRING_LENGTH,
+    oversized_strings: None,
+    max_array_length: DEFAULT_MAX_ARRAY_LENGTH,
+    oversized_arrays: None,
+  };
+  evaluator.eval(expr).map_err(|span| {
+    let hint = eval_hint(expr, span, &HashSet::new(), &|id| evaluator.is_global(id));
+    MacroError::EvaluationError(span, hint)
+  })
+}
+
+/// Strips parentheses and TypeScript type assertions such as `x as const` and `x!`,
+/// which do not affect the value of the inner expression.
+pub(crate) fn unwrap_expr(mut expr: &Expr) -> &Expr {
+  loop {
+    expr = match expr {
+      Expr::Paren(ParenExpr { expr, .. })
+      | Expr::TsAs(TsAsExpr { expr, .. })
+      | Expr::TsConstAssertion(TsConstAssertion { expr, .. })
+      | Expr::TsNonNull(TsNonNullExpr { expr, .. })
+      | Expr::TsTypeAssertion(TsTypeAssertion { expr, .. })
+      | Expr::TsSatisfies(TsSatisfiesExpr { expr, .. }) => expr,
+      _ => return expr,
+    }
+  }
+}
+
+/// Converts a primitive value to a number, as JS operators do. Objects are
+/// converted via their methods, which can't be evaluated, so return None.
+/// BigInts are not implicitly converted, since mixing them with numbers throws.
+fn primitive_to_number(value: &JsValue) -> Option<f64> {
+  match value {
+    JsValue::Undefined
+    | JsValue::Null
+    | JsValue::Bool(_)
+    | JsValue::Number(_)
+    | JsValue::String(_) => Some(value.to_js_number()),
+    _ => None,
+  }
+}
+
+/// Compares a BigInt with a number by their mathematical values, as `<` and `==` do.
+/// Returns None if the number is NaN, which is not comparable.
+fn compare_bigint_number(a: &BigIntValue, b: f64) -> Option<Ordering> {
+  if b.is_nan() {
+    return None;
+  }
+  if b.is_infinite() {
+    return Some(if b > 0.0 {
+      Ordering::Less
+    } else {
+      Ordering::Greater
+    });
+  }
+  // Integral numbers are formatted exactly, so the comparison does not round. A BigInt
+  // equal to the floor of a fractional number is less than it.
+  let floor: BigIntValue = format!("{:.0}", b.floor()).parse().unwrap();
+  match a.cmp(&floor) {
+    Ordering::Equal if b.fract() != 0.0 => Some(Ordering::Less),
+    ord => Some(ord),
+  }
+}
+
+/// Methods supported by `Evaluator::eval_method`.
+const SUPPORTED_METHODS: &[&str] = &[
+  "at",
+  "concat",
+  "entries",
+  "every",
+  "fill",
+  "filter",
+  "find",
+  "findIndex",
+  "get",
+  "getFullYear",
+  "getTime",
+  "has",
+  "hasOwnProperty",
+  "join",
+  "keys",
+  "map",
+  "match",
+  "matchAll",
+  "normalize",
+  "padEnd",
+  "padStart",
+  "repeat",
+  "some",
+  "split",
+  "test",
+  "toISOString",
+  "toLocaleString",
+  "toString",
+  "values",
+];
+
+/// If the expression is a function without parameters whose body only returns a value,
+/// e.g. `() => 42` or `function() { return "a"; }`, returns the returned expression.
+fn returned_expr(expr: &Expr) -> Option<&Expr> {
+  let body = match expr {
+    Expr::Arrow(arrow) if arrow.params.is_empty() && !arrow.is_async && !arrow.is_generator => {
+      match &*arrow.body {
+        BlockStmtOrExpr::Expr(expr) => return Some(expr),
+        BlockStmtOrExpr::BlockStmt(block) => block,
+      }
+    }
+    Expr::Fn(FnExpr { function, .. })
+      if function.params.is_empty() && !function.is_async && !function.is_generator =>
+    {
+      function.body.as_ref()?
+    }
+    _ => return None,
+  };
+  match &body.stmts[..] {
+    [Stmt::Return(ReturnStmt { arg: Some(arg), .. })] => Some(arg),
+    _ => None,
+  }
+}
+
+/// Returns the values produced by iterating over a value, as in a spread. Strings are
+/// iterated by code point, so surrogate pairs are not split, and maps by `[key, value]` entry.
+pub(crate) fn iterate(value: JsValue) -> Option<Vec<JsValue>> {
+  match value {
+    JsValue::Array(arr) | JsValue::Set(arr) => Some(arr),
+    JsValue::Map(entries) => Some(
+      entries
+        .into_iter()
+        .map(|(k, v)| JsValue::Array(vec![k, v]))
+        .collect(),
+    ),
+    JsValue::String(s) => Some(s.chars().map(|c| JsValue::String(c.to_string())).collect()),
+    _ => None,
+  }
+}
+
+/// Returns the own enumerable properties of a value, as `Object.entries` does. Strings are
+/// indexed by UTF-16 code unit.
+fn own_entries(value: &JsValue) -> Option<Vec<(String, JsValue)>> {
+  match value {
+    JsValue::Object(obj) => Some(obj.iter().map(|(k, v)| (k.clone(), v.clone())).collect()),
+    JsValue::Array(arr) => Some(
+      arr
+        .iter()
+        .enumerate()
+        .map(|(i, v)| (i.to_string(), v.clone()))
+        .collect(),
+    ),
+    JsValue::String(s) => Some(
+      s.encode_utf16()
+        .enumerate()
+        .map(|(i, c)| {
+          (
+            i.to_string(),
+            JsValue::String(String::from_utf16_lossy(&[c])),
+          )
+        })
+        .collect(),
+    ),
+    _ => None,
+  }
+}
+
+/// Returns the names of the own properties of a value, as `Object.getOwnPropertyNames`
+/// does. Like in JS, arrays and strings include their indices and `length`.
+fn own_property_names(value: &JsValue) -> Option<Vec<String>> {
+  let indices = |len: usize| {
+    (0..len)
+      .map(|i| i.to_string())
+      .chain(std::iter::once("length".to_string()))
+      .collect()
+  };
+  match value {
+    JsValue::Object(obj) => Some(obj.keys().cloned().collect()),
+    JsValue::Array(arr) => Some(indices(arr.len())),
+    JsValue::String(s) => Some(indices(s.encode_utf16().count())),
+    _ => None,
+  }
+}
+
+/// Global functions that can be passed as callbacks to array methods, since they only
+/// convert their argument to a primitive.
+const COERCION_FUNCTIONS: &[&str] = &["Boolean", "Number", "String"];
+
+/// The value of the global `Boolean` function, which is the only callback supported
+/// by array methods that test elements, such as `every` and `filter`.
+fn is_boolean_function(value: Option<&JsValue>) -> bool {
+  matches!(value, Some(JsValue::Function(source)) if source == "Boolean")
+}
+
+/// Compiles a regular expression using a JS compatible engine. Returns None if the
+/// pattern is invalid, or it has duplicate, unknown, or the given unsupported flags.
+fn compile_regex(source: &str, flags: &str, unsupported: &str) -> Option<regress::Regex> {
+  for (i, flag) in flags.char_indices() {
+    if !"dgimsuvy".contains(flag) || unsupported.contains(flag) || flags[..i].contains(flag) {
+      return None;
+    }
+  }
+  regress::Regex::with_flags(source, flags).ok()
+}
+
+/// Converts a regex match to an array containing the matched string followed by the
+/// capture groups, with `undefined` for groups that did not participate in the match.
+/// Unlike JS, the `index`, `input`, and `groups` properties are not included, so named
+/// groups are only available by position.
+fn match_groups(s: &str, m: &regress::Match) -> JsValue {
+  JsValue::Array(
+    m.groups()
+      .map(|group| match group {
+        Some(range) => JsValue::String(s[range].to_owned()),
+        None => JsValue::Undefined,
+      })
+      .collect(),
+  )
+}
+
+/// Implements `String.prototype.split` with a regular expression separator, including
+/// capture groups in the result. Returns at most `limit` elements.
+fn split_regex(s: &str, re: &regress::Regex, limit: usize) -> Vec<JsValue> {
+  let mut res = Vec::new();
+  if s.is_empty() {
+    // An empty string is only split if the separator matches it.
+    if re.find(s).is_none() {
+      res.push(JsValue::String(String::new()));
+    }
+    return res;
+  }
+
+  // The start of the current element, and the position to search for the next separator.
+  let mut p = 0;
+  let mut q = 0;
+  while q < s.len() {
+    let m = match re.find_from(s, q).next() {
+      Some(m) if m.start() < s.len() => m,
+      _ => break,
+    };
+    // An empty match at the start of an element is not a separator, so search again from
+    // the next character.
+    if m.end() == p {
+      q = m.start() + s[m.start()..].chars().next().map_or(1, char::len_utf8);
+      continue;
+    }
+
+    let groups = m.groups().skip(1).map(|group| match group {
+      Some(range) => JsValue::String(s[range].to_owned()),
+      None => JsValue::Undefined,
+    });
+    for value in std::iter::once(JsValue::String(s[p..m.start()].to_owned())).chain(groups) {
+      if res.len() == limit {
+        return res;
+      }
+      res.push(value);
+    }
+    p = m.end();
+    q = p;
+  }
+
+  if res.len() < limit {
+    res.push(JsValue::String(s[p..].to_owned()));
+  }
+  res
+}
+
+/// The largest integer that can be exactly represented by a number.
+const MAX_SAFE_INTEGER: f64 = 9007199254740991.0;
+
+/// Implements `Number.prototype.toString` with a radix other than 10. Only integers are
+/// supported, since fractional digits in other bases are rarely useful in macro arguments.
+fn integer_to_radix_string(n: f64, radix: u32) -> Option<String> {
+  if n.fract() != 0.0 || n.abs() > MAX_SAFE_INTEGER {
+    return None;
+  }
+  let mut digits = Vec::new();
+  let mut rest = n.abs() as u64;
+  loop {
+    digits.push(std::char::from_digit((rest % radix as u64) as u32, radix)?);
+    rest /= radix as u64;
+    if rest == 0 {
+      break;
+    }
+  }
+  if n < 0.0 {
+    digits.push('-');
+  }
+  Some(digits.iter().rev().collect())
+}
+
+/// Implements `Number.prototype.toLocaleString` for a few locales whose default format only
+/// differs in the grouping and decimal separators. Like `Intl.NumberFormat`, the number is
+/// rounded half away from zero to at most 3 fraction digits. Returns None for other locales.
+fn number_to_locale_string(n: f64, locale: &str) -> Option<String> {
+  let (group, decimal) = match locale {
+    "en" | "en-US" | "en-GB" => (',', '.'),
+    "de" | "de-DE" => ('.', ','),
+    _ => return None,
+  };
+  let sign = if n.is_sign_negative() { "-" } else { "" };
+  if n.is_nan() {
+    return Some("NaN".into());
+  } else if n.is_infinite() {
+    return Some(format!("{}∞", sign));
+  }
+
+  // Round the shortest decimal representation of the number, as JS does, rather than
+  // its exact binary value, e.g. 1.0005 rounds up to 1.001.
+  let exponential = format!("{:e}", n.abs());
+  let (mantissa, exponent) = exponential.split_once('e')?;
+  let mut digits: Vec<u8> = mantissa.bytes().filter(|b| *b != b'.').collect();
+  let mut int_len = exponent.parse::<isize>().ok()? + 1;
+  if int_len <= 0 {
+    let mut padded = vec![b'0'; (1 - int_len) as usize];
+    padded.append(&mut digits);
+    digits = padded;
+    int_len = 1;
+  }
+  let int_len = int_len as usize;
+  if digits.len() < int_len {
+    digits.resize(int_len, b'0');
+  }
+  if digits.len() > int_len + 3 {
+    let round_up = digits[int_len + 3] >= b'5';
+    digits.truncate(int_len + 3);
+    if round_up {
+      let mut i = digits.len();
+      loop {
+        if i == 0 {
+          digits.insert(0, b'1');
+          break;
+        }
+        i -= 1;
+        if digits[i] == b'9' {
+          digits[i] = b'0';
+        } else {
+          digits[i] += 1;
+          break;
+        }
+      }
+    }
+  }
+
+  let int_len = digits.len() - (digits.len() - int_len).min(3);
+  let (int, fraction) = digits.split_at(int_len);
+  let mut res = sign.to_string();
+  for (i, digit) in int.iter().enumerate() {
+    if i > 0 && (int.len() - i) % 3 == 0 {
+      res.push(group);
+    }
+    res.push(*digit as char);
+  }
+  let fraction = std::str::from_utf8(fraction).ok()?.trim_end_matches('0');
+  if !fraction.is_empty() {
+    res.push(decimal);
+    res.push_str(fraction);
+  }
+  Some(res)
+}
+
+/// Resolves a relative index argument (e.g. the start of `fill` or `slice`) to a position
+/// in a sequence of the given length. Negative indices count back from the end. Returns
+/// None if the argument is not a number.
+fn relative_index(arg: Option<&JsValue>, len: usize, default: usize) -> Option<usize> {
+  match arg {
+    None | Some(JsValue::Undefined) => Some(default),
+    Some(JsValue::Number(n)) if n.is_nan() => Some(0),
+    Some(JsValue::Number(n)) => {
+      let n = n.trunc();
+      let len = len as f64;
+      Some(if n < 0.0 {
+        (len + n).max(0.0)
+      } else {
+        n.min(len)
+      } as usize)
+    }
+    _ => None,
+  }
+}
+
+/// A source of constant values that identifiers are resolved against.
+pub(crate) trait Constants {
+  /// Returns the value of a constant, or the span where it was mutated if it can no longer be used.
+  fn get_constant(&self, id: &Id) -> Option<Result<&JsValue, Span>>;
+}
+
+impl Constants for HashMap<Id, JsValue> {
+  fn get_constant(&self, id: &Id) -> Option<Result<&JsValue, Span>> {
+    self.get(id).map(Ok)
+  }
+}
+
+impl Constants for HashMap<Id, Result<JsValue, Span>> {
+  fn get_constant(&self, id: &Id) -> Option<Result<&JsValue, Span>> {
+    self.get(id).map(|val| val.as_ref().map_err(|span| *span))
+  }
+}
+
+/// Whether the value is an object, which is compared by reference rather than by value.
+fn is_object(val: &JsValue) -> bool {
+  matches!(
+    val,
+    JsValue::Object(_)
+      | JsValue::Array(_)
+      | JsValue::Map(_)
+      | JsValue::Set(_)
+      | JsValue::Date(_)
+      | JsValue::Regex { .. }
+  )
+}
+
+/// Returns the result of `typeof` for a value.
+fn type_of(val: &JsValue) -> &'static str {
+  match val {
+    JsValue::Bool(_) => "boolean",
+    JsValue::Number(_) => "number",
+    JsValue::String(_) => "string",
+    JsValue::BigInt(_) => "bigint",
+    JsValue::Undefined => "undefined",
+    JsValue::Function(_) => "function",
+    JsValue::Object(_)
+    | JsValue::Array(_)
+    | JsValue::Map(_)
+    | JsValue::Set(_)
+    | JsValue::Date(_)
+    | JsValue::Regex { .. }
+    | JsValue::Null => "object",
+  }
+}
+
+/// Evaluates expressions against a set of constants, optionally caching results by span.
+pub(crate) struct Evaluator<'a, C> {
+  pub constants: &'a C,
+  pub cache: Option<&'a RefCell<HashMap<Span, Result<JsValue, Span>>>>,
+  /// Incremented whenever a result is returned from `cache`.
+  pub cache_hits: Option<&'a Cell<usize>>,
+  /// Spans shared by several nodes, e.g. the source of a function returned by a macro, which
+  /// is remapped to the macro call. These cannot be used as cache keys.
+  pub shared_spans: Option<&'a RefCell<HashSet<Span>>>,
+  /// The syntax context of unresolved (global) references, if the resolver has run.
+  /// Otherwise, any identifier that is not a constant is assumed to be a global.
+  pub unresolved_ctxt: Option<SyntaxContext>,
+  /// The time in milliseconds since the epoch returned by `Date.now()`. If not set,
+  /// the current time cannot be evaluated, so builds are reproducible.
+  pub build_time: Option<f64>,
+  /// Used to get the source text of object methods, which evaluate to functions.
+  /// Without it, objects containing methods cannot be evaluated.
+  pub source_map: Option<&'a SourceMap>,
+  /// Environment variables that `process.env.KEY` and `import.meta.env.KEY` evaluate to.
+  /// If not set, these cannot be evaluated.
+  pub env: Option<&'a HashMap<String, JsValue>>,
+  /// Whether environment variables missing from `env` evaluate to undefined.
+  pub missing_env_undefined: bool,
+  /// The result of `typeof` for globals in the target environment, e.g. `window`.
+  pub global_types: Option<&'a HashMap<String, String>>,
+  /// The maximum length of strings produced during evaluation. Longer strings are errors.
+  pub max_string_length: usize,
+  /// Records the spans of expressions that produced strings longer than `max_string_length`.
+  pub oversized_strings: Option<&'a RefCell<HashSet<Span>>>,
+  /// The maximum length of arrays created by `new Array(len)`. Longer arrays are errors.
+  pub max_array_length: usize,
+  /// Records the spans of expressions that created arrays longer than `max_array_length`.
+  pub oversized_arrays: Option<&'a RefCell<HashSet<Span>>>,
+}
+
+impl<'a, C: Constants> Evaluator<'a, C> {
+  /// Whether the identifier refers to a global rather than a local binding.
+  pub(crate) fn is_global(&self, id: &Ident) -> bool {
+    self.constants.get_constant(&id.to_id()).is_none()
+      && !matches!(self.unresolved_ctxt, Some(ctxt) if id.span.ctxt != ctxt)
+  }
+
+  /// Whether both expressions reference the same named constant.
+  fn is_same_constant(&self, a: &Expr, b: &Expr) -> bool {
+    match (unwrap_expr(a), unwrap_expr(b)) {
+      (Expr::Ident(a), Expr::Ident(b)) => {
+        a.to_id() == b.to_id() && self.constants.get_constant(&a.to_id()).is_some()
+      }
+      _ => false,
+    }
+  }
+
+  /// Whether the identifier is a global that is not defined in any environment, so `typeof`
+  /// evaluates to "undefined". Globals of the target environment such as `window` may only be
+  /// defined at runtime, and are configured with `global_types` instead. Without the resolver's
+  /// mark, the identifier could be a local binding such as a parameter, so it is not undeclared.
+  fn is_undeclared(&self, id: &Ident) -> bool {
+    self.unresolved_ctxt == Some(id.span.ctxt)
+      && self.is_global(id)
+      && !globals::is_known_global(&id.sym)
+  }
+
+  /// If the expression is a property of a global object, e.g. `String.raw`,
+  /// returns the names of the object and property.
+  fn global_member<'e>(&self, expr: &'e Expr) -> Option<(&'e str, &'e str)> {
+    match expr {
+      Expr::Member(MemberExpr {
+        obj,
+        prop: MemberProp::Ident(prop),
+        ..
+      }) => match &**obj {
+        Expr::Ident(id) if self.is_global(id) => Some((&id.sym, &prop.sym)),
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+
+  /// Whether the property key is a symbol, e.g. `[Symbol.iterator]` or `[Symbol("x")]`.
+  fn is_symbol_key(&self, key: &PropName) -> bool {
+    let expr = match key {
+      PropName::Computed(c) => unwrap_expr(&c.expr),
+      _ => return false,
+    };
+    let expr = match expr {
+      Expr::Call(CallExpr {
+        callee: Callee::Expr(callee),
+        ..
+      }) => unwrap_expr(callee),
+      expr => expr,
+    };
+    match expr {
+      Expr::Ident(id) => self.is_global(id) && &*id.sym == "Symbol",
+      expr => matches!(self.global_member(expr), Some(("Symbol", _))),
+    }
+  }
+
+  /// Returns the configured result of `typeof` for a global identifier.
+  fn global_type(&self, expr: &Expr) -> Option<&'a String> {
+    match unwrap_expr(expr) {
+      Expr::Ident(id) if self.is_global(id) => self.global_types?.get(&*id.sym),
+      _ => None,
+    }
+  }
+
+  /// Whether the expression is `process.env` or `import.meta.env`.
+  fn is_env_object(&self, expr: &Expr) -> bool {
+    match unwrap_expr(expr) {
+      Expr::Member(MemberExpr {
+        obj,
+        prop: MemberProp::Ident(prop),
+        ..
+      }) if &prop.sym == "env" => match unwrap_expr(obj) {
+        Expr::Ident(id) => &id.sym == "process" && self.is_global(id),
+        Expr::MetaProp(MetaPropExpr {
+          kind: MetaPropKind::ImportMeta,
+          ..
+        }) => true,
+        _ => false,
+      },
+      _ => false,
+    }
+  }
+
+  /// Resolves `process.env.KEY` or `import.meta.env.KEY` against the configured environment
+  /// variables. Returns None if the expression is not an environment variable access.
+  fn eval_env(&self, member: &MemberExpr) -> Option<Result<Cow<'a, JsValue>, Span>> {
+    let env = self.env?;
+    if !self.is_env_object(&member.obj) {
+      return None;
+    }
+
+    let key = match &member.prop {
+      MemberProp::Ident(id) => id.sym.to_string(),
+      MemberProp::Computed(c) => match self.eval(&c.expr) {
+        Ok(JsValue::String(key)) => key,
+        Ok(_) => return Some(Err(c.span)),
+        Err(span) => return Some(Err(span)),
+      },
+      MemberProp::PrivateName(_) => return None,
+    };
+    Some(match env.get(&key) {
+      Some(val) => Ok(Cow::Borrowed(val)),
+      None if self.missing_env_undefined => Ok(Cow::Owned(JsValue::Undefined)),
+      None => Err(member.span),
+    })
+  }
+
+  /// Evaluates a template literal. If `raw` is true, escape sequences in the
+  /// template are not processed, as in `String.raw`.
+  fn eval_tpl(&self, tpl: &Tpl, raw: bool) -> Result<JsValue, Span> {
+    let mut res = String::new();
+    let mut exprs = tpl.exprs.iter();
+    for quasi in &tpl.quasis {
+      match &quasi.cooked {
+        Some(cooked) if !raw => res.push_str(cooked),
+        _ => res.push_str(&quasi.raw),
+      }
+      if let Some(expr) = exprs.next() {
+        res.push_str(&self.eval(expr)?.to_js_string());
+      }
+    }
+
+    Ok(JsValue::String(res))
+  }
+
+  /// Evaluates the function call form of `String.raw`, e.g. `String.raw({ raw: ["a", "b"] }, 1)`.
+  fn eval_string_raw(&self, call: &CallExpr) -> Result<JsValue, Span> {
+    let mut args = call.args.iter().map(|arg| match arg.spread {
+      None => self.eval(&arg.expr),
+      Some(_) => Err(call.span),
+    });
+
+    let strings = match args.next().transpose()? {
+      Some(JsValue::Object(mut obj)) => match obj.swap_remove("raw") {
+        Some(JsValue::Array(strings)) => strings,
+        _ => return Err(call.span),
+      },
+      _ => return Err(call.span),
+    };
+
+    let mut res = String::new();
+    let len = strings.len();
+    for (i, s) in strings.into_iter().enumerate() {
+      res.push_str(&s.to_js_string());
+      if i + 1 < len {
+        if let Some(val) = args.next().transpose()? {
+          res.push_str(&val.to_js_string());
+        }
+      }
+    }
+
+    Ok(JsValue::String(res))
+  }
+
+  /// Evaluates a call to a supported global function with static arguments.
+  fn eval_call(&self, call: &CallExpr) -> Result<JsValue, Span> {
+    let callee = match &call.callee {
+      Callee::Expr(callee) => &**callee,
+      _ => return Err(call.span),
+    };
+
+    // Immediately invoked functions that only return a value, e.g. `(() => 42)()`.
+    if let Some(expr) = returned_expr(unwrap_expr(callee)) {
+      if !call.args.is_empty() {
+        return Err(call.span);
+      }
+      return self.eval(expr);
+    }
+
+    if let Some(member) = self.global_member(callee) {
+      return match member {
+        ("String", "raw") => self.eval_string_raw(call),
+        ("Array", "of") => Ok(JsValue::Array(self.eval_args(call)?)),
+        ("Array", "from") => {
+          // The map function form is not supported.
+          if call.args.len() != 1 {
+            return Err(call.span);
+          }
+          let mut args = self.eval_args(call)?;
+          match (args.pop(), args.is_empty()) {
+            (Some(JsValue::String(s)), true) => Ok(JsValue::Array(
+              s.chars().map(|c| JsValue::String(c.to_string())).collect(),
+            )),
+            (Some(arr @ JsValue::Array(_)), true) => Ok(arr),
+            _ => Err(call.span),
+          }
+        }
+        ("Object", "getOwnPropertyNames") => {
+          let args = self.eval_args(call)?;
+          let names = args.first().and_then(own_property_names).ok_or(call.span)?;
+          Ok(JsValue::Array(
+            names.into_iter().map(JsValue::String).collect(),
+          ))
+        }
+        ("Object", name @ ("keys" | "values" | "entries")) => {
+          let args = self.eval_args(call)?;
+          let entries = args.first().and_then(own_entries).ok_or(call.span)?;
+          Ok(JsValue::Array(
+            entries
+              .into_iter()
+              .map(|(k, v)| match name {
+                "keys" => JsValue::String(k),
+                "values" => v,
+                _ => JsValue::Array(vec![JsValue::String(k), v]),
+              })
+              .collect(),
+          ))
+        }
+        ("JSON", "stringify") => {
+          // The replacer and indentation arguments are not supported.
+          let args = self.eval_args(call)?;
+          match &args[..] {
+            [value] => globals::json_stringify(value)
+              .map(|json| json.map_or(JsValue::Undefined, JsValue::String))
+              .map_err(|_| call.span),
+            _ => Err(call.span),
+          }
+        }
+        ("Date", "now") => self.build_time.map(JsValue::Number).ok_or(call.span),
+        ("Math", name) => {
+          let args = self
+            .eval_args(call)?
+            .into_iter()
+            .map(|arg| match arg {
+              JsValue::Number(n) => Some(n),
+              _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(call.span)?;
+          globals::math(name, &args)
+            .map(JsValue::Number)
+            .ok_or(call.span)
+        }
+        _ => Err(call.span),
+      };
+    }
+
+    match callee {
+      Expr::Ident(id) if self.is_global(id) => match &*id.sym {
+        "encodeURI" | "encodeURIComponent" => {
+          let s = self.eval_string_arg(call)?;
+          Ok(JsValue::String(globals::encode_uri(
+            &s,
+            &id.sym == "encodeURIComponent",
+          )))
+        }
+        "decodeURI" | "decodeURIComponent" => {
+          let s = self.eval_string_arg(call)?;
+          globals::decode_uri(&s, &id.sym == "decodeURIComponent")
+            .map(JsValue::String)
+            .ok_or(call.span)
+        }
+        "btoa" => {
+          let s = self.eval_string_arg(call)?;
+          globals::btoa(&s).map(JsValue::String).ok_or(call.span)
+        }
+        "atob" => {
+          let s = self.eval_string_arg(call)?;
+          globals::atob(&s).map(JsValue::String).ok_or(call.span)
+        }
+        "Number" | "String" | "Boolean" => {
+          let args = self.eval_args(call)?;
+          Ok(match (&*id.sym, args.first()) {
+            ("Number", None) => JsValue::Number(0.0),
+            ("Number", Some(value)) => JsValue::Number(value.to_js_number()),
+            ("String", None) => JsValue::String(String::new()),
+            ("String", Some(value)) => JsValue::String(value.to_js_string()),
+            (_, value) => JsValue::Bool(value.is_some_and(JsValue::is_truthy)),
+          })
+        }
+        "parseInt" | "parseFloat" => {
+          let args = self.eval_args(call)?;
+          let s = args.first().unwrap_or(&JsValue::Undefined).to_js_string();
+          Ok(JsValue::Number(if &id.sym == "parseFloat" {
+            globals::parse_float(&s)
+          } else {
+            let radix = args
+              .get(1)
+              .map_or(0, |radix| to_int32(radix.to_js_number()));
+            globals::parse_int(&s, radix)
+          }))
+        }
+        // Values are already copied when evaluated, so cloning only needs to reject the
+        // functions that structuredClone cannot clone. Transfer options are not supported.
+        "structuredClone" => match &self.eval_args(call)?[..] {
+          [value] if !value.contains_function() => Ok(value.clone()),
+          _ => Err(call.span),
+        },
+        _ => Err(call.span),
+      },
+      Expr::Member(MemberExpr {
+        obj,
+        prop: MemberProp::Ident(method),
+        ..
+      }) => self.eval_method(obj, &method.sym, call),
+      _ => Err(call.span),
+    }
+  }
+
+  /// Evaluates a call to a method of a value. Methods never mutate the receiver,
+  /// e.g. `fill` returns a new array.
+  fn eval_method(&self, obj: &Expr, method: &str, call: &CallExpr) -> Result<JsValue, Span> {
+    // Avoid evaluating the receiver for unsupported methods, so errors point at the call.
+    if !SUPPORTED_METHODS.contains(&method) {
+      return Err(call.span);
+    }
+
+    let obj = self.eval_cow(obj)?;
+    let args = self.eval_args(call)?;
+    match (&*obj, method) {
+      (JsValue::Array(arr), "fill") => {
+        let len = arr.len();
+        let value = args.first().cloned().unwrap_or(JsValue::Undefined);
+        let start = relative_index(args.get(1), len, 0).ok_or(call.span)?;
+        let end = relative_index(args.get(2), len, len).ok_or(call.span)?;
+        let mut res = arr.clone();
+        if start < end {
+          res[start..end].fill(value);
+        }
+        Ok(JsValue::Array(res))
+      }
+      (JsValue::Array(arr), "every" | "some") => {
+        if !is_boolean_function(args.first()) {
+          return Err(call.span);
+        }
+        Ok(JsValue::Bool(if method == "every" {
+          arr.iter().all(JsValue::is_truthy)
+        } else {
+          arr.iter().any(JsValue::is_truthy)
+        }))
+      }
+      (JsValue::Array(arr), "map") => {
+        let convert = match args.first() {
+          Some(JsValue::Function(f)) if f == "Boolean" => {
+            |v: &JsValue| JsValue::Bool(v.is_truthy())
+          }
+          Some(JsValue::Function(f)) if f == "Number" => {
+            |v: &JsValue| JsValue::Number(v.to_js_number())
+          }
+          Some(JsValue::Function(f)) if f == "String" => {
+            |v: &JsValue| JsValue::String(v.to_js_string())
+          }
+          _ => return Err(call.span),
+        };
+        Ok(JsValue::Array(arr.iter().map(convert).collect()))
+      }
+      (JsValue::Array(arr), "filter") => {
+        if !is_boolean_function(args.first()) {
+          return Err(call.span);
+        }
+        Ok(JsValue::Array(
+          arr.iter().filter(|v| v.is_truthy()).cloned().collect(),
+        ))
+      }
+      (JsValue::Array(arr), "find" | "findIndex") => {
+        if !is_boolean_function(args.first()) {
+          return Err(call.span);
+        }
+        let index = arr.iter().position(|v| v.is_truthy());
+        Ok(match (method, index) {
+          ("find", Some(index)) => arr[index].clone(),
+          ("find", None) => JsValue::Undefined,
+          (_, Some(index)) => JsValue::Number(index as f64),
+          (_, None) => JsValue::Number(-1.0),
+        })
+      }
+      // Iterators cannot be represented, so they are materialized as arrays, which can be
+      // spread or iterated in the same way, e.g. `[...arr.entries()]`.
+      (JsValue::Array(arr), "keys" | "values" | "entries") => Ok(JsValue::Array(
+        arr
+          .iter()
+          .enumerate()
+          .map(|(i, v)| match method {
+            "keys" => JsValue::Number(i as f64),
+            "values" => v.clone(),
+            _ => JsValue::Array(vec![JsValue::Number(i as f64), v.clone()]),
+          })
+          .collect(),
+      )),
+      (JsValue::Map(entries), "keys" | "values" | "entries") => Ok(JsValue::Array(
+        entries
+          .iter()
+          .map(|(k, v)| match method {
+            "keys" => k.clone(),
+            "values" => v.clone(),
+            _ => JsValue::Array(vec![k.clone(), v.clone()]),
+          })
+          .collect(),
+      )),
+      // The keys of a set are its values.
+      (JsValue::Set(values), "keys" | "values" | "entries") => Ok(JsValue::Array(
+        values
+          .iter()
+          .map(|v| match method {
+            "entries" => JsValue::Array(vec![v.clone(), v.clone()]),
+            _ => v.clone(),
+          })
+          .collect(),
+      )),
+      (JsValue::Array(arr), "join") => {
+        let separator = match args.first() {
+          None | Some(JsValue::Undefined) => ",".to_string(),
+          Some(separator) => separator.to_js_string(),
+        };
+        // Like converting an array to a string, null and undefined become empty strings.
+        let parts: Vec<_> = arr
+          .iter()
+          .map(|val| match val {
+            JsValue::Null | JsValue::Undefined => String::new(),
+            val => val.to_js_string(),
+          })
+          .collect();
+        Ok(JsValue::String(parts.join(&separator)))
+      }
+      (obj, "hasOwnProperty") => {
+        let names = own_property_names(obj).ok_or(call.span)?;
+        let key = args.first().unwrap_or(&JsValue::Undefined).to_js_string();
+        Ok(JsValue::Bool(names.contains(&key)))
+      }
+      (JsValue::Set(values), "has") => {
+        let value = args.first().unwrap_or(&JsValue::Undefined);
+        Ok(JsValue::Bool(
+          values.iter().any(|v| v.same_value_zero(value)),
+        ))
+      }
+      (JsValue::Map(entries), "get" | "has") => {
+        let key = args.first().unwrap_or(&JsValue::Undefined);
+        let value = entries
+          .iter()
+          .find(|(k, _)| k.same_value_zero(key))
+          .map(|(_, v)| v);
+        Ok(match method {
+          "has" => JsValue::Bool(value.is_some()),
+          _ => value.cloned().unwrap_or(JsValue::Undefined),
+        })
+      }
+      (JsValue::String(s), "at") => {
+        // Like indexing, this uses UTF-16 code units. Negative indices count from the end.
+        let units: Vec<u16> = s.encode_utf16().collect();
+        let index = args.first().map_or(0.0, JsValue::to_js_number);
+        let index = if index.is_nan() { 0.0 } else { index.trunc() };
+        let index = if index < 0.0 {
+          index + units.len() as f64
+        } else {
+          index
+        };
+        Ok(if index >= 0.0 && index < units.len() as f64 {
+          JsValue::String(String::from_utf16_lossy(
+            &units[index as usize..=index as usize],
+          ))
+        } else {
+          JsValue::Undefined
+        })
+      }
+      (JsValue::String(s), "normalize") => {
+        let form = match args.first() {
+          None | Some(JsValue::Undefined) => "NFC".to_string(),
+          Some(form) => form.to_js_string(),
+        };
+        // Other forms throw a RangeError.
+        Ok(JsValue::String(match form.as_str() {
+          "NFC" => s.nfc().collect(),
+          "NFD" => s.nfd().collect(),
+          "NFKC" => s.nfkc().collect(),
+          "NFKD" => s.nfkd().collect(),
+          _ => return Err(call.span),
+        }))
+      }
+      (JsValue::String(s), "concat") => {
+        let mut res = s.clone();
+        for arg in &args {
+          res.push_str(&arg.to_js_string());
+        }
+        Ok(JsValue::String(res))
+      }
+      (JsValue::String(s), "repeat") => {
+        let count = args.first().map_or(0.0, JsValue::to_js_number);
+        let count = if count.is_nan() { 0.0 } else { count.trunc() };
+        // Negative and infinite counts throw a RangeError.
+        if count < 0.0 || count.is_infinite() {
+          return Err(call.span);
+        }
+        // Check the length before allocating, since the result could be huge.
+        if s.len() as f64 * count > self.max_string_length as f64 {
+          return Err(self.oversized_string(call.span));
+        }
+        Ok(JsValue::String(s.repeat(count as usize)))
+      }
+      (JsValue::String(s), "padStart" | "padEnd") => {
+        let target = args.first().map_or(0.0, JsValue::to_js_number);
+        let len = s.encode_utf16().count();
+        let filler = match args.get(1) {
+          None | Some(JsValue::Undefined) => " ".to_string(),
+          Some(filler) => filler.to_js_string(),
+        };
+        if target.is_nan() || target <= len as f64 || filler.is_empty() {
+          return Ok(JsValue::String(s.clone()));
+        }
+        // Check the length before allocating. Each code unit is at least one byte.
+        if target > self.max_string_length as f64 {
+          return Err(self.oversized_string(call.span));
+        }
+        // The filler is repeated and truncated to the target length in UTF-16 code units.
+        let units: Vec<u16> = filler
+          .encode_utf16()
+          .cycle()
+          .take(target as usize - len)
+          .collect();
+        let padding = String::from_utf16_lossy(&units);
+        Ok(JsValue::String(if method == "padStart" {
+          format!("{}{}", padding, s)
+        } else {
+          format!("{}{}", s, padding)
+        }))
+      }
+      // The default locale depends on the environment, and options are not supported.
+      (JsValue::Number(n), "toLocaleString") => match &args[..] {
+        [JsValue::String(locale)] => number_to_locale_string(*n, locale)
+          .map(JsValue::String)
+          .ok_or(call.span),
+        _ => Err(call.span),
+      },
+      (JsValue::Number(n), "toString") => match args.first() {
+        None | Some(JsValue::Undefined) => Ok(JsValue::String(js_number_to_string(*n))),
+        Some(JsValue::Number(radix)) if *radix == 10.0 => {
+          Ok(JsValue::String(js_number_to_string(*n)))
+        }
+        // Invalid radixes throw a RangeError.
+        Some(JsValue::Number(radix)) if (2.0..=36.0).contains(radix) && radix.fract() == 0.0 => {
+          integer_to_radix_string(*n, *radix as u32)
+            .map(JsValue::String)
+            .ok_or(call.span)
+        }
+        _ => Err(call.span),
+      },
+      // Dates are evaluated in UTC, so that the result does not depend on the time zone
+      // of the machine running the build.
+      (JsValue::Date(time), "getTime") => Ok(JsValue::Number(*time)),
+      (JsValue::Date(time), "getFullYear") => Ok(JsValue::Number(date_year(*time))),
+      (JsValue::Date(time), "toISOString") => date_to_iso_string(*time)
+        .map(JsValue::String)
+        .ok_or(call.span),
+      (JsValue::String(_) | JsValue::Bool(_) | JsValue::Array(_), "toString") => {
+        Ok(JsValue::String(obj.to_js_string()))
+      }
+      (JsValue::String(s), "split") => {
+        let limit = match args.get(1) {
+          None | Some(JsValue::Undefined) => u32::MAX as usize,
+          Some(limit) => to_uint32(primitive_to_number(limit).ok_or(call.span)?) as usize,
+        };
+        let res = match args.first() {
+          _ if limit == 0 => vec![],
+          None | Some(JsValue::Undefined) => vec![JsValue::String(s.clone())],
+          Some(JsValue::Regex { source, flags }) => {
+            let re = compile_regex(source, flags, "").ok_or(call.span)?;
+            split_regex(s, &re, limit)
+          }
+          // An empty separator splits into UTF-16 code units, like indexing.
+          Some(JsValue::String(sep)) if sep.is_empty() => s
+            .encode_utf16()
+            .take(limit)
+            .map(|c| JsValue::String(String::from_utf16_lossy(&[c])))
+            .collect(),
+          Some(
+            sep @ (JsValue::String(_) | JsValue::Number(_) | JsValue::Bool(_) | JsValue::Null),
+          ) => s
+            .split(sep.to_js_string().as_str())
+            .take(limit)
+            .map(|part| JsValue::String(part.to_owned()))
+            .collect(),
+          _ => return Err(call.span),
+        };
+        Ok(JsValue::Array(res))
+      }
+      (JsValue::Regex { source, flags }, "test") => {
+        // The global and sticky flags make test() stateful, so cannot be evaluated statically.
+        let re = compile_regex(source, flags, "gy").ok_or(call.span)?;
+        let s = args.first().unwrap_or(&JsValue::Undefined).to_js_string();
+        Ok(JsValue::Bool(re.find(&s).is_some()))
+      }
+      (JsValue::String(s), "match" | "matchAll") => {
+        let (source, flags) = match args.first() {
+          Some(JsValue::Regex { source, flags }) => (source.as_str(), flags.as_str()),
+          Some(JsValue::String(source)) => (source.as_str(), ""),
+          _ => return Err(call.span),
+        };
+        let global = flags.contains('g');
+        // matchAll throws a TypeError for non-global regular expressions.
+        if method == "matchAll" && !global {
+          return Err(call.span);
+        }
+        let re = compile_regex(source, flags, "y").ok_or(call.span)?;
+        let mut matches = re.find_iter(s);
+        Ok(match (method, global) {
+          ("matchAll", _) => JsValue::Array(matches.map(|m| match_groups(s, &m)).collect()),
+          (_, true) => {
+            let res: Vec<_> = matches
+              .map(|m| JsValue::String(s[m.range()].to_owned()))
+              .collect();
+            if res.is_empty() {
+              JsValue::Null
+            } else {
+              JsValue::Array(res)
+            }
+          }
+          _ => matches
+            .next()
+            .map_or(JsValue::Null, |m| match_groups(s, &m)),
+        })
+      }
+      _ => Err(call.span),
+    }
+  }
+
+  /// Evaluates a `new` expression for a small set of global constructors. Wrapper
+  /// objects such as `new String(x)` evaluate to the primitive value.
+  fn eval_new(&self, new: &NewExpr) -> Result<JsValue, Span> {
+    let name = match &*new.callee {
+      Expr::Ident(id) if self.is_global(id) => &*id.sym,
+      _ => return Err(new.span),
+    };
+    if ![
+      "Array", "Boolean", "Date", "Map", "Number", "RegExp", "Set", "String",
+    ]
+    .contains(&name)
+    {
+      return Err(new.span);
+    }
+
+    let mut args = Vec::new();
+    for arg in new.args.iter().flatten() {
+      match arg.spread {
+        None => args.push(self.eval(&arg.expr)?),
+        Some(_) => args.extend(iterate(self.eval(&arg.expr)?).ok_or(new.span)?),
+      }
+    }
+
+    let first = args.first().unwrap_or(&JsValue::Undefined);
+    match name {
+      // The current date is the configured build time. Dates can also be created from
+      // another date or a time in milliseconds, but not from a string or date components.
+      "Date" => match &args[..] {
+        [] => self
+          .build_time
+          .map(|time| JsValue::Date(time_clip(time)))
+          .ok_or(new.span),
+        [JsValue::Number(time) | JsValue::Date(time)] => Ok(JsValue::Date(time_clip(*time))),
+        _ => Err(new.span),
+      },
+      "Array" => match &args[..] {
+        // A single number is the length of the array, which must be a valid array length.
+        [JsValue::Number(len)] => {
+          if *len < 0.0 || len.fract() != 0.0 || *len >= u32::MAX as f64 {
+            return Err(new.span);
+          }
+          // Check the length before allocating, since the array could be huge.
+          if *len > self.max_array_length as f64 {
+            if let Some(oversized_arrays) = self.oversized_arrays {
+              oversized_arrays.borrow_mut().insert(new.span);
+            }
+            return Err(new.span);
+          }
+          Ok(JsValue::Array(vec![JsValue::Undefined; *len as usize]))
+        }
+        _ => Ok(JsValue::Array(args)),
+      },
+      "Boolean" => Ok(JsValue::Bool(first.is_truthy())),
+      "Map" => {
+        let mut entries: Vec<(JsValue, JsValue)> = Vec::new();
+        match first {
+          JsValue::Undefined | JsValue::Null => {}
+          JsValue::Array(arr) => {
+            for entry in arr {
+              let (key, value) = match entry {
+                JsValue::Array(entry) => (
+                  entry.first().cloned().unwrap_or(JsValue::Undefined),
+                  entry.get(1).cloned().unwrap_or(JsValue::Undefined),
+                ),
+                _ => return Err(new.span),
+              };
+              // Later entries overwrite earlier ones with the same key, keeping the position.
+              match entries.iter_mut().find(|(k, _)| k.same_value_zero(&key)) {
+                Some(entry) => entry.1 = value,
+                None => entries.push((key, value)),
+              }
+            }
+          }
+          _ => return Err(new.span),
+        }
+        Ok(JsValue::Map(entries))
+      }
+      "Set" => {
+        let mut values: Vec<JsValue> = Vec::new();
+        match first {
+          JsValue::Undefined | JsValue::Null => {}
+          JsValue::Array(arr) => {
+            for value in arr {
+              if !values.iter().any(|v| v.same_value_zero(value)) {
+                values.push(value.clone());
+              }
+            }
+          }
+          JsValue::String(s) => {
+            for c in s.chars() {
+              let value = JsValue::String(c.to_string());
+              if !values.contains(&value) {
+                values.push(value);
+              }
+            }
+          }
+          _ => return Err(new.span),
+        }
+        Ok(JsValue::Set(values))
+      }
+      "Number" => Ok(JsValue::Number(if args.is_empty() {
+        0.0
+      } else {
+        first.to_js_number()
+      })),
+      "String" if args.is_empty() => Ok(JsValue::String(String::new())),
+      "String" => Ok(JsValue::String(first.to_js_string())),
+      "RegExp" => {
+        let (source, flags) = match first {
+          JsValue::Regex { source, flags } => (source.clone(), flags.clone()),
+          JsValue::Undefined => ("(?:)".into(), String::new()),
+          pattern => (
+            globals::regexp_source(&pattern.to_js_string()),
+            String::new(),
+          ),
+        };
+        let flags = match args.get(1) {
+          None | Some(JsValue::Undefined) => flags,
+          Some(flags) => flags.to_js_string(),
+        };
+        // Invalid patterns and flags throw a SyntaxError.
+        compile_regex(&source, &flags, "").ok_or(new.span)?;
+        Ok(JsValue::Regex { source, flags })
+      }
+      _ => Err(new.span),
+    }
+  }
+
+  /// Returns the source of an object method as a function expression, e.g.
+  /// `foo(a) { ... }` becomes `function(a) { ... }`.
+  fn method_source(&self, method: &MethodProp) -> Option<String> {
+    let function = &method.function;
+    let span = Span::new(method.key.span_hi(), function.span.hi, Default::default());
+    let rest = self.source_map?.span_to_snippet(span).ok()?;
+    let keyword = match (function.is_async, function.is_generator) {
+      (false, false) => "function",
+      (false, true) => "function*",
+      (true, false) => "async function",
+      (true, true) => "async function*",
+    };
+    Some(format!("{}{}", keyword, rest))
+  }
+
+  /// Evaluates all arguments of a call, expanding spread arrays and strings.
+  fn eval_args(&self, call: &CallExpr) -> Result<Vec<JsValue>, Span> {
+    let mut args = Vec::with_capacity(call.args.len());
+    for arg in &call.args {
+      let val = self.eval(&arg.expr)?;
+      match arg.spread {
+        None => args.push(val),
+        Some(_) => args.extend(iterate(val).ok_or(call.span)?),
+      }
+    }
+    Ok(args)
+  }
+
+  /// Evaluates the first argument of a call, which must be a string.
+  fn eval_string_arg(&self, call: &CallExpr) -> Result<String, Span> {
+    match call.args.first() {
+      Some(ExprOrSpread { spread: None, expr }) => match self.eval(expr)? {
+        JsValue::String(s) => Ok(s),
+        _ => Err(call.span),
+      },
+      _ => Err(call.span),
+    }
+  }
+
+  /// Returns an error if a string is longer than `max_string_length`, so that large strings
+  /// are not built up across many expressions.
+  fn check_string_length(
+    &self,
+    result: Result<JsValue, Span>,
+    span: Span,
+  ) -> Result<JsValue, Span> {
+    match result {
+      Ok(JsValue::String(s)) if s.len() > self.max_string_length => {
+        Err(self.oversized_string(span))
+      }
+      result => result,
+    }
+  }
+
+  /// Records that the expression at the span produced a string that is too long.
+  fn oversized_string(&self, span: Span) -> Span {
+    if let Some(oversized_strings) = self.oversized_strings {
+      oversized_strings.borrow_mut().insert(span);
+    }
+    span
+  }
+
+  /// Returns the cache if results for the span can be cached. Nodes generated by previous
+  /// macro expansions have no location, or share the location of the macro call, so their
+  /// spans do not identify a single node.
+  fn cache_for(&self, span: Span) -> Option<&'a RefCell<HashMap<Span, Result<JsValue, Span>>>> {
+    if span.is_dummy() || matches!(self.shared_spans, Some(spans) if spans.borrow().contains(&span))
+    {
+      return None;
+    }
+    self.cache
+  }
+
+  /// Statically evaluate a JS expression to a value, if possible.
+  /// Results are cached by span, which avoids repeatedly evaluating shared sub-expressions,
+  /// e.g. the objects of nested member expressions passed to non-macro calls.
+  pub(crate) fn eval(&self, expr: &Expr) -> Result<JsValue, Span> {
+    let span = expr.span();
+    let cache = match self.cache_for(span) {
+      Some(cache) => cache,
+      None => return self.check_string_length(self.eval_uncached(expr), span),
+    };
+
+    if let Some(result) = self.cached(cache, span) {
+      return result;
+    }
+
+    let result = self.check_string_length(self.eval_uncached(expr), span);
+    cache.borrow_mut().insert(span, result.clone());
+    result
+  }
+
+  /// Returns a cached result for the span, counting the hit.
+  fn cached(
+    &self,
+    cache: &RefCell<HashMap<Span, Result<JsValue, Span>>>,
+    span: Span,
+  ) -> Option<Result<JsValue, Span>> {
+    let result = cache.borrow().get(&span)?.clone();
+    if let Some(cache_hits) = self.cache_hits {
+      cache_hits.set(cache_hits.get() + 1);
+    }
+    Some(result)
+  }
+
+  fn eval_uncached(&self, expr: &Expr) -> Result<JsValue, Span> {
+    match unwrap_expr(expr) {
+      Expr::Lit(lit) => match lit {
+        Lit::Null(_) => Ok(JsValue::Null),
+        Lit::Bool(v) => Ok(JsValue::Bool(v.value)),
+        Lit::Num(v) => Ok(JsValue::Number(v.value)),
+        Lit::Str(v) => Ok(JsValue::String(v.value.to_string())),
+        Lit::JSXText(v) => Ok(JsValue::String(v.value.to_string())),
+        Lit::Regex(v) => Ok(JsValue::Regex {
+          source: v.exp.to_string(),
+          flags: v.flags.to_string(),
+        }),
+        Lit::BigInt(v) => Ok(JsValue::BigInt((*v.value).clone())),
+      },
+      Expr::Tpl(tpl) => self.eval_tpl(tpl, false),
+      Expr::TaggedTpl(tagged) if self.global_member(&tagged.tag) == Some(("String", "raw")) => {
+        self.eval_tpl(&tagged.tpl, true)
+      }
+      // Holes in sparse arrays are evaluated as undefined. This is a simplification, but
+      // gives the same results for `length`, `JSON.stringify`, and spreads.
+      Expr::Array(arr) => {
+        let mut res = Vec::with_capacity(arr.elems.len());
+        for elem in &arr.elems {
+          if let Some(elem) = elem {
+            let val = self.eval(&*elem.expr)?;
+            if elem.spread.is_some() {
+              res.extend(iterate(val).ok_or(arr.span)?);
+            } else {
+              res.push(val);
+            }
+          } else {
+            res.push(JsValue::Undefined);
+          }
+        }
+        Ok(JsValue::Array(res))
+      }
+      Expr::Object(obj) => {
+        let mut res = IndexMap::with_capacity(obj.props.len());
+        for prop in &obj.props {
+          match prop {
+            PropOrSpread::Prop(prop) => match &**prop {
+              // Symbol keys cannot be represented by a JsValue, and macros only read string
+              // keys, so these properties are skipped rather than failing the whole object.
+              Prop::KeyValue(kv) if self.is_symbol_key(&kv.key) => {}
+              Prop::Method(method) if self.is_symbol_key(&method.key) => {}
+              Prop::KeyValue(kv) => {
+                let v = self.eval(&*kv.value)?;
+                let k = match &kv.key {
+                  PropName::Ident(Ident { sym, .. }) | PropName::Str(Str { value: sym, .. }) => {
+                    sym.to_string()
+                  }
+                  // Numeric keys are converted to strings the same way as at runtime,
+                  // e.g. `{1e21: x}` has the key "1e+21".
+                  PropName::Num(n) => js_number_to_string(n.value),
+                  PropName::Computed(c) => match self.eval(&*c.expr) {
+                    Err(e) => return Err(e),
+                    Ok(JsValue::String(s)) => s,
+                    Ok(JsValue::Number(n)) => js_number_to_string(n),
+                    Ok(JsValue::Bool(b)) => b.to_string(),
+                    Ok(JsValue::BigInt(n)) => n.to_string(),
+                    _ => return Err(c.span),
+                  },
+                  PropName::BigInt(v) => v.value.to_string(),
+                };
+
+                res.insert(k.to_string(), v);
+              }
+              Prop::Shorthand(s) => {
+                if let Some(val) = self.constants.get_constant(&s.to_id()) {
+                  res.insert(s.sym.to_string(), val?.clone());
+                } else {
+                  return Err(s.span);
+                }
+              }
+              Prop::Method(method) => {
+                let k = match &method.key {
+                  PropName::Ident(Ident { sym, .. }) | PropName::Str(Str { value: sym, .. }) => {
+                    sym.to_string()
+                  }
+                  key => return Err(key.span()),
+                };
+                let source = self.method_source(method).ok_or(method.function.span)?;
+                res.insert(k, JsValue::Function(source));
+              }
+              // Getters and setters would need to be called to read the property.
+              _ => return Err(obj.span),
+            },
+            PropOrSpread::Spread(spread) => {
+              let v = self.eval(&*spread.expr)?;
+              match v {
+                JsValue::Object(o) => res.extend(o),
+                _ => return Err(obj.span),
+              }
+            }
+          }
+        }
+        Ok(JsValue::Object(res))
+      }
+      // Logical operators short circuit, so the right side is only evaluated if it is used.
+      Expr::Bin(
+        bin @ BinExpr {
+          op: BinaryOp::LogicalAnd | BinaryOp::LogicalOr | BinaryOp::NullishCoalescing,
+          ..
+        },
+      ) => {
+        // Chains such as `a ?? b ?? c` are parsed as nested left operands. Flatten them so
+        // the operands are evaluated in order, stopping at the first one that is used.
+        let mut operands = vec![&*bin.right];
+        let mut left = &*bin.left;
+        while let Expr::Bin(inner) = unwrap_expr(left) {
+          if inner.op != bin.op {
+            break;
+          }
+          operands.push(&*inner.right);
+          left = &*inner.left;
+        }
+        operands.push(left);
+
+        let (last, rest) = operands.split_first().unwrap();
+        for operand in rest.iter().rev() {
+          let value = if bin.op == BinaryOp::NullishCoalescing {
+            self.eval_optional_operand(operand)?
+          } else {
+            self.eval(operand)?
+          };
+          let use_value = match bin.op {
+            BinaryOp::LogicalAnd => !value.is_truthy(),
+            BinaryOp::LogicalOr => value.is_truthy(),
+            _ => !matches!(value, JsValue::Null | JsValue::Undefined),
+          };
+          if use_value {
+            return Ok(value);
+          }
+        }
+        self.eval(last)
+      }
+      Expr::Bin(bin) => match (bin.op, self.eval(&*bin.left), self.eval(&*bin.right)) {
+        (BinaryOp::Add, Ok(JsValue::String(a)), Ok(JsValue::String(b))) => {
+          Ok(JsValue::String(format!("{}{}", a, b)))
+        }
+        (BinaryOp::Add, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
+          Ok(JsValue::Number(a + b))
+        }
+        (BinaryOp::Add, Ok(JsValue::String(a)), Ok(JsValue::Number(b))) => {
+          Ok(JsValue::String(format!("{}{}", a, js_number_to_string(b))))
+        }
+        (BinaryOp::Add, Ok(JsValue::Number(a)), Ok(JsValue::String(b))) => {
+          Ok(JsValue::String(format!("{}{}", js_number_to_string(a), b)))
+        }
+        (
+          op @ (BinaryOp::BitAnd
+          | BinaryOp::BitOr
+          | BinaryOp::BitXor
+          | BinaryOp::LShift
+          | BinaryOp::RShift
+          | BinaryOp::ZeroFillRShift),
+          Ok(a),
+          Ok(b),
+        ) => {
+          let (a, b) = match (primitive_to_number(&a), primitive_to_number(&b)) {
+            (Some(a), Some(b)) => (to_int32(a), to_uint32(b)),
+            _ => return Err(bin.span),
+          };
+          // Shift counts only use the lowest 5 bits.
+          let res = match op {
+            BinaryOp::BitAnd => (a & b as i32) as f64,
+            BinaryOp::BitOr => (a | b as i32) as f64,
+            BinaryOp::BitXor => (a ^ b as i32) as f64,
+            BinaryOp::LShift => a.wrapping_shl(b) as f64,
+            BinaryOp::RShift => a.wrapping_shr(b) as f64,
+            // The result of an unsigned shift is an unsigned 32 bit integer.
+            BinaryOp::ZeroFillRShift => (a as u32).wrapping_shr(b) as f64,
+            _ => unreachable!(),
+          };
+          Ok(JsValue::Number(res))
+        }
+        // Unlike +, the other arithmetic operators always convert their operands to numbers.
+        (
+          op @ (BinaryOp::Sub | BinaryOp::Div | BinaryOp::Mul | BinaryOp::Mod | BinaryOp::Exp),
+          Ok(a),
+          Ok(b),
+        ) => {
+          let (a, b) = match (primitive_to_number(&a), primitive_to_number(&b)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return Err(bin.span),
+          };
+          let res = match op {
+            BinaryOp::Sub => a - b,
+            BinaryOp::Div => a / b,
+            BinaryOp::Mul => a * b,
+            BinaryOp::Mod => a % b,
+            BinaryOp::Exp => globals::pow(a, b),
+            _ => unreachable!(),
+          };
+          Ok(JsValue::Number(res))
+        }
+        (BinaryOp::EqEq, Ok(JsValue::Bool(a)), Ok(JsValue::Bool(b))) => Ok(JsValue::Bool(a == b)),
+        (BinaryOp::EqEqEq, Ok(JsValue::Bool(a)), Ok(JsValue::Bool(b))) => Ok(JsValue::Bool(a == b)),
+        (BinaryOp::NotEq, Ok(JsValue::Bool(a)), Ok(JsValue::Bool(b))) => Ok(JsValue::Bool(a != b)),
+        (BinaryOp::NotEqEq, Ok(JsValue::Bool(a)), Ok(JsValue::Bool(b))) => {
+          Ok(JsValue::Bool(a != b))
+        }
+        (BinaryOp::EqEq, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
+          Ok(JsValue::Bool(a == b))
+        }
+        (BinaryOp::EqEqEq, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
+          Ok(JsValue::Bool(a == b))
+        }
+        (BinaryOp::NotEq, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
+          Ok(JsValue::Bool(a != b))
+        }
+        (BinaryOp::NotEqEq, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
+          Ok(JsValue::Bool(a != b))
+        }
+        (BinaryOp::EqEq, Ok(JsValue::String(a)), Ok(JsValue::String(b))) => {
+          Ok(JsValue::Bool(a == b))
+        }
+        (BinaryOp::EqEqEq, Ok(JsValue::String(a)), Ok(JsValue::String(b))) => {
+          Ok(JsValue::Bool(a == b))
+        }
+        (BinaryOp::NotEq, Ok(JsValue::String(a)), Ok(JsValue::String(b))) => {
+          Ok(JsValue::Bool(a != b))
+        }
+        (BinaryOp::NotEqEq, Ok(JsValue::String(a)), Ok(JsValue::String(b))) => {
+          Ok(JsValue::Bool(a != b))
+        }
+        // Objects are compared by reference, but evaluated values have no identity. A named
+        // constant is the same object wherever it is referenced, so it is equal to itself.
+        // Any other objects are assumed to be different, which is always the case for
+        // literals since each evaluation creates a new object.
+        (
+          op @ (BinaryOp::EqEq | BinaryOp::EqEqEq | BinaryOp::NotEq | BinaryOp::NotEqEq),
+          Ok(a),
+          Ok(b),
+        ) if is_object(&a) && is_object(&b) => {
+          let same = self.is_same_constant(&bin.left, &bin.right);
+          Ok(JsValue::Bool(
+            same == matches!(op, BinaryOp::EqEq | BinaryOp::EqEqEq),
+          ))
+        }
+        // BigInts are compared with numbers by their mathematical values, e.g. `1n == 1`,
+        // but are never strictly equal to them. Other operators that mix them throw.
+        (op, Ok(a), Ok(b))
+          if matches!(
+            (&a, &b),
+            (JsValue::BigInt(_), JsValue::BigInt(_) | JsValue::Number(_))
+              | (JsValue::Number(_), JsValue::BigInt(_))
+          ) =>
+        {
+          let ord = match (&a, &b) {
+            (JsValue::BigInt(a), JsValue::BigInt(b)) => Some(a.cmp(b)),
+            (JsValue::BigInt(a), JsValue::Number(b)) => compare_bigint_number(a, *b),
+            (JsValue::Number(a), JsValue::BigInt(b)) => {
+              compare_bigint_number(b, *a).map(Ordering::reverse)
+            }
+            _ => unreachable!(),
+          };
+          let same_type = std::mem::discriminant(&a) == std::mem::discriminant(&b);
+          let res = match op {
+            BinaryOp::EqEq => ord == Some(Ordering::Equal),
+            BinaryOp::NotEq => ord != Some(Ordering::Equal),
+            BinaryOp::EqEqEq => same_type && ord == Some(Ordering::Equal),
+            BinaryOp::NotEqEq => !same_type || ord != Some(Ordering::Equal),
+            BinaryOp::Gt => ord == Some(Ordering::Greater),
+            BinaryOp::GtEq => matches!(ord, Some(Ordering::Greater | Ordering::Equal)),
+            BinaryOp::Lt => ord == Some(Ordering::Less),
+            BinaryOp::LtEq => matches!(ord, Some(Ordering::Less | Ordering::Equal)),
+            _ => return Err(bin.span),
+          };
+          Ok(JsValue::Bool(res))
+        }
+        (BinaryOp::Gt, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => Ok(JsValue::Bool(a > b)),
+        (BinaryOp::GtEq, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
+          Ok(JsValue::Bool(a >= b))
+        }
+        (BinaryOp::Lt, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => Ok(JsValue::Bool(a < b)),
+        (BinaryOp::LtEq, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
+          Ok(JsValue::Bool(a <= b))
+        }
+        // Point at the operand that could not be evaluated, e.g. an unknown identifier.
+        (_, Err(span), _) | (_, _, Err(span)) => Err(span),
+        _ => Err(bin.span),
+      },
+      // The value of a sequence is its last expression. Evaluation has no side effects,
+      // but each expression is evaluated so that errors are reported.
+      Expr::Seq(seq) => {
+        let mut res = JsValue::Undefined;
+        for expr in &seq.exprs {
+          res = self.eval(expr)?;
+        }
+        Ok(res)
+      }
+      // Deleting a property of an object literal has no observable effect, since nothing
+      // else references it. Deleting from named constants is a mutation, so is not supported.
+      Expr::Unary(UnaryExpr {
+        op: UnaryOp::Delete,
+        arg,
+        span,
+      }) => match unwrap_expr(arg) {
+        Expr::Member(member)
+          if matches!(unwrap_expr(&member.obj), Expr::Object(_) | Expr::Array(_)) =>
+        {
+          self.eval(&member.obj)?;
+          if let MemberProp::Computed(prop) = &member.prop {
+            self.eval(&prop.expr)?;
+          }
+          Ok(JsValue::Bool(true))
+        }
+        _ => Err(*span),
+      },
+      // Globals configured for the target environment, e.g. `typeof window`.
+      Expr::Unary(UnaryExpr {
+        op: UnaryOp::TypeOf,
+        arg,
+        ..
+      }) if self.global_type(arg).is_some() => {
+        Ok(JsValue::String(self.global_type(arg).unwrap().clone()))
+      }
+      Expr::Unary(UnaryExpr {
+        op: UnaryOp::TypeOf,
+        arg,
+        span,
+      }) => {
+        // Like in JS, a missing property or undeclared global is "undefined" rather than
+        // an error, e.g. `typeof CONFIG.feature === "undefined"`.
+        let val = match unwrap_expr(arg) {
+          Expr::Ident(id) if self.is_undeclared(id) => JsValue::Undefined,
+          _ => self.eval_optional_operand(arg).map_err(|_| *span)?,
+        };
+        Ok(JsValue::String(type_of(&val).to_string()))
+      }
+      Expr::Unary(unary) => match (unary.op, self.eval(&*unary.arg)) {
+        (UnaryOp::Bang, Ok(v)) => Ok(JsValue::Bool(!v.is_truthy())),
+        (UnaryOp::Minus, Ok(JsValue::BigInt(v))) => Ok(JsValue::BigInt(-v)),
+        (UnaryOp::Minus | UnaryOp::Plus, Ok(v)) => match primitive_to_number(&v) {
+          Some(v) if unary.op == UnaryOp::Minus => Ok(JsValue::Number(-v)),
+          Some(v) => Ok(JsValue::Number(v)),
+          None => Err(unary.span),
+        },
+        (UnaryOp::Tilde, Ok(v)) => match primitive_to_number(&v) {
+          Some(v) => Ok(JsValue::Number(!to_int32(v) as f64)),
+          None => Err(unary.span),
+        },
+        (UnaryOp::Void, Ok(_)) => Ok(JsValue::Undefined),
+        _ => Err(unary.span),
+      },
+      Expr::Cond(cond) => {
+        if self.eval(&cond.test)?.is_truthy() {
+          self.eval(&cond.cons)
+        } else {
+          self.eval(&cond.alt)
+        }
+      }
+      Expr::Ident(id) if &id.sym == "undefined" => Ok(JsValue::Undefined),
+      // Global coercion functions can be used as callbacks, e.g. `arr.every(Boolean)`.
+      Expr::Ident(id) if COERCION_FUNCTIONS.contains(&&*id.sym) && self.is_global(id) => {
+        Ok(JsValue::Function(id.sym.to_string()))
+      }
+      Expr::Ident(id) => {
+        if let Some(val) = self.constants.get_constant(&id.to_id()) {
+          val.cloned()
+        } else {
+          Err(id.span)
+        }
+      }
+      Expr::Member(_) => self.eval_cow(expr).map(Cow::into_owned),
+      Expr::OptChain(opt) => Ok(self.eval_opt_chain(opt)?.unwrap_or(JsValue::Undefined)),
+      Expr::Call(call) => self.eval_call(call),
+      Expr::New(new) => self.eval_new(new),
+      Expr::Fn(FnExpr { function, .. }) => Err(function.span),
+      Expr::Class(ClassExpr { class, .. }) => Err(class.span),
+      Expr::JSXElement(el) => Err(el.span),
+      Expr::This(ThisExpr { span, .. })
+      | Expr::Update(UpdateExpr { span, .. })
+      | Expr::Assign(AssignExpr { span, .. })
+      | Expr::TaggedTpl(TaggedTpl { span, .. })
+      | Expr::Arrow(ArrowExpr { span, .. })
+      | Expr::Yield(YieldExpr { span, .. })
+      | Expr::Await(AwaitExpr { span, .. })
+      | Expr::JSXFragment(JSXFragment { span, .. })
+      | Expr::MetaProp(MetaPropExpr { span, .. })
+      | Expr::PrivateName(PrivateName { span, .. }) => Err(*span),
+      _ => Err(DUMMY_SP),
+    }
+  }
+
+  /// Evaluates an optional chain, returning None if it short-circuits because an object
+  /// accessed with `?.` is null or undefined. This ends the whole chain, so `a?.b.c` is
+  /// undefined rather than an error when `a` is. As with `??`, missing properties within
+  /// the chain evaluate to undefined, e.g. `OBJ?.missing?.x`. Optional calls such as
+  /// `fn?.()` are only supported for functions that return a value without arguments.
+  fn eval_opt_chain(&self, opt: &OptChainExpr) -> Result<Option<JsValue>, Span> {
+    let obj = match &*opt.base {
+      OptChainBase::Member(member) => &member.obj,
+      OptChainBase::Call(call) => {
+        // Function literals do not evaluate to values, so are called directly,
+        // e.g. `(() => 1)?.()`.
+        if let Some(expr) = returned_expr(unwrap_expr(&call.callee)) {
+          if !call.args.is_empty() {
+            return Err(opt.span);
+          }
+          return self.eval(expr).map(Some);
+        }
+        &call.callee
+      }
+    };
+
+    let obj = match unwrap_expr(obj) {
+      Expr::OptChain(inner) => match self.eval_opt_chain(inner)? {
+        Some(obj) => Cow::Owned(obj),
+        None => return Ok(None),
+      },
+      _ => self.eval_cow(obj)?,
+    };
+    if opt.optional && matches!(&*obj, JsValue::Undefined | JsValue::Null) {
+      return Ok(None);
+    }
+    match (&*opt.base, &*obj) {
+      (OptChainBase::Member(member), _) => self.eval_optional_member(obj, member).map(Some),
+      (OptChainBase::Call(call), JsValue::Function(source)) if call.args.is_empty() => {
+        self.call_function(source).map(Some).ok_or(opt.span)
+      }
+      _ => Err(opt.span),
+    }
+  }
+
+  /// Calls a function value without arguments, if it only returns a value, e.g. the
+  /// source of an object method such as `function() { return 42; }`.
+  fn call_function(&self, source: &str) -> Option<JsValue> {
+    // Parse into a new file, so that spans do not overlap with the module's in the cache.
+    let source_file = self
+      .source_map?
+      .new_source_file(FileName::MacroExpansion, source.into());
+    let lexer = Lexer::new(
+      Default::default(),
+      Default::default(),
+      StringInput::from(&*source_file),
+      None,
+    );
+    let expr = Parser::new_from(lexer).parse_expr().ok()?;
+    self.eval(returned_expr(&expr)?).ok()
+  }
+
+  /// Evaluates the left side of `??` or the argument of `typeof`. Unlike elsewhere, a missing
+  /// property of an object or array evaluates to undefined, so a default can be provided,
+  /// e.g. `MAP[key] ?? 0`. Missing properties are errors in other places, since they are most
+  /// likely typos.
+  fn eval_optional_operand(&self, expr: &Expr) -> Result<JsValue, Span> {
+    let member = match unwrap_expr(expr) {
+      Expr::Member(member)
+        if self.global_member(unwrap_expr(expr)).is_none() && !self.is_env_object(&member.obj) =>
+      {
+        member
+      }
+      _ => return self.eval(expr),
+    };
+
+    let obj = self.eval_cow(&member.obj)?;
+    self.eval_optional_member(obj, member)
+  }
+
+  /// Gets a property of an object, or undefined if an object or array does not have it.
+  fn eval_optional_member<'v>(
+    &self,
+    obj: Cow<'v, JsValue>,
+    member: &MemberExpr,
+  ) -> Result<JsValue, Span> {
+    let prop = match &member.prop {
+      MemberProp::Ident(id) => JsValue::String(id.sym.to_string()),
+      MemberProp::Computed(prop) => self.eval(&prop.expr)?,
+      _ => return Err(member.span),
+    };
+    let value = match (&*obj, &prop) {
+      (JsValue::Object(_), _) => obj.get_id(&prop.to_js_string()),
+      (JsValue::Array(_), JsValue::Number(_)) => obj.get(&prop),
+      _ => return self.eval_member_cow(obj, member).map(Cow::into_owned),
+    };
+    Ok(value.map_or(JsValue::Undefined, Cow::into_owned))
+  }
+
+  /// Evaluates an expression, borrowing from constants rather than cloning them where possible.
+  /// This avoids copying large constant objects when only a single property is accessed.
+  pub(crate) fn eval_cow(&self, expr: &Expr) -> Result<Cow<'a, JsValue>, Span> {
+    match unwrap_expr(expr) {
+      Expr::Ident(id) => match self.constants.get_constant(&id.to_id()) {
+        Some(Ok(val)) => Ok(Cow::Borrowed(val)),
+        _ => self.eval(expr).map(Cow::Owned),
+      },
+      Expr::Member(member) => {
+        if let Some(("Math", name)) = self.global_member(unwrap_expr(expr)) {
+          return globals::math_constant(name)
+            .map(|n| Cow::Owned(JsValue::Number(n)))
+            .ok_or(member.span);
+        }
+        if let Some(val) = self.eval_env(member) {
+          return val;
+        }
+
+        let cache = self.cache_for(member.span);
+        if let Some(result) = cache.and_then(|cache| self.cached(cache, member.span)) {
+          return result.map(Cow::Owned);
+        }
+
+        let result = self
+          .eval_cow(&member.obj)
+          .and_then(|obj| self.eval_member_cow(obj, member));
+        // Properties borrowed from constants are cheaper to look up again than to clone,
+        // so only owned results, e.g. properties of evaluated objects, are cached.
+        if let Some(cache) = cache {
+          match &result {
+            Ok(Cow::Borrowed(_)) => {}
+            Ok(Cow::Owned(val)) => {
+              cache.borrow_mut().insert(member.span, Ok(val.clone()));
+            }
+            Err(span) => {
+              cache.borrow_mut().insert(member.span, Err(*span));
+            }
+          }
+        }
+        result
+      }
+      _ => self.eval(expr).map(Cow::Owned),
+    }
+  }
+
+  pub(crate) fn eval_member_cow<'v>(
+    &self,
+    obj: Cow<'v, JsValue>,
+    member: &MemberExpr,
+  ) -> Result<Cow<'v, JsValue>, Span> {
+    match obj {
+      Cow::Borrowed(obj) => self.eval_member_prop(obj, member),
+      Cow::Owned(obj) => self
+        .eval_member_prop(&obj, member)
+        .map(|val| Cow::Owned(val.into_owned())),
+    }
+  }
+
+  fn eval_member_prop<'v>(
+    &self,
+    obj: &'v JsValue,
+    member: &MemberExpr,
+  ) -> Result<Cow<'v, JsValue>, Span> {
+    match &member.prop {
+      MemberProp::Ident(id) => obj.get_id(id.as_ref()).ok_or(member.span),
+      MemberProp::Computed(prop) => {
+        let k = self.eval(&prop.expr)?;
+        obj.get(&k).ok_or(prop.span)
+      }
+      _ => Err(member.span),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use swc_core::common::{FileName, SourceMap};
+  use swc_core::ecma::atoms::JsWord;
+  use swc_core::ecma::parser::{lexer::Lexer, Parser, StringInput};
+
+  fn parse_expr(source_map: &SourceMap, code: &str) -> Box<Expr> {
+    let source_file = source_map.new_source_file(FileName::Anon, code.into());
+    let lexer = Lexer::new(
+      Default::default(),
+      Default::default(),
+      StringInput::from(&*source_file),
+      None,
+    );
+    Parser::new_from(lexer)
+      .parse_expr()
+      .expect("failed to parse")
+  }
+
+  fn eval(code: &str, constants: &HashMap<Id, JsValue>) -> Result<JsValue, MacroError> {
+    let source_map = SourceMap::default();
+    eval_expr(&parse_expr(&source_map, code), constants)
+  }
+
+  #[test]
+  fn eval_expr_literals() {
+    let constants = HashMap::new();
+    assert_eq!(
+      eval("`a${1 + 2}` + 'b'", &constants),
+      Ok(JsValue::String("a3b".into()))
+    );
+    assert_eq!(
+      eval("[1, ...[2], { x: true ? 'y' : 'z' }.x]", &constants),
+      Ok(JsValue::Array(vec![
+        JsValue::Number(1.0),
+        JsValue::Number(2.0),
+        JsValue::String("y".into()),
+      ]))
+    );
+  }
+
+  #[test]
+  fn eval_expr_constants() {
+    let mut constants = HashMap::new();
+    constants.insert(
+      (JsWord::from("config"), Default::default()),
+      JsValue::Object(IndexMap::from([(
+        "sizes".to_string(),
+        JsValue::Array(vec![JsValue::Number(10.0), JsValue::Number(20.0)]),
+      )])),
+    );
+    assert_eq!(
+      eval("config.sizes[1] * 2", &constants),
+      Ok(JsValue::Number(40.0))
+    );
+  }
+
+  #[test]
+  fn eval_expr_uri() {
+    let constants = HashMap::new();
+    assert_eq!(
+      eval("encodeURIComponent('a b&c/d')", &constants),
+      Ok(JsValue::String("a%20b%26c%2Fd".into()))
+    );
+    assert_eq!(
+      eval("encodeURI('/a b?c=d#e')", &constants),
+      Ok(JsValue::String("/a%20b?c=d#e".into()))
+    );
+    assert_eq!(
+      eval(
+        "decodeURIComponent(encodeURIComponent('a b&c/d'))",
+        &constants
+      ),
+      Ok(JsValue::String("a b&c/d".into()))
+    );
+    assert_eq!(
+      eval("decodeURI('%2F%20')", &constants),
+      Ok(JsValue::String("%2F ".into()))
+    );
+  }
+
+  #[test]
+  fn eval_expr_uri_malformed() {
+    let constants = HashMap::new();
+    match eval("decodeURIComponent('%E0%A4%A')", &constants) {
+      Err(MacroError::EvaluationError(span, hint)) => {
+        assert_eq!(span.lo.0, 1);
+        assert_eq!(
+          hint.as_deref(),
+          Some("`decodeURIComponent` requires a string containing valid percent-encoded UTF-8.")
+        );
+      }
+      res => panic!("expected an evaluation error, got {:?}", res),
+    }
+  }
+
+  #[test]
+  fn eval_expr_structured_clone() {
+    let mut constants = HashMap::new();
+    let obj = JsValue::Object(IndexMap::from([(
+      "a".to_string(),
+      JsValue::Array(vec![
+        JsValue::Object(IndexMap::from([("b".to_string(), JsValue::Null)])),
+        JsValue::Regex {
+          source: "c".into(),
+          flags: "g".into(),
+        },
+      ]),
+    )]));
+    constants.insert((JsWord::from("OBJ"), Default::default()), obj.clone());
+    assert_eq!(eval("structuredClone(OBJ)", &constants), Ok(obj));
+    assert_eq!(
+      eval("structuredClone(OBJ).a[0].b", &constants),
+      Ok(JsValue::Null)
+    );
+    constants.insert(
+      (JsWord::from("FNS"), Default::default()),
+      JsValue::Array(vec![JsValue::Function("function f() {}".into())]),
+    );
+    match eval("structuredClone({ a: FNS })", &constants) {
+      Err(MacroError::EvaluationError(_, hint)) => assert_eq!(
+        hint.as_deref(),
+        Some("`structuredClone` requires a value that does not contain functions.")
+      ),
+      res => panic!("expected an evaluation error, got {:?}", res),
+    }
+  }
+
+  #[test]
+  fn eval_expr_base64() {
+    let constants = HashMap::new();
+    assert_eq!(
+      eval("atob(btoa('<svg>ÿ</svg>'))", &constants),
+      Ok(JsValue::String("<svg>ÿ</svg>".into()))
+    );
+    assert_eq!(
+      eval("btoa('hi')", &constants),
+      Ok(JsValue::String("aGk=".into()))
+    );
+  }
+
+  #[test]
+  fn eval_expr_btoa_out_of_range() {
+    let constants = HashMap::new();
+    match eval("btoa('✓')", &constants) {
+      Err(MacroError::EvaluationError(span, hint)) => {
+        assert_eq!(span.lo.0, 1);
+        assert_eq!(
+          hint.as_deref(),
+          Some("`btoa` requires a string containing only Latin-1 characters.")
+        );
+      }
+      res => panic!("expected an evaluation error, got {:?}", res),
+    }
+  }
+
+  #[test]
+  fn eval_expr_string_raw() {
+    let constants = HashMap::new();
+    assert_eq!(
+      eval(r"String.raw`a\nb${1}`", &constants),
+      Ok(JsValue::String(r"a\nb1".into()))
+    );
+    assert_eq!(
+      eval(r"`a\nb${1}`", &constants),
+      Ok(JsValue::String("a\nb1".into()))
+    );
+    assert_eq!(
+      eval(
+        r#"String.raw({ raw: ["x", "y", "z"] }, 1, "-", 3)"#,
+        &constants
+      ),
+      Ok(JsValue::String("x1y-z".into()))
+    );
+    assert!(eval(r"foo`a\nb`", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_array_of_from() {
+    let constants = HashMap::new();
+    let numbers = JsValue::Array(vec![
+      JsValue::Number(1.0),
+      JsValue::Number(2.0),
+      JsValue::Number(3.0),
+    ]);
+    assert_eq!(eval("Array.of(1, 2, 3)", &constants), Ok(numbers.clone()));
+    assert_eq!(
+      eval("Array.of(1, ...[2, 3])", &constants),
+      Ok(numbers.clone())
+    );
+    assert_eq!(
+      eval("Array.from('a😀')", &constants),
+      Ok(JsValue::Array(vec![
+        JsValue::String("a".into()),
+        JsValue::String("😀".into()),
+      ]))
+    );
+    assert_eq!(eval("Array.from([1, 2, 3])", &constants), Ok(numbers));
+    assert!(eval("Array.from(...[])", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_array_from_map() {
+    let constants = HashMap::new();
+    match eval("Array.from('ab', c => c + c)", &constants) {
+      Err(MacroError::EvaluationError(span, hint)) => {
+        assert_eq!(span.lo.0, 1);
+        assert_eq!(
+          hint.as_deref(),
+          Some("`Array.from` requires a string or array, and does not support a map function.")
+        );
+      }
+      res => panic!("expected an evaluation error, got {:?}", res),
+    }
+  }
+
+  #[test]
+  fn eval_expr_array_fill() {
+    let constants = HashMap::new();
+    let numbers = |values: &[f64]| {
+      Ok(JsValue::Array(
+        values.iter().map(|n| JsValue::Number(*n)).collect(),
+      ))
+    };
+    assert_eq!(
+      eval("[1, 2, 3].fill(0)", &constants),
+      numbers(&[0.0, 0.0, 0.0])
+    );
+    assert_eq!(
+      eval("[1, 2, 3, 4].fill(0, 1, 3)", &constants),
+      numbers(&[1.0, 0.0, 0.0, 4.0])
+    );
+    assert_eq!(
+      eval("[1, 2, 3, 4].fill(0, -3, -1)", &constants),
+      numbers(&[1.0, 0.0, 0.0, 4.0])
+    );
+    assert_eq!(
+      eval("[1, 2, 3].fill(0, -10, 10)", &constants),
+      numbers(&[0.0, 0.0, 0.0])
+    );
+    assert_eq!(
+      eval("[1, 2, 3].fill(0, 2, 1)", &constants),
+      numbers(&[1.0, 2.0, 3.0])
+    );
+    assert!(eval("[1, 2, 3].fill(0, 'a')", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_array_every_some() {
+    let mut constants = HashMap::new();
+    constants.insert(
+      (JsWord::from("isTruthy"), Default::default()),
+      JsValue::Function("Boolean".into()),
+    );
+    assert_eq!(
+      eval("[1, 'a', {}].every(Boolean)", &constants),
+      Ok(JsValue::Bool(true))
+    );
+    assert_eq!(
+      eval("[1, '', {}].every(Boolean)", &constants),
+      Ok(JsValue::Bool(false))
+    );
+    assert_eq!(
+      eval("[0, null, 'a'].some(Boolean)", &constants),
+      Ok(JsValue::Bool(true))
+    );
+    assert_eq!(
+      eval("[0, null, ''].some(isTruthy)", &constants),
+      Ok(JsValue::Bool(false))
+    );
+    assert_eq!(
+      eval("[].every(Boolean)", &constants),
+      Ok(JsValue::Bool(true))
+    );
+    assert!(eval("[1, 2].every(x => x > 0)", &constants).is_err());
+    assert!(eval("[1, 2].some()", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_array_map_coercion() {
+    let constants = HashMap::new();
+    assert_eq!(
+      eval("[1, 'a', null, [2, 3]].map(String)", &constants),
+      Ok(JsValue::Array(vec![
+        JsValue::String("1".into()),
+        JsValue::String("a".into()),
+        JsValue::String("null".into()),
+        JsValue::String("2,3".into()),
+      ]))
+    );
+    assert_eq!(
+      eval("['1', ' 2 ', '', true, null].map(Number)", &constants),
+      Ok(JsValue::Array(vec![
+        JsValue::Number(1.0),
+        JsValue::Number(2.0),
+        JsValue::Number(0.0),
+        JsValue::Number(1.0),
+        JsValue::Number(0.0),
+      ]))
+    );
+    assert_eq!(
+      eval("[0, 'a'].map(Boolean)", &constants),
+      Ok(JsValue::Array(vec![
+        JsValue::Bool(false),
+        JsValue::Bool(true)
+      ]))
+    );
+    assert!(eval("[1].map(x => x)", &constants).is_err());
+    assert!(eval("[1].map(parseInt)", &constants).is_err());
+    assert!(eval("[1].every(String)", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_array_holes() {
+    let constants = HashMap::new();
+    assert_eq!(eval("[,].length", &constants), Ok(JsValue::Number(1.0)));
+    assert_eq!(
+      eval("[1, , 2].length", &constants),
+      Ok(JsValue::Number(3.0))
+    );
+    assert_eq!(
+      eval("JSON.stringify([, 1])", &constants),
+      Ok(JsValue::String("[null,1]".into()))
+    );
+    assert_eq!(
+      eval("[...[, 1]]", &constants),
+      Ok(JsValue::Array(vec![
+        JsValue::Undefined,
+        JsValue::Number(1.0)
+      ]))
+    );
+  }
+
+  #[test]
+  fn eval_expr_json_stringify() {
+    let constants = HashMap::new();
+    assert_eq!(
+      eval(
+        "JSON.stringify({ a: [1.5, 'x\"y', null, 0 / 0], b: undefined, c: { d: true } })",
+        &constants
+      ),
+      Ok(JsValue::String(
+        r#"{"a":[1.5,"x\"y",null,null],"c":{"d":true}}"#.into()
+      ))
+    );
+    assert_eq!(
+      eval("JSON.stringify(undefined)", &constants),
+      Ok(JsValue::Undefined)
+    );
+    assert!(eval("JSON.stringify({}, null, 2)", &constants).is_err());
+    assert!(eval("JSON.stringify({ a: [1n] })", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_bigint() {
+    let constants = HashMap::new();
+    let bool = |b| Ok(JsValue::Bool(b));
+    assert_eq!(eval("2n > 1", &constants), bool(true));
+    assert_eq!(eval("1n == 1", &constants), bool(true));
+    assert_eq!(eval("1n === 1", &constants), bool(false));
+    assert_eq!(eval("1n !== 1", &constants), bool(true));
+    assert_eq!(eval("1n === 1n", &constants), bool(true));
+    assert_eq!(eval("0n == -0", &constants), bool(true));
+    assert_eq!(eval("1n < 1.5", &constants), bool(true));
+    assert_eq!(eval("-2n < -1.5", &constants), bool(true));
+    assert_eq!(
+      eval("18446744073709551617n > 2 ** 64", &constants),
+      bool(true)
+    );
+    assert_eq!(eval("1n < 0 / 0", &constants), bool(false));
+    assert_eq!(eval("1n != 0 / 0", &constants), bool(true));
+    assert_eq!(eval("-1n < -1 / 0", &constants), bool(false));
+    assert_eq!(eval("-5n", &constants), Ok(JsValue::BigInt((-5).into())));
+    assert_eq!(
+      eval("typeof 1n", &constants),
+      Ok(JsValue::String("bigint".into()))
+    );
+    assert_eq!(eval("!0n", &constants), bool(true));
+    assert!(eval("1n + 1", &constants).is_err());
+    assert!(eval("2n ** 64n", &constants).is_err());
+    assert!(eval("+1n", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_array_filter() {
+    let constants = HashMap::new();
+    assert_eq!(
+      eval(
+        "[0, 'a', '', null, 1, undefined, [], false].filter(Boolean)",
+        &constants
+      ),
+      Ok(JsValue::Array(vec![
+        JsValue::String("a".into()),
+        JsValue::Number(1.0),
+        JsValue::Array(vec![]),
+      ]))
+    );
+    assert!(eval("[1, 2].filter(x => x > 1)", &constants).is_err());
+    assert!(eval("[1, 2].filter(String)", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_method_chain() {
+    let constants = HashMap::new();
+    let string = |s: &str| Ok(JsValue::String(s.into()));
+    assert_eq!(
+      eval("(5).toString().padStart(3, '0')", &constants),
+      string("005")
+    );
+    assert_eq!(eval("'ab'.padEnd(7, 'xyz')", &constants), string("abxyzxy"));
+    assert_eq!(eval("'ab'.padStart(4)", &constants), string("  ab"));
+    assert_eq!(eval("'abc'.padStart(2, '0')", &constants), string("abc"));
+    assert_eq!(eval("'abc'.padStart(5, '')", &constants), string("abc"));
+    assert_eq!(eval("(255).toString(16)", &constants), string("ff"));
+    assert_eq!(eval("(-5).toString(2)", &constants), string("-101"));
+    assert_eq!(eval("(1.5).toString()", &constants), string("1.5"));
+    assert_eq!(eval("[1, 'a'].toString()", &constants), string("1,a"));
+    assert_eq!(
+      eval(
+        "(7).toString(2).padStart(8, '0').split('').map(Number)[5]",
+        &constants
+      ),
+      Ok(JsValue::Number(1.0))
+    );
+    assert!(eval("(1.5).toString(2)", &constants).is_err());
+    assert!(eval("(5).toString(1)", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_to_locale_string() {
+    let constants = HashMap::new();
+    let string = |s: &str| Ok(JsValue::String(s.into()));
+    assert_eq!(
+      eval("(1234.5).toLocaleString('en-US')", &constants),
+      string("1,234.5")
+    );
+    assert_eq!(
+      eval("(1234567.891).toLocaleString('de-DE')", &constants),
+      string("1.234.567,891")
+    );
+    assert_eq!(
+      eval("(999.9996).toLocaleString('en')", &constants),
+      string("1,000")
+    );
+    assert_eq!(
+      eval("(1.0005).toLocaleString('en')", &constants),
+      string("1.001")
+    );
+    assert_eq!(
+      eval("(0.00012).toLocaleString('en')", &constants),
+      string("0")
+    );
+    assert_eq!(
+      eval("(-0.5).toLocaleString('en')", &constants),
+      string("-0.5")
+    );
+    assert_eq!(
+      eval("(123).toLocaleString('en')", &constants),
+      string("123")
+    );
+    assert_eq!(
+      eval("(1e21).toLocaleString('en')", &constants),
+      string("1,000,000,000,000,000,000,000")
+    );
+    assert_eq!(
+      eval("(-1 / 0).toLocaleString('en')", &constants),
+      string("-∞")
+    );
+    assert!(eval("(1234.5).toLocaleString('xx-YY')", &constants).is_err());
+    assert!(eval("(1234.5).toLocaleString()", &constants).is_err());
+    assert!(eval("(1).toLocaleString('en', { style: 'percent' })", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_string_split() {
+    let constants = HashMap::new();
+    let strings = |values: &[&str]| {
+      Ok(JsValue::Array(
+        values
+          .iter()
+          .map(|s| JsValue::String(s.to_string()))
+          .collect(),
+      ))
+    };
+    assert_eq!(
+      eval("'a,b,,c'.split(',')", &constants),
+      strings(&["a", "b", "", "c"])
+    );
+    assert_eq!(
+      eval("'a,b,c'.split(',', 2)", &constants),
+      strings(&["a", "b"])
+    );
+    assert_eq!(
+      eval("'abc'.split('')", &constants),
+      strings(&["a", "b", "c"])
+    );
+    assert_eq!(eval("'abc'.split()", &constants), strings(&["abc"]));
+    assert_eq!(eval("'a1b'.split(1)", &constants), strings(&["a", "b"]));
+    assert_eq!(eval("''.split(',')", &constants), strings(&[""]));
+    assert_eq!(eval("''.split('')", &constants), strings(&[]));
+    assert_eq!(
+      eval(r"'a1b2c'.split(/\d/)", &constants),
+      strings(&["a", "b", "c"])
+    );
+    assert_eq!(
+      eval(r"'a1b2c'.split(/(\d)/)", &constants),
+      strings(&["a", "1", "b", "2", "c"])
+    );
+    assert_eq!(
+      eval(r"'a1b2c'.split(/(\d)/, 2)", &constants),
+      strings(&["a", "1"])
+    );
+    assert_eq!(
+      eval("'a-b'.split(/(-)|(x)/)", &constants),
+      Ok(JsValue::Array(vec![
+        JsValue::String("a".into()),
+        JsValue::String("-".into()),
+        JsValue::Undefined,
+        JsValue::String("b".into()),
+      ]))
+    );
+    assert_eq!(eval("'ab'.split(/a*?/)", &constants), strings(&["a", "b"]));
+    assert_eq!(eval("'ab'.split(/a*/)", &constants), strings(&["", "b"]));
+    assert_eq!(eval("''.split(/x/)", &constants), strings(&[""]));
+    assert_eq!(eval("''.split(/(?:)/)", &constants), strings(&[]));
+    assert!(eval("'a'.split(new RegExp('['))", &constants).is_err());
+    assert!(eval("'a'.split({})", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_array_find() {
+    let constants = HashMap::new();
+    assert_eq!(
+      eval("[0, '', 'a', 'b'].find(Boolean)", &constants),
+      Ok(JsValue::String("a".into()))
+    );
+    assert_eq!(
+      eval("[0, '', 'a', 'b'].findIndex(Boolean)", &constants),
+      Ok(JsValue::Number(2.0))
+    );
+    assert_eq!(
+      eval("[0, null].find(Boolean)", &constants),
+      Ok(JsValue::Undefined)
+    );
+    assert_eq!(
+      eval("[0, null].findIndex(Boolean)", &constants),
+      Ok(JsValue::Number(-1.0))
+    );
+    assert!(eval("[1, 2].find(x => x > 1)", &constants).is_err());
+    assert!(eval("[1, 2].findIndex(x => x > 1)", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_object_entries() {
+    let mut constants = HashMap::new();
+    constants.insert(
+      (JsWord::from("OBJ"), Default::default()),
+      JsValue::Object(IndexMap::from([
+        ("a".to_string(), JsValue::Number(1.0)),
+        ("b".to_string(), JsValue::Null),
+      ])),
+    );
+    let string = |s: &str| Ok(JsValue::String(s.into()));
+    assert_eq!(
+      eval("Object.keys(OBJ).join(',')", &constants),
+      string("a,b")
+    );
+    assert_eq!(eval("Object.keys(OBJ).join()", &constants), string("a,b"));
+    assert_eq!(
+      eval("Object.values(OBJ).join(' ')", &constants),
+      string("1 ")
+    );
+    assert_eq!(
+      eval("Object.entries(OBJ).map(String).join(';')", &constants),
+      string("a,1;b,")
+    );
+    assert_eq!(
+      eval("Object.entries(['x'])", &constants),
+      Ok(JsValue::Array(vec![JsValue::Array(vec![
+        JsValue::String("0".into()),
+        JsValue::String("x".into()),
+      ])]))
+    );
+    assert_eq!(eval("Object.keys('ab').join('')", &constants), string("01"));
+    assert!(eval("Object.keys(null)", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_iterators() {
+    let constants = HashMap::new();
+    let number = |n: f64| JsValue::Number(n);
+    let string = |s: &str| JsValue::String(s.into());
+    assert_eq!(
+      eval("[...['a', 'b'].entries()]", &constants),
+      Ok(JsValue::Array(vec![
+        JsValue::Array(vec![number(0.0), string("a")]),
+        JsValue::Array(vec![number(1.0), string("b")]),
+      ]))
+    );
+    assert_eq!(
+      eval("[...['a', 'b'].keys()]", &constants),
+      Ok(JsValue::Array(vec![number(0.0), number(1.0)]))
+    );
+    assert_eq!(
+      eval("[...['a', 'b'].values(), 'c']", &constants),
+      Ok(JsValue::Array(vec![string("a"), string("b"), string("c")]))
+    );
+    assert_eq!(
+      eval("[...new Map([['a', 1]]).keys()]", &constants),
+      Ok(JsValue::Array(vec![string("a")]))
+    );
+    assert_eq!(
+      eval("[...new Set([1]).entries()]", &constants),
+      Ok(JsValue::Array(vec![JsValue::Array(vec![
+        number(1.0),
+        number(1.0)
+      ])]))
+    );
+  }
+
+  #[test]
+  fn eval_expr_symbol_keys() {
+    let constants = HashMap::new();
+    assert_eq!(
+      eval(
+        "({ a: 1, [Symbol.iterator]: function() {}, *[Symbol.iterator]() {} }).a",
+        &constants
+      ),
+      Ok(JsValue::Number(1.0))
+    );
+    assert_eq!(
+      eval("Object.keys({ a: 1, [Symbol('b')]: 2 })", &constants),
+      Ok(JsValue::Array(vec![JsValue::String("a".into())]))
+    );
+  }
+
+  #[test]
+  fn eval_expr_own_properties() {
+    let constants = HashMap::new();
+    let strings = |values: &[&str]| {
+      Ok(JsValue::Array(
+        values
+          .iter()
+          .map(|s| JsValue::String(s.to_string()))
+          .collect(),
+      ))
+    };
+    assert_eq!(
+      eval("Object.getOwnPropertyNames({ a: 1, b: 'b' })", &constants),
+      strings(&["a", "b"])
+    );
+    assert_eq!(
+      eval("Object.getOwnPropertyNames(['x', 'y'])", &constants),
+      strings(&["0", "1", "length"])
+    );
+    assert!(eval("Object.getOwnPropertyNames(null)", &constants).is_err());
+    assert_eq!(
+      eval("({ a: 1, 2: 'b' }).hasOwnProperty('a')", &constants),
+      Ok(JsValue::Bool(true))
+    );
+    assert_eq!(
+      eval("({ a: 1, 2: 'b' }).hasOwnProperty(2)", &constants),
+      Ok(JsValue::Bool(true))
+    );
+    assert_eq!(
+      eval("({ a: 1 }).hasOwnProperty('toString')", &constants),
+      Ok(JsValue::Bool(false))
+    );
+    assert_eq!(
+      eval("['x'].hasOwnProperty('length')", &constants),
+      Ok(JsValue::Bool(true))
+    );
+  }
+
+  #[test]
+  fn eval_expr_string_index() {
+    let constants = HashMap::new();
+    assert_eq!(
+      eval("'abc'[1]", &constants),
+      Ok(JsValue::String("b".into()))
+    );
+    assert_eq!(eval("'abc'[9]", &constants), Ok(JsValue::Undefined));
+    assert_eq!(eval("'abc'[1.5]", &constants), Ok(JsValue::Undefined));
+    assert_eq!(
+      eval("'héllo'[2]", &constants),
+      Ok(JsValue::String("l".into()))
+    );
+    assert_eq!(
+      eval("'a😀b'[3]", &constants),
+      Ok(JsValue::String("b".into()))
+    );
+    assert_eq!(
+      eval("'a😀b'[1]", &constants),
+      Ok(JsValue::String("\u{fffd}".into()))
+    );
+  }
+
+  #[test]
+  fn eval_expr_string_length() {
+    let constants = HashMap::new();
+    assert_eq!(eval("'abc'.length", &constants), Ok(JsValue::Number(3.0)));
+    assert_eq!(
+      eval("'abc'['length']", &constants),
+      Ok(JsValue::Number(3.0))
+    );
+    assert_eq!(eval("'é😀'.length", &constants), Ok(JsValue::Number(3.0)));
+    assert_eq!(
+      eval("'abc'['len' + 'gth']", &constants),
+      Ok(JsValue::Number(3.0))
+    );
+  }
+
+  #[test]
+  fn eval_expr_spread_string() {
+    let constants = HashMap::new();
+    let strings = |values: &[&str]| {
+      Ok(JsValue::Array(
+        values
+          .iter()
+          .map(|s| JsValue::String(s.to_string()))
+          .collect(),
+      ))
+    };
+    assert_eq!(eval("[...'abc']", &constants), strings(&["a", "b", "c"]));
+    assert_eq!(
+      eval("[...'a😀b', ...['c']]", &constants),
+      strings(&["a", "😀", "b", "c"])
+    );
+    assert_eq!(eval("Array.of(...'ab')", &constants), strings(&["a", "b"]));
+    assert!(eval("[...1]", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_iife() {
+    let constants = HashMap::new();
+    assert_eq!(eval("(() => 42)()", &constants), Ok(JsValue::Number(42.0)));
+    assert_eq!(
+      eval("(() => { return [1, 2]; })()", &constants),
+      Ok(JsValue::Array(vec![
+        JsValue::Number(1.0),
+        JsValue::Number(2.0)
+      ]))
+    );
+    assert_eq!(
+      eval("(function() { return 'a' + 'b'; })()", &constants),
+      Ok(JsValue::String("ab".into()))
+    );
+    assert!(eval("(function() { const a = 1; return a; })()", &constants).is_err());
+    assert!(eval("((a) => a)(1)", &constants).is_err());
+    assert!(eval("(() => 1)(2)", &constants).is_err());
+    assert!(eval("(async () => 1)()", &constants).is_err());
+    assert!(eval("(function() { return this; })()", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_nullish_missing_property() {
+    let mut constants = HashMap::new();
+    constants.insert(
+      (JsWord::from("MAP"), Default::default()),
+      JsValue::Object(IndexMap::from([
+        ("a".to_string(), JsValue::Number(1.0)),
+        ("b".to_string(), JsValue::Null),
+      ])),
+    );
+    assert_eq!(eval("MAP['a'] ?? 0", &constants), Ok(JsValue::Number(1.0)));
+    assert_eq!(eval("MAP.b ?? 0", &constants), Ok(JsValue::Number(0.0)));
+    assert_eq!(
+      eval("MAP['c'] ?? 'd'", &constants),
+      Ok(JsValue::String("d".into()))
+    );
+    assert_eq!(
+      eval("({ a: 1, b: 2 })['b']", &constants),
+      Ok(JsValue::Number(2.0))
+    );
+    assert_eq!(eval("[1][5] ?? 2", &constants), Ok(JsValue::Number(2.0)));
+    assert!(eval("MAP.c", &constants).is_err());
+    assert!(eval("MAP.c || 0", &constants).is_err());
+    assert!(eval("MISSING.c ?? 0", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_object_equality() {
+    let mut constants = HashMap::new();
+    constants.insert(
+      (JsWord::from("OBJ"), Default::default()),
+      JsValue::Object(IndexMap::from([("a".to_string(), JsValue::Number(1.0))])),
+    );
+    constants.insert(
+      (JsWord::from("OTHER"), Default::default()),
+      JsValue::Object(IndexMap::from([("a".to_string(), JsValue::Number(1.0))])),
+    );
+    assert_eq!(eval("[] === []", &constants), Ok(JsValue::Bool(false)));
+    assert_eq!(eval("({}) == {}", &constants), Ok(JsValue::Bool(false)));
+    assert_eq!(eval("[1] !== [1]", &constants), Ok(JsValue::Bool(true)));
+    assert_eq!(eval("OBJ === OBJ", &constants), Ok(JsValue::Bool(true)));
+    assert_eq!(eval("OBJ !== OBJ", &constants), Ok(JsValue::Bool(false)));
+    assert_eq!(eval("OBJ === OTHER", &constants), Ok(JsValue::Bool(false)));
+    assert_eq!(
+      eval("OBJ === { a: 1 }", &constants),
+      Ok(JsValue::Bool(false))
+    );
+  }
+
+  #[test]
+  fn eval_expr_optional_chain() {
+    let mut constants = HashMap::new();
+    constants.insert(
+      (JsWord::from("OBJ"), Default::default()),
+      JsValue::Object(IndexMap::from([
+        (
+          "a".to_string(),
+          JsValue::Object(IndexMap::from([(
+            "b".to_string(),
+            JsValue::Array(vec![JsValue::String("c".into())]),
+          )])),
+        ),
+        ("empty".to_string(), JsValue::Null),
+      ])),
+    );
+    assert_eq!(
+      eval("OBJ?.a?.b?.[0]", &constants),
+      Ok(JsValue::String("c".into()))
+    );
+    assert_eq!(
+      eval("OBJ?.a.b[0].length", &constants),
+      Ok(JsValue::Number(1.0))
+    );
+    assert_eq!(eval("OBJ?.missing?.x", &constants), Ok(JsValue::Undefined));
+    assert_eq!(
+      eval("OBJ?.empty?.x.y.z", &constants),
+      Ok(JsValue::Undefined)
+    );
+    assert_eq!(eval("OBJ.a?.b[5]?.x", &constants), Ok(JsValue::Undefined));
+    assert_eq!(eval("null?.x.y", &constants), Ok(JsValue::Undefined));
+    assert!(eval("OBJ?.empty.x", &constants).is_err());
+    assert!(eval("OBJ.missing?.x", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_typeof_missing() {
+    let mut constants = HashMap::new();
+    constants.insert(
+      (JsWord::from("OBJ"), Default::default()),
+      JsValue::Object(IndexMap::from([("a".to_string(), JsValue::Number(1.0))])),
+    );
+    let undefined = Ok(JsValue::String("undefined".into()));
+    assert_eq!(eval("typeof OBJ.nope", &constants), undefined);
+    assert_eq!(eval("typeof OBJ['nope']", &constants), undefined);
+    // Without the resolver's mark, the identifier could be a local binding.
+    assert!(eval("typeof UNDECLARED", &constants).is_err());
+    assert_eq!(
+      eval("typeof OBJ.a", &constants),
+      Ok(JsValue::String("number".into()))
+    );
+    assert!(eval("typeof OBJ.a.toFixed()", &constants).is_err());
+    assert!(eval("typeof UNDECLARED.a", &constants).is_err());
+    assert!(eval("typeof window", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_string_at() {
+    let constants = HashMap::new();
+    assert_eq!(
+      eval("'abc'.at(0)", &constants),
+      Ok(JsValue::String("a".into()))
+    );
+    assert_eq!(
+      eval("'abc'.at()", &constants),
+      Ok(JsValue::String("a".into()))
+    );
+    assert_eq!(
+      eval("'abc'.at(-1)", &constants),
+      Ok(JsValue::String("c".into()))
+    );
+    assert_eq!(
+      eval("'abc'.at(1.7)", &constants),
+      Ok(JsValue::String("b".into()))
+    );
+    assert_eq!(eval("'abc'.at(3)", &constants), Ok(JsValue::Undefined));
+    assert_eq!(eval("'abc'.at(-4)", &constants), Ok(JsValue::Undefined));
+    assert_eq!(
+      eval("'é😀x'.at(-1)", &constants),
+      Ok(JsValue::String("x".into()))
+    );
+    assert_eq!(
+      eval("'é😀x'.at(3)", &constants),
+      Ok(JsValue::String("x".into()))
+    );
+  }
+
+  #[test]
+  fn eval_expr_string_normalize() {
+    let constants = HashMap::new();
+    let composed = Ok(JsValue::String("\u{e9}".into()));
+    assert_eq!(eval("'e\\u0301'.normalize()", &constants), composed);
+    assert_eq!(eval("'e\\u0301'.normalize('NFC')", &constants), composed);
+    assert_eq!(
+      eval("'\\u00e9'.normalize('NFD')", &constants),
+      Ok(JsValue::String("e\u{301}".into()))
+    );
+    assert_eq!(
+      eval("'\\ufb01'.normalize('NFKC')", &constants),
+      Ok(JsValue::String("fi".into()))
+    );
+    assert_eq!(
+      eval("'\\u2460'.normalize('NFKD')", &constants),
+      Ok(JsValue::String("1".into()))
+    );
+    assert!(eval("'a'.normalize('nfc')", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_string_concat() {
+    let constants = HashMap::new();
+    assert_eq!(
+      eval("'a'.concat('b', 'c')", &constants),
+      Ok(JsValue::String("abc".into()))
+    );
+    assert_eq!(
+      eval("'a'.concat(1, null, [2, 3])", &constants),
+      Ok(JsValue::String("a1null2,3".into()))
+    );
+    assert_eq!(
+      eval("'a'.concat()", &constants),
+      Ok(JsValue::String("a".into()))
+    );
+  }
+
+  #[test]
+  fn eval_expr_string_repeat() {
+    let constants = HashMap::new();
+    assert_eq!(
+      eval("'ab'.repeat(2.5)", &constants),
+      Ok(JsValue::String("abab".into()))
+    );
+    assert_eq!(
+      eval("'ab'.repeat()", &constants),
+      Ok(JsValue::String("".into()))
+    );
+    assert!(eval("'ab'.repeat(-1)", &constants).is_err());
+    assert!(eval("'ab'.repeat(Infinity)", &constants).is_err());
+    assert!(eval("'x'.repeat(1e9)", &constants).is_err());
+    assert!(eval("'x'.repeat(3e6) + 'x'.repeat(3e6)", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_template_interpolation() {
+    let constants = HashMap::new();
+    assert_eq!(
+      eval(
+        "`${'s'} ${1} ${1.5} ${-0} ${true} ${null} ${undefined} ${[1, [2, null], undefined]} ${{}} ${/a/g}`",
+        &constants
+      ),
+      Ok(JsValue::String(
+        "s 1 1.5 0 true null undefined 1,2,, [object Object] /a/g".into()
+      ))
+    );
+    assert_eq!(
+      eval("`${0 / 0} ${1 / 0}`", &constants),
+      Ok(JsValue::String("NaN Infinity".into()))
+    );
+  }
+
+  #[test]
+  fn eval_expr_template_error() {
+    let constants = HashMap::new();
+    match eval("`a${b}`", &constants) {
+      Err(MacroError::EvaluationError(span, hint)) => {
+        assert_eq!(span.lo.0, 5);
+        assert_eq!(
+          hint.as_deref(),
+          Some("`b` is not a constant that can be evaluated at build time.")
+        );
+      }
+      res => panic!("expected an evaluation error, got {:?}", res),
+    }
+  }
+
+  #[test]
+  fn eval_expr_coercion_calls() {
+    let mut constants = HashMap::new();
+    constants.insert(
+      (JsWord::from("MAJOR"), Default::default()),
+      JsValue::Number(4.0),
+    );
+    let number = |n: f64| Ok(JsValue::Number(n));
+    assert_eq!(eval("Number(`${MAJOR}0`)", &constants), number(40.0));
+    assert_eq!(eval("parseInt(`${MAJOR}0px`)", &constants), number(40.0));
+    assert_eq!(
+      eval("parseInt(`ff${MAJOR}`, 16)", &constants),
+      number(4084.0)
+    );
+    assert_eq!(eval("parseFloat(`${MAJOR}.5.1`)", &constants), number(4.5));
+    assert_eq!(eval("Number(`0x${MAJOR}`) + 1", &constants), number(5.0));
+    assert_eq!(eval("Number()", &constants), number(0.0));
+    assert!(eval("Number(`${MAJOR}px`)", &constants)
+      .unwrap()
+      .to_js_number()
+      .is_nan());
+    assert_eq!(
+      eval("String(MAJOR) + String(Boolean(''))", &constants),
+      Ok(JsValue::String("4false".into()))
+    );
+    assert!(eval("parseInt(`${minor}`)", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_bitwise_coercion() {
+    let constants = HashMap::new();
+    let number = |n: f64| Ok(JsValue::Number(n));
+    assert_eq!(eval("'5' | 0", &constants), number(5.0));
+    assert_eq!(eval("true << 1", &constants), number(2.0));
+    assert_eq!(eval("null | 0", &constants), number(0.0));
+    assert_eq!(eval("undefined ^ 3", &constants), number(3.0));
+    assert_eq!(eval("~'7'", &constants), number(-8.0));
+    assert_eq!(eval("2 ** 32 + 5 | 0", &constants), number(5.0));
+    assert_eq!(eval("1 << 33", &constants), number(2.0));
+    assert_eq!(eval("-16 >> 2", &constants), number(-4.0));
+    assert!(eval("({}) | 0", &constants).is_err());
+    assert_eq!(eval("-1 >>> 0", &constants), number(4294967295.0));
+    assert_eq!(eval("-8 >>> 1", &constants), number(2147483644.0));
+    assert_eq!(eval("-1 >>> 32", &constants), number(4294967295.0));
+    assert_eq!(eval("'16' >>> 2", &constants), number(4.0));
+    assert!(eval("~[1]", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_arithmetic_coercion() {
+    let constants = HashMap::new();
+    let number = |n: f64| Ok(JsValue::Number(n));
+    assert_eq!(eval("'6' * '7'", &constants), number(42.0));
+    assert_eq!(eval("'10' - 5", &constants), number(5.0));
+    assert_eq!(eval("true / 2", &constants), number(0.5));
+    assert!(matches!(eval("' 7 ' % null", &constants), Ok(JsValue::Number(n)) if n.is_nan()));
+    assert_eq!(eval("' 7 ' % '2'", &constants), number(1.0));
+    assert_eq!(eval("'2' ** '3'", &constants), number(8.0));
+    assert!(matches!(eval("'x' - 1", &constants), Ok(JsValue::Number(n)) if n.is_nan()));
+    assert!(matches!(eval("undefined * 2", &constants), Ok(JsValue::Number(n)) if n.is_nan()));
+    assert!(eval("[] - 1", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_exponent() {
+    let constants = HashMap::new();
+    let number = |n: f64| Ok(JsValue::Number(n));
+    // `**` is right associative.
+    assert_eq!(eval("2 ** 3 ** 2", &constants), number(512.0));
+    assert_eq!(eval("(2 ** 3) ** 2", &constants), number(64.0));
+    assert_eq!(eval("(-2) ** 2", &constants), number(4.0));
+    assert_eq!(eval("(-2) ** 3", &constants), number(-8.0));
+    assert_eq!(eval("-(2 ** 2)", &constants), number(-4.0));
+    assert_eq!(eval("0 ** -1", &constants), number(f64::INFINITY));
+    assert_eq!(eval("(-0) ** -1", &constants), number(f64::NEG_INFINITY));
+    assert!(matches!(eval("(-8) ** 0.5", &constants), Ok(JsValue::Number(n)) if n.is_nan()));
+  }
+
+  #[test]
+  fn eval_expr_unary_coercion() {
+    let constants = HashMap::new();
+    assert_eq!(eval("!''", &constants), Ok(JsValue::Bool(true)));
+    assert_eq!(eval("!'a'", &constants), Ok(JsValue::Bool(false)));
+    assert_eq!(eval("!0", &constants), Ok(JsValue::Bool(true)));
+    assert_eq!(eval("![]", &constants), Ok(JsValue::Bool(false)));
+    assert_eq!(eval("!null", &constants), Ok(JsValue::Bool(true)));
+    assert_eq!(eval("-'5'", &constants), Ok(JsValue::Number(-5.0)));
+    assert_eq!(eval("+'5'", &constants), Ok(JsValue::Number(5.0)));
+    assert_eq!(eval("+true", &constants), Ok(JsValue::Number(1.0)));
+    assert_eq!(eval("-null", &constants), Ok(JsValue::Number(-0.0)));
+    assert!(matches!(eval("+'x'", &constants), Ok(JsValue::Number(n)) if n.is_nan()));
+    assert!(eval("-{}", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_conditional_truthiness() {
+    let source_map = SourceMap::default();
+    let constants: HashMap<Id, JsValue> = HashMap::new();
+    let evaluator = Evaluator {
+      constants: &constants,
+      cache: None,
+      cache_hits: None,
+      shared_spans: None,
+      unresolved_ctxt: None,
+      build_time: None,
+      source_map: Some(&source_map),
+      env: None,
+      missing_env_undefined: false,
+      global_types: None,
+      max_string_length: DEFAULT_MAX_STRING_LENGTH,
+      oversized_strings: None,
+      max_array_length: DEFAULT_MAX_ARRAY_LENGTH,
+      oversized_arrays: None,
+    };
+    let eval = |code: &str| evaluator.eval(&parse_expr(&source_map, code));
+    let yes = Ok(JsValue::String("yes".into()));
+    let no = Ok(JsValue::String("no".into()));
+    assert_eq!(eval("({ f() {} }).f ? 'yes' : 'no'"), yes);
+    assert_eq!(eval("/a/ ? 'yes' : 'no'"), yes);
+    assert_eq!(eval("0 / 0 ? 'yes' : 'no'"), no);
+    assert_eq!(eval("-0 ? 'yes' : 'no'"), no);
+    assert_eq!(eval("0n ? 'yes' : 'no'"), no);
+    assert_eq!(eval("1n ? 'yes' : 'no'"), yes);
+    assert_eq!(eval("'0' ? 'yes' : 'no'"), yes);
+  }
+
+  #[test]
+  fn eval_expr_logical_short_circuit() {
+    let constants = HashMap::new();
+    assert_eq!(eval("false && foo", &constants), Ok(JsValue::Bool(false)));
+    assert_eq!(
+      eval("'' && foo", &constants),
+      Ok(JsValue::String("".into()))
+    );
+    assert_eq!(
+      eval("1 && 'b'", &constants),
+      Ok(JsValue::String("b".into()))
+    );
+    assert_eq!(
+      eval("'a' || foo", &constants),
+      Ok(JsValue::String("a".into()))
+    );
+    assert_eq!(eval("0 || null", &constants), Ok(JsValue::Null));
+    assert_eq!(eval("0 ?? foo", &constants), Ok(JsValue::Number(0.0)));
+    assert_eq!(
+      eval("undefined ?? 'd'", &constants),
+      Ok(JsValue::String("d".into()))
+    );
+    assert!(eval("true && foo", &constants).is_err());
+    assert!(eval("foo || true", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_logical_chain() {
+    let mut constants = HashMap::new();
+    constants.insert(
+      (JsWord::from("OBJ"), Default::default()),
+      JsValue::Object(IndexMap::from([("b".to_string(), JsValue::Null)])),
+    );
+    assert_eq!(
+      eval(
+        "OBJ.a ?? OBJ.b ?? OBJ['c'] ?? 'd' ?? foo() ?? bar.baz",
+        &constants
+      ),
+      Ok(JsValue::String("d".into()))
+    );
+    assert_eq!(
+      eval("(0 || '' || 1) && (null ?? 2) && 3", &constants),
+      Ok(JsValue::Number(3.0))
+    );
+
+    // Only the first defined value is evaluated, however long the chain is.
+    let chain = format!("1{}", " ?? foo()".repeat(2000));
+    assert_eq!(eval(&chain, &constants), Ok(JsValue::Number(1.0)));
+    let chain = format!("{}1", "undefined ?? ".repeat(2000));
+    assert_eq!(eval(&chain, &constants), Ok(JsValue::Number(1.0)));
+    let chain = format!("{}foo", "null ?? ".repeat(2000));
+    assert!(eval(&chain, &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_sequence_delete() {
+    let mut constants = HashMap::new();
+    constants.insert(
+      ("obj".into(), SyntaxContext::empty()),
+      JsValue::Object(IndexMap::from([("a".to_string(), JsValue::Number(1.0))])),
+    );
+    assert_eq!(
+      eval("delete { a: 1 }.a", &constants),
+      Ok(JsValue::Bool(true))
+    );
+    assert_eq!(
+      eval("(delete { a: 1, b: 2 }['a'], 'done')", &constants),
+      Ok(JsValue::String("done".into()))
+    );
+    assert_eq!(eval("(1, 2, obj.a)", &constants), Ok(JsValue::Number(1.0)));
+    assert!(eval("delete obj.a", &constants).is_err());
+    assert!(eval("(delete { a: foo }.a, 1)", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_number_to_string() {
+    let constants = HashMap::new();
+    assert_eq!(
+      eval(
+        "'' + 1e21 + ' ' + 0.1 + `${-0}` + 1000000 + { [1e21]: 1 }['1e+21']",
+        &constants
+      ),
+      Ok(JsValue::String("1e+21 0.1010000001".into()))
+    );
+  }
+
+  #[test]
+  fn eval_expr_regex_test() {
+    let constants = HashMap::new();
+    assert_eq!(
+      eval("/b+c/.test('abbcd')", &constants),
+      Ok(JsValue::Bool(true))
+    );
+    assert_eq!(
+      eval("/^b/.test('abc')", &constants),
+      Ok(JsValue::Bool(false))
+    );
+    assert_eq!(
+      eval("/ABC/i.test('xabcx')", &constants),
+      Ok(JsValue::Bool(true))
+    );
+    assert_eq!(
+      eval("/ABC/.test('xabcx')", &constants),
+      Ok(JsValue::Bool(false))
+    );
+    assert_eq!(
+      eval("/(?<=a)b/.test('ab')", &constants),
+      Ok(JsValue::Bool(true))
+    );
+    assert!(eval("/a/g.test('a')", &constants).is_err());
+    assert!(eval("/a/ii.test('a')", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_string_match() {
+    let constants = HashMap::new();
+    let strings = |values: &[&str]| {
+      JsValue::Array(
+        values
+          .iter()
+          .map(|s| JsValue::String(s.to_string()))
+          .collect(),
+      )
+    };
+    assert_eq!(
+      eval(r"'2024-01'.match(/(\d+)-(\d+)/)", &constants),
+      Ok(strings(&["2024-01", "2024", "01"]))
+    );
+    assert_eq!(
+      eval(r"'a1'.match(/(?<letter>[a-z])(x)?/)", &constants),
+      Ok(JsValue::Array(vec![
+        JsValue::String("a".into()),
+        JsValue::String("a".into()),
+        JsValue::Undefined,
+      ]))
+    );
+    assert_eq!(eval(r"'abc'.match(/\d/)", &constants), Ok(JsValue::Null));
+    assert_eq!(eval(r"'abc'.match(/\d/g)", &constants), Ok(JsValue::Null));
+    assert_eq!(
+      eval(r"'a1b22'.match(/\d+/g)", &constants),
+      Ok(strings(&["1", "22"]))
+    );
+    assert_eq!(
+      eval(r"'a1b22'.matchAll(/[a-z](\d)/g)", &constants),
+      Ok(JsValue::Array(vec![
+        strings(&["a1", "1"]),
+        strings(&["b2", "2"])
+      ]))
+    );
+    assert!(eval(r"'a1'.matchAll(/\d/)", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_math() {
+    let constants = HashMap::new();
+    let number = |n: f64| Ok(JsValue::Number(n));
+    assert_eq!(eval("Math.hypot(2, 3, 6)", &constants), number(7.0));
+    assert_eq!(eval("Math.hypot()", &constants), number(0.0));
+    assert_eq!(
+      eval("Math.min(Math.max(-5, 0), 10)", &constants),
+      number(0.0)
+    );
+    assert_eq!(
+      eval("Math.min(Math.max(15, 0), 10)", &constants),
+      number(10.0)
+    );
+    assert_eq!(
+      eval("Math.min(Math.max(7, 0), 10)", &constants),
+      number(7.0)
+    );
+    assert_eq!(eval("Math.cbrt(-27)", &constants), number(-3.0));
+    assert_eq!(eval("Math.round(-2.5)", &constants), number(-2.0));
+    assert_eq!(eval("Math.round(2.5)", &constants), number(3.0));
+    assert_eq!(eval("Math.floor(Math.PI * 100)", &constants), number(314.0));
+    assert_eq!(eval("Math.max()", &constants), number(f64::NEG_INFINITY));
+    assert!(matches!(eval("Math.max(1, 0 / 0)", &constants), Ok(JsValue::Number(n)) if n.is_nan()));
+    assert!(eval("Math.random()", &constants).is_err());
+    assert!(eval("Math.abs('1')", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_member_cache() {
+    let source_map = SourceMap::default();
+    let mut constants: HashMap<Id, JsValue> = HashMap::new();
+    constants.insert(
+      (JsWord::from("OBJ"), Default::default()),
+      JsValue::Object(IndexMap::from([("a".to_string(), JsValue::Number(1.0))])),
+    );
+    let cache = RefCell::new(HashMap::new());
+    let cache_hits = Cell::new(0);
+    let evaluator = Evaluator {
+      constants: &constants,
+      cache: Some(&cache),
+      cache_hits: Some(&cache_hits),
+      shared_spans: None,
+      unresolved_ctxt: None,
+      build_time: None,
+      source_map: None,
+      env: None,
+      missing_env_undefined: false,
+      global_types: None,
+      max_string_length: DEFAULT_MAX_STRING_LENGTH,
+      oversized_strings: None,
+      max_array_length: DEFAULT_MAX_ARRAY_LENGTH,
+      oversized_arrays: None,
+    };
+
+    // Properties of evaluated objects are cached, while properties of constants are borrowed.
+    let owned = parse_expr(&source_map, "({ a: { b: 'c' } }).a.b");
+    let borrowed = parse_expr(&source_map, "OBJ.a");
+    for _ in 0..2 {
+      assert_eq!(
+        evaluator.eval_cow(&owned).as_deref(),
+        Ok(&JsValue::String("c".into()))
+      );
+      assert!(matches!(
+        evaluator.eval_cow(&borrowed),
+        Ok(Cow::Borrowed(_))
+      ));
+    }
+    assert_eq!(cache_hits.get(), 1);
+  }
+
+  #[test]
+  fn eval_object_methods() {
+    let source_map = SourceMap::default();
+    let constants: HashMap<Id, JsValue> = HashMap::new();
+    let mut evaluator = Evaluator {
+      constants: &constants,
+      cache: None,
+      cache_hits: None,
+      shared_spans: None,
+      unresolved_ctxt: None,
+      build_time: None,
+      source_map: None,
+      env: None,
+      missing_env_undefined: false,
+      global_types: None,
+      max_string_length: DEFAULT_MAX_STRING_LENGTH,
+      oversized_strings: None,
+      max_array_length: DEFAULT_MAX_ARRAY_LENGTH,
+      oversized_arrays: None,
+    };
+    let obj = parse_expr(
+      &source_map,
+      "({ a: 1, foo(x) { return x; }, async *'bar'() {} })",
+    );
+    assert!(evaluator.eval(&obj).is_err());
+
+    evaluator.source_map = Some(&source_map);
+    let expected = IndexMap::from([
+      ("a".to_string(), JsValue::Number(1.0)),
+      (
+        "foo".to_string(),
+        JsValue::Function("function(x) { return x; }".into()),
+      ),
+      (
+        "bar".to_string(),
+        JsValue::Function("async function*() {}".into()),
+      ),
+    ]);
+    assert_eq!(evaluator.eval(&obj), Ok(JsValue::Object(expected)));
+    assert!(evaluator
+      .eval(&parse_expr(&source_map, "({ get a() { return 1; } })"))
+      .is_err());
+  }
+
+  #[test]
+  fn eval_build_time() {
+    let source_map = SourceMap::default();
+    let constants: HashMap<Id, JsValue> = HashMap::new();
+    let mut evaluator = Evaluator {
+      constants: &constants,
+      cache: None,
+      cache_hits: None,
+      shared_spans: None,
+      unresolved_ctxt: None,
+      build_time: None,
+      source_map: None,
+      env: None,
+      missing_env_undefined: false,
+      global_types: None,
+      max_string_length: DEFAULT_MAX_STRING_LENGTH,
+      oversized_strings: None,
+      max_array_length: DEFAULT_MAX_ARRAY_LENGTH,
+      oversized_arrays: None,
+    };
+    let now = parse_expr(&source_map, "Date.now()");
+    let get_time = parse_expr(&source_map, "new Date().getTime()");
+    assert!(evaluator.eval(&now).is_err());
+    assert!(evaluator.eval(&get_time).is_err());
+
+    evaluator.build_time = Some(1700000000000.0);
+    assert_eq!(evaluator.eval(&now), Ok(JsValue::Number(1700000000000.0)));
+    assert_eq!(
+      evaluator.eval(&get_time),
+      Ok(JsValue::Number(1700000000000.0))
+    );
+    assert_eq!(
+      evaluator.eval(&parse_expr(&source_map, "new Date(0).getTime()")),
+      Ok(JsValue::Number(0.0))
+    );
+  }
+
+  #[test]
+  fn eval_expr_new() {
+    let constants = HashMap::new();
+    assert_eq!(
+      eval("new Array(3)", &constants),
+      Ok(JsValue::Array(vec![JsValue::Undefined; 3]))
+    );
+    assert_eq!(
+      eval("new Array('3', 4)", &constants),
+      Ok(JsValue::Array(vec![
+        JsValue::String("3".into()),
+        JsValue::Number(4.0)
+      ]))
+    );
+    assert!(eval("new Array(-1)", &constants).is_err());
+    assert!(eval("new Array(4294967294)", &constants).is_err());
+    assert_eq!(
+      eval("new RegExp('a', 'i')", &constants),
+      Ok(JsValue::Regex {
+        source: "a".into(),
+        flags: "i".into()
+      })
+    );
+    assert_eq!(
+      eval("new RegExp('a/b\\n[/]')", &constants),
+      Ok(JsValue::Regex {
+        source: r"a\/b\n[/]".into(),
+        flags: "".into()
+      })
+    );
+    assert_eq!(
+      eval("new RegExp(/x/g, 'y')", &constants),
+      Ok(JsValue::Regex {
+        source: "x".into(),
+        flags: "y".into()
+      })
+    );
+    assert!(eval("new RegExp('(')", &constants).is_err());
+    assert_eq!(
+      eval("new String(1.5)", &constants),
+      Ok(JsValue::String("1.5".into()))
+    );
+    assert_eq!(
+      eval("new Number(' 0x1f ')", &constants),
+      Ok(JsValue::Number(31.0))
+    );
+    assert_eq!(
+      eval("new Boolean('')", &constants),
+      Ok(JsValue::Bool(false))
+    );
+    assert!(eval("new Date()", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_date() {
+    let constants = HashMap::new();
+    assert_eq!(
+      eval("new Date(1700000000000)", &constants),
+      Ok(JsValue::Date(1700000000000.0))
+    );
+    assert_eq!(
+      eval("new Date(new Date(1.5)).getTime()", &constants),
+      Ok(JsValue::Number(1.0))
+    );
+    assert_eq!(
+      eval("new Date(1700000000000).toISOString()", &constants),
+      Ok(JsValue::String("2023-11-14T22:13:20.000Z".into()))
+    );
+    assert_eq!(
+      eval("new Date(1700000000000).getFullYear()", &constants),
+      Ok(JsValue::Number(2023.0))
+    );
+    assert_eq!(
+      eval("JSON.stringify({ date: new Date(0) })", &constants),
+      Ok(JsValue::String(
+        r#"{"date":"1970-01-01T00:00:00.000Z"}"#.into()
+      ))
+    );
+    assert_eq!(
+      eval("typeof new Date(0)", &constants),
+      Ok(JsValue::String("object".into()))
+    );
+    assert!(eval("new Date(8.64e15 + 1).getFullYear()", &constants)
+      .unwrap()
+      .to_js_number()
+      .is_nan());
+    assert!(eval("new Date(NaN).toISOString()", &constants).is_err());
+    assert!(eval("new Date('2020-01-01')", &constants).is_err());
+    assert!(eval("new Date(2020, 0, 1)", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_map() {
+    let constants = HashMap::new();
+    assert_eq!(eval("new Map()", &constants), Ok(JsValue::Map(vec![])));
+    assert_eq!(
+      eval("new Map([['a', 1], [2, 'b'], ['a', 3]])", &constants),
+      Ok(JsValue::Map(vec![
+        (JsValue::String("a".into()), JsValue::Number(3.0)),
+        (JsValue::Number(2.0), JsValue::String("b".into())),
+      ]))
+    );
+    assert_eq!(
+      eval("new Map([[[1], 'x']]).get([1])", &constants),
+      Ok(JsValue::String("x".into()))
+    );
+    assert_eq!(
+      eval("new Map([['a', 1]]).get('b')", &constants),
+      Ok(JsValue::Undefined)
+    );
+    assert_eq!(
+      eval("new Map([['a', 1]]).has('a')", &constants),
+      Ok(JsValue::Bool(true))
+    );
+    assert_eq!(
+      eval("new Map([['a', 1]]).has(1)", &constants),
+      Ok(JsValue::Bool(false))
+    );
+    assert_eq!(
+      eval("new Map([['a', 1], ['b', 2]]).size", &constants),
+      Ok(JsValue::Number(2.0))
+    );
+    assert_eq!(
+      eval("typeof new Map()", &constants),
+      Ok(JsValue::String("object".into()))
+    );
+    assert_eq!(
+      eval("new Map([[0 / 0, 1], [0 / 0, 2]]).get(0 / 0)", &constants),
+      Ok(JsValue::Number(2.0))
+    );
+    assert_eq!(
+      eval("new Map([[0 / 0, 1]]).has(0 / 0)", &constants),
+      Ok(JsValue::Bool(true))
+    );
+    assert_eq!(
+      eval("new Map([[-0, 1]]).has(0)", &constants),
+      Ok(JsValue::Bool(true))
+    );
+    assert!(eval("new Map([1])", &constants).is_err());
+    assert!(eval("new Map('ab')", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_set() {
+    let constants = HashMap::new();
+    assert_eq!(eval("new Set()", &constants), Ok(JsValue::Set(vec![])));
+    assert_eq!(
+      eval("new Set([1, 'a', 1, [2], [2], 0, -0])", &constants),
+      Ok(JsValue::Set(vec![
+        JsValue::Number(1.0),
+        JsValue::String("a".into()),
+        JsValue::Array(vec![JsValue::Number(2.0)]),
+        JsValue::Number(0.0),
+      ]))
+    );
+    assert_eq!(
+      eval("new Set('abca')", &constants),
+      Ok(JsValue::Set(vec![
+        JsValue::String("a".into()),
+        JsValue::String("b".into()),
+        JsValue::String("c".into()),
+      ]))
+    );
+    assert_eq!(
+      eval("new Set(['a', 'b']).has('b')", &constants),
+      Ok(JsValue::Bool(true))
+    );
+    assert_eq!(
+      eval("new Set(['a', 'b']).has('c')", &constants),
+      Ok(JsValue::Bool(false))
+    );
+    assert_eq!(
+      eval("new Set([1, 2, 2, 3]).size", &constants),
+      Ok(JsValue::Number(3.0))
+    );
+    assert_eq!(
+      eval("new Set([0 / 0, 0 / 0]).size", &constants),
+      Ok(JsValue::Number(1.0))
+    );
+    assert_eq!(
+      eval("new Set([0 / 0]).has(0 / 0)", &constants),
+      Ok(JsValue::Bool(true))
+    );
+    assert!(eval("new Set([1]).get(1)", &constants).is_err());
+    assert!(eval("new Set(1)", &constants).is_err());
+  }
+
+  #[test]
+  fn eval_expr_number_keys() {
+    let constants = HashMap::new();
+    let res = eval(
+      "({1.5: 'a', 1e21: 'b', 0x10: 'c', 1.0: 'd', [-0]: 'e', [2 ** 70]: 'f'})",
+      &constants,
+    );
+    let keys: Vec<_> = match &res {
+      Ok(JsValue::Object(obj)) => obj.keys().map(String::as_str).collect(),
+      _ => panic!("expected object, got {:?}", res),
+    };
+    assert_eq!(
+      keys,
+      ["1.5", "1e+21", "16", "1", "0", "1.1805916207174113e+21"]
+    );
+  }
+
+  #[test]
+  fn eval_expr_error() {
+    let constants = HashMap::new();
+    match eval("[1, foo]", &constants) {
+      Err(MacroError::EvaluationError(span, hint)) => {
+        assert_eq!(span.lo.0, 5);
+        assert_eq!(
+          hint.as_deref(),
+          Some("`foo` is not a constant that can be evaluated at build time.")
+        );
+      }
+      res => panic!("expected an evaluation error, got {:?}", res),
+    }
+  }
+}
@@ -0,0 +1,669 @@
+//! Implementations of global JavaScript functions that can be statically evaluated.
+
+use crate::value::{date_to_iso_string, js_number_to_string};
+use crate::JsValue;
+
+/// Returns a hint describing the arguments a global function requires, used when
+/// a call to it could not be evaluated.
+pub(crate) fn call_hint(name: &str) -> Option<String> {
+  let requirement = match name {
+    "encodeURI" | "encodeURIComponent" => "a string argument",
+    "decodeURI" | "decodeURIComponent" => "a string containing valid percent-encoded UTF-8",
+    "btoa" => "a string containing only Latin-1 characters",
+    "atob" => "a valid base64 encoded string",
+    "Array.from" => "a string or array, and does not support a map function",
+    "JSON.stringify" => "a single argument, and does not support a replacer or indentation",
+    "structuredClone" => "a value that does not contain functions",
+    "Date.now" => "a build time to be configured, so that builds are reproducible",
+    _ => return None,
+  };
+  Some(format!("`{}` requires {}.", name, requirement))
+}
+
+/// Globals defined by JavaScript itself or by common host environments. Other unresolved
+/// identifiers are assumed to be undeclared.
+const KNOWN_GLOBALS: &[&str] = &[
+  "AggregateError",
+  "Array",
+  "ArrayBuffer",
+  "Atomics",
+  "BigInt",
+  "BigInt64Array",
+  "BigUint64Array",
+  "Boolean",
+  "Buffer",
+  "DataView",
+  "Date",
+  "Deno",
+  "Error",
+  "EvalError",
+  "FinalizationRegistry",
+  "Float32Array",
+  "Float64Array",
+  "Function",
+  "Infinity",
+  "Int16Array",
+  "Int32Array",
+  "Int8Array",
+  "Intl",
+  "JSON",
+  "Map",
+  "Math",
+  "NaN",
+  "Number",
+  "Object",
+  "Promise",
+  "Proxy",
+  "RangeError",
+  "ReferenceError",
+  "Reflect",
+  "RegExp",
+  "Set",
+  "SharedArrayBuffer",
+  "String",
+  "Symbol",
+  "SyntaxError",
+  "TypeError",
+  "URIError",
+  "Uint16Array",
+  "Uint32Array",
+  "Uint8Array",
+  "Uint8ClampedArray",
+  "WeakMap",
+  "WeakRef",
+  "WeakSet",
+  "atob",
+  "btoa",
+  "clearInterval",
+  "clearTimeout",
+  "console",
+  "decodeURI",
+  "decodeURIComponent",
+  "document",
+  "encodeURI",
+  "encodeURIComponent",
+  "escape",
+  "eval",
+  "exports",
+  "fetch",
+  "global",
+  "globalThis",
+  "isFinite",
+  "isNaN",
+  "location",
+  "module",
+  "navigator",
+  "parseFloat",
+  "parseInt",
+  "process",
+  "require",
+  "self",
+  "setInterval",
+  "setTimeout",
+  "structuredClone",
+  "undefined",
+  "unescape",
+  "window",
+];
+
+/// Whether the name is a global that may be defined at runtime.
+pub(crate) fn is_known_global(name: &str) -> bool {
+  KNOWN_GLOBALS.binary_search(&name).is_ok()
+}
+
+/// Characters that are never escaped by `encodeURIComponent`.
+const URI_UNRESERVED_MARKS: &str = "-_.!~*'()";
+
+/// Additional characters that are not escaped by `encodeURI`, and that `decodeURI`
+/// leaves escaped.
+const URI_RESERVED: &str = ";/?:@&=+$,#";
+
+/// Implements `encodeURI` and `encodeURIComponent`.
+pub(crate) fn encode_uri(s: &str, component: bool) -> String {
+  let mut res = String::with_capacity(s.len());
+  for c in s.chars() {
+    if c.is_ascii_alphanumeric()
+      || URI_UNRESERVED_MARKS.contains(c)
+      || (!component && URI_RESERVED.contains(c))
+    {
+      res.push(c);
+    } else {
+      let mut buf = [0; 4];
+      for b in c.encode_utf8(&mut buf).bytes() {
+        res.push_str(&format!("%{:02X}", b));
+      }
+    }
+  }
+  res
+}
+
+/// Implements `decodeURI` and `decodeURIComponent`. Returns None if the string
+/// contains malformed escape sequences, in which case JS throws a URIError.
+pub(crate) fn decode_uri(s: &str, component: bool) -> Option<String> {
+  let bytes = s.as_bytes();
+  let mut res = String::with_capacity(s.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    if bytes[i] != b'%' {
+      // Copy everything up to the next escape sequence.
+      let end = s[i..].find('%').map_or(s.len(), |j| i + j);
+      res.push_str(&s[i..end]);
+      i = end;
+      continue;
+    }
+
+    let b = decode_escape(bytes, i)?;
+    if b < 0x80 {
+      let c = b as char;
+      if !component && URI_RESERVED.contains(c) {
+        res.push_str(&s[i..i + 3]);
+      } else {
+        res.push(c);
+      }
+      i += 3;
+      continue;
+    }
+
+    // Multi-byte UTF-8 sequences must be fully escaped.
+    let len = b.leading_ones() as usize;
+    if !(2..=4).contains(&len) {
+      return None;
+    }
+    let mut buf = Vec::with_capacity(len);
+    for _ in 0..len {
+      buf.push(decode_escape(bytes, i)?);
+      i += 3;
+    }
+    res.push_str(std::str::from_utf8(&buf).ok()?);
+  }
+  Some(res)
+}
+
+/// Decodes the `%XX` escape sequence at the given index.
+fn decode_escape(bytes: &[u8], i: usize) -> Option<u8> {
+  if bytes.get(i) != Some(&b'%') {
+    return None;
+  }
+  let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+  u8::from_str_radix(hex, 16).ok()
+}
+
+/// Escapes a pattern passed to the `RegExp` constructor so it can be used as the
+/// source of a regular expression literal, as `RegExp.prototype.source` does.
+pub(crate) fn regexp_source(pattern: &str) -> String {
+  if pattern.is_empty() {
+    return "(?:)".into();
+  }
+
+  let mut res = String::with_capacity(pattern.len());
+  let mut escaped = false;
+  let mut in_class = false;
+  for c in pattern.chars() {
+    match c {
+      '/' if !escaped && !in_class => res.push_str("\\/"),
+      '\n' => res.push_str(if escaped { "n" } else { "\\n" }),
+      '\r' => res.push_str(if escaped { "r" } else { "\\r" }),
+      '\u{2028}' => res.push_str(if escaped { "u2028" } else { "\\u2028" }),
+      '\u{2029}' => res.push_str(if escaped { "u2029" } else { "\\u2029" }),
+      c => {
+        if !escaped {
+          match c {
+            '[' => in_class = true,
+            ']' => in_class = false,
+            _ => {}
+          }
+        }
+        res.push(c);
+      }
+    }
+    escaped = !escaped && c == '\\';
+  }
+  res
+}
+
+/// Normalizes regular expression flags to the order `RegExp.prototype.flags` uses,
+/// removing duplicates. Returns None if a flag is unknown, or both `u` and `v` are set.
+pub(crate) fn regexp_flags(flags: &str) -> Option<String> {
+  if flags.chars().any(|c| !"dgimsuvy".contains(c)) || (flags.contains('u') && flags.contains('v'))
+  {
+    return None;
+  }
+  Some("dgimsuvy".chars().filter(|c| flags.contains(*c)).collect())
+}
+
+/// Returns the value of a `Math` constant, e.g. `Math.PI`.
+pub(crate) fn math_constant(name: &str) -> Option<f64> {
+  use std::f64::consts;
+
+  Some(match name {
+    "E" => consts::E,
+    "LN10" => consts::LN_10,
+    "LN2" => consts::LN_2,
+    "LOG10E" => consts::LOG10_E,
+    "LOG2E" => consts::LOG2_E,
+    "PI" => consts::PI,
+    "SQRT1_2" => consts::FRAC_1_SQRT_2,
+    "SQRT2" => consts::SQRT_2,
+    _ => return None,
+  })
+}
+
+/// Implements the `Math` functions with numeric arguments. Missing arguments are NaN,
+/// as in JS. `Math.random` is intentionally not supported, since it is not deterministic.
+pub(crate) fn math(name: &str, args: &[f64]) -> Option<f64> {
+  let x = args.first().copied().unwrap_or(f64::NAN);
+  let y = args.get(1).copied().unwrap_or(f64::NAN);
+  Some(match name {
+    "abs" => x.abs(),
+    "acos" => x.acos(),
+    "acosh" => x.acosh(),
+    "asin" => x.asin(),
+    "asinh" => x.asinh(),
+    "atan" => x.atan(),
+    "atan2" => x.atan2(y),
+    "atanh" => x.atanh(),
+    // Unlike x.powf(1 / 3), this handles negative numbers.
+    "cbrt" => x.cbrt(),
+    "ceil" => x.ceil(),
+    "cos" => x.cos(),
+    "cosh" => x.cosh(),
+    "exp" => x.exp(),
+    "expm1" => x.exp_m1(),
+    "floor" => x.floor(),
+    "hypot" => hypot(args),
+    "log" => x.ln(),
+    "log10" => x.log10(),
+    "log1p" => x.ln_1p(),
+    "log2" => x.log2(),
+    "max" => args
+      .iter()
+      .try_fold(f64::NEG_INFINITY, |max, &n| {
+        // NaN is returned if any argument is NaN, and +0 is considered larger than -0.
+        if n.is_nan() {
+          None
+        } else if n > max || (n == max && max.is_sign_negative()) {
+          Some(n)
+        } else {
+          Some(max)
+        }
+      })
+      .unwrap_or(f64::NAN),
+    "min" => args
+      .iter()
+      .try_fold(f64::INFINITY, |min, &n| {
+        if n.is_nan() {
+          None
+        } else if n < min || (n == min && n.is_sign_negative()) {
+          Some(n)
+        } else {
+          Some(min)
+        }
+      })
+      .unwrap_or(f64::NAN),
+    "pow" => pow(x, y),
+    "round" => round(x),
+    "sign" => {
+      if x.is_nan() || x == 0.0 {
+        x
+      } else {
+        x.signum()
+      }
+    }
+    "sin" => x.sin(),
+    "sinh" => x.sinh(),
+    "sqrt" => x.sqrt(),
+    "tan" => x.tan(),
+    "tanh" => x.tanh(),
+    "trunc" => x.trunc(),
+    _ => return None,
+  })
+}
+
+/// Implements the `**` operator and `Math.pow`, which differ from `powf` for NaN
+/// exponents and a base of ±1 with an infinite exponent.
+pub(crate) fn pow(x: f64, y: f64) -> f64 {
+  if y.is_nan() || (x.abs() == 1.0 && y.is_infinite()) {
+    f64::NAN
+  } else {
+    x.powf(y)
+  }
+}
+
+/// Implements `Math.round`, which rounds halfway cases towards positive infinity.
+fn round(x: f64) -> f64 {
+  if !x.is_finite() || x == 0.0 {
+    return x;
+  }
+  if (-0.5..0.0).contains(&x) {
+    return -0.0;
+  }
+  let floor = x.floor();
+  if x - floor >= 0.5 {
+    floor + 1.0
+  } else {
+    floor
+  }
+}
+
+/// Implements `Math.hypot` for any number of arguments, scaling by the largest value
+/// to avoid overflow.
+fn hypot(args: &[f64]) -> f64 {
+  if args.iter().any(|n| n.is_infinite()) {
+    return f64::INFINITY;
+  }
+  if args.iter().any(|n| n.is_nan()) {
+    return f64::NAN;
+  }
+  let max = args.iter().fold(0.0, |max: f64, n| max.max(n.abs()));
+  if max == 0.0 {
+    return 0.0;
+  }
+  let sum: f64 = args.iter().map(|n| (n / max) * (n / max)).sum();
+  max * sum.sqrt()
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+  b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Implements `btoa`. Returns None if the string contains characters outside of
+/// Latin-1, in which case JS throws an InvalidCharacterError.
+pub(crate) fn btoa(s: &str) -> Option<String> {
+  let bytes = s
+    .chars()
+    .map(|c| u8::try_from(c).ok())
+    .collect::<Option<Vec<u8>>>()?;
+
+  let mut res = String::with_capacity(bytes.len().div_ceil(3) * 4);
+  for chunk in bytes.chunks(3) {
+    let n = chunk
+      .iter()
+      .enumerate()
+      .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - i * 8));
+    for i in 0..4 {
+      if i <= chunk.len() {
+        res.push(BASE64_ALPHABET[(n >> (18 - i * 6)) as usize & 0x3f] as char);
+      } else {
+        res.push('=');
+      }
+    }
+  }
+  Some(res)
+}
+
+/// Implements `atob`, using the forgiving base64 decoding algorithm. Returns None
+/// if the string is not valid base64. Each decoded byte becomes one Latin-1 character.
+pub(crate) fn atob(s: &str) -> Option<String> {
+  let mut data: Vec<u8> = s
+    .bytes()
+    .filter(|b| !matches!(b, b' ' | b'\t' | b'\n' | b'\x0c' | b'\r'))
+    .collect();
+
+  match data.len() % 4 {
+    0 => {
+      for _ in 0..2 {
+        if data.last() == Some(&b'=') {
+          data.pop();
+        }
+      }
+    }
+    1 => return None,
+    _ => {}
+  }
+
+  let mut res = String::with_capacity(data.len() * 3 / 4);
+  let mut buffer = 0u32;
+  let mut bits = 0;
+  for b in data {
+    let value = BASE64_ALPHABET.iter().position(|c| *c == b)? as u32;
+    buffer = buffer << 6 | value;
+    bits += 6;
+    if bits >= 8 {
+      bits -= 8;
+      res.push(((buffer >> bits) & 0xff) as u8 as char);
+    }
+  }
+  Some(res)
+}
+
+/// Implements `parseInt`. The radix has already been converted to an integer, and 0
+/// means it was omitted, in which case a `0x` prefix selects base 16.
+pub(crate) fn parse_int(s: &str, radix: i32) -> f64 {
+  let s = s.trim_start();
+  let (sign, s) = match s.strip_prefix('-') {
+    Some(s) => (-1.0, s),
+    None => (1.0, s.strip_prefix('+').unwrap_or(s)),
+  };
+  let (radix, s) = match radix {
+    0 | 16 => match s.get(..2) {
+      Some("0x" | "0X") => (16, &s[2..]),
+      _ => (if radix == 0 { 10 } else { 16 }, s),
+    },
+    2..=36 => (radix as u32, s),
+    _ => return f64::NAN,
+  };
+
+  let end = s.find(|c: char| !c.is_digit(radix)).unwrap_or(s.len());
+  let digits = &s[..end];
+  if digits.is_empty() {
+    return f64::NAN;
+  }
+  // Parse decimal digits directly so that long inputs are correctly rounded.
+  let n = if radix == 10 {
+    digits.parse().unwrap()
+  } else {
+    digits.chars().fold(0.0, |n, c| {
+      n * radix as f64 + c.to_digit(radix).unwrap() as f64
+    })
+  };
+  sign * n
+}
+
+/// Implements `parseFloat`, which parses the longest prefix of the string that is a
+/// decimal number, ignoring any trailing characters.
+pub(crate) fn parse_float(s: &str) -> f64 {
+  let s = s.trim_start();
+  let unsigned = s.strip_prefix(['+', '-']).unwrap_or(s);
+  if unsigned.starts_with("Infinity") {
+    return if s.starts_with('-') {
+      f64::NEG_INFINITY
+    } else {
+      f64::INFINITY
+    };
+  }
+
+  let bytes = s.as_bytes();
+  let digits_from = |mut i: usize| {
+    while i < bytes.len() && bytes[i].is_ascii_digit() {
+      i += 1;
+    }
+    i
+  };
+  let start = s.len() - unsigned.len();
+  let mut end = digits_from(start);
+  let mut has_digits = end > start;
+  if bytes.get(end) == Some(&b'.') {
+    let fraction_end = digits_from(end + 1);
+    has_digits |= fraction_end > end + 1;
+    end = fraction_end;
+  }
+  if !has_digits {
+    return f64::NAN;
+  }
+  // The exponent is only included if it has digits, e.g. "1e" parses as 1.
+  if matches!(bytes.get(end), Some(b'e' | b'E')) {
+    let sign_len = matches!(bytes.get(end + 1), Some(b'+' | b'-')) as usize;
+    let exponent_end = digits_from(end + 1 + sign_len);
+    if exponent_end > end + 1 + sign_len {
+      end = exponent_end;
+    }
+  }
+  s[..end].parse().unwrap_or(f64::NAN)
+}
+
+/// Implements `JSON.stringify` without a replacer or indentation. Returns None for values
+/// that are not serialized, i.e. undefined and functions, which `JSON.stringify` returns
+/// undefined for. Like in JS, these become null in arrays and are omitted from objects.
+/// BigInts cannot be serialized, so return an error, as `JSON.stringify` throws.
+pub(crate) fn json_stringify(value: &JsValue) -> Result<Option<String>, ()> {
+  let json_string = |s: &str| serde_json::to_string(s).unwrap();
+  Ok(Some(match value {
+    JsValue::Undefined | JsValue::Function(_) => return Ok(None),
+    JsValue::BigInt(_) => return Err(()),
+    JsValue::Null => "null".into(),
+    JsValue::Bool(b) => b.to_string(),
+    JsValue::Number(n) if n.is_finite() => js_number_to_string(*n),
+    JsValue::Number(_) => "null".into(),
+    JsValue::String(s) => json_string(s),
+    JsValue::Array(arr) => {
+      let elements = arr
+        .iter()
+        .map(|v| Ok(json_stringify(v)?.unwrap_or_else(|| "null".into())))
+        .collect::<Result<Vec<_>, _>>()?;
+      format!("[{}]", elements.join(","))
+    }
+    JsValue::Object(obj) => {
+      let mut entries = Vec::with_capacity(obj.len());
+      for (k, v) in obj {
+        if let Some(v) = json_stringify(v)? {
+          entries.push(format!("{}:{}", json_string(k), v));
+        }
+      }
+      format!("{{{}}}", entries.join(","))
+    }
+    // These have no enumerable own properties.
+    JsValue::Regex { .. } | JsValue::Map(_) | JsValue::Set(_) => "{}".into(),
+    // Dates are converted by their toJSON method.
+    JsValue::Date(time) => {
+      date_to_iso_string(*time).map_or_else(|| "null".into(), |s| json_string(&s))
+    }
+  }))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn math_edge_cases() {
+    assert_eq!(math("round", &[0.49999999999999994]), Some(0.0));
+    assert!(math("round", &[-0.2]).unwrap().is_sign_negative());
+    assert!(math("min", &[0.0, -0.0]).unwrap().is_sign_negative());
+    assert!(math("max", &[-0.0, 0.0]).unwrap().is_sign_positive());
+    assert_eq!(
+      math("hypot", &[3.0, f64::NAN, f64::INFINITY]),
+      Some(f64::INFINITY)
+    );
+    assert_eq!(math("hypot", &[1e200, 1e200]), Some(1e200 * 2f64.sqrt()));
+    assert!(pow(1.0, f64::INFINITY).is_nan());
+    assert!(math("sqrt", &[]).unwrap().is_nan());
+    assert_eq!(math("random", &[]), None);
+  }
+
+  #[test]
+  fn base64_round_trip() {
+    for (s, encoded) in [
+      ("", ""),
+      ("a", "YQ=="),
+      ("ab", "YWI="),
+      ("abc", "YWJj"),
+      ("hello, wörld\u{ff}", "aGVsbG8sIHf2cmxk/w=="),
+    ] {
+      assert_eq!(btoa(s).as_deref(), Some(encoded));
+      assert_eq!(atob(encoded).as_deref(), Some(s));
+    }
+
+    assert_eq!(atob(" YW Jj\n").as_deref(), Some("abc"));
+    assert_eq!(atob("YQ").as_deref(), Some("a"));
+  }
+
+  #[test]
+  fn parse_int_radix() {
+    assert_eq!(parse_int("  42px", 0), 42.0);
+    assert_eq!(parse_int("-0x1F", 0), -31.0);
+    assert_eq!(parse_int("0x1F", 16), 31.0);
+    assert_eq!(parse_int("1F", 16), 31.0);
+    assert_eq!(parse_int("0x1F", 10), 0.0);
+    assert_eq!(parse_int("z", 36), 35.0);
+    assert_eq!(parse_int("1010", 2), 10.0);
+    assert_eq!(
+      parse_int("12345678901234567890123", 0),
+      1.2345678901234568e22
+    );
+    assert!(parse_int("-0", 0).is_sign_negative());
+    assert!(parse_int("", 0).is_nan());
+    assert!(parse_int("0x", 0).is_nan());
+    assert!(parse_int("2", 2).is_nan());
+    assert!(parse_int("1", 1).is_nan());
+    assert!(parse_int("1", 37).is_nan());
+  }
+
+  #[test]
+  fn parse_float_prefix() {
+    assert_eq!(parse_float(" 2.5abc"), 2.5);
+    assert_eq!(parse_float("-.5"), -0.5);
+    assert_eq!(parse_float("5."), 5.0);
+    assert_eq!(parse_float("1e3x"), 1000.0);
+    assert_eq!(parse_float("1e"), 1.0);
+    assert_eq!(parse_float("1e+"), 1.0);
+    assert_eq!(parse_float("2E-2"), 0.02);
+    assert_eq!(parse_float("0x10"), 0.0);
+    assert_eq!(parse_float("-Infinityx"), f64::NEG_INFINITY);
+    assert!(parse_float(".").is_nan());
+    assert!(parse_float("inf").is_nan());
+    assert!(parse_float("-").is_nan());
+  }
+
+  #[test]
+  fn base64_errors() {
+    assert_eq!(btoa("€"), None);
+    assert_eq!(atob("Y"), None);
+    assert_eq!(atob("YQ=a"), None);
+    assert_eq!(atob("Y!=="), None);
+  }
+
+  #[test]
+  fn uri_round_trip() {
+    let s = "a b&c=d/é?#ü€😀-_.!~*'()";
+    let component = encode_uri(s, true);
+    assert_eq!(
+      component,
+      "a%20b%26c%3Dd%2F%C3%A9%3F%23%C3%BC%E2%82%AC%F0%9F%98%80-_.!~*'()"
+    );
+    assert_eq!(decode_uri(&component, true).as_deref(), Some(s));
+
+    let uri = encode_uri(s, false);
+    assert_eq!(
+      uri,
+      "a%20b&c=d/%C3%A9?#%C3%BC%E2%82%AC%F0%9F%98%80-_.!~*'()"
+    );
+    assert_eq!(decode_uri(&uri, false).as_deref(), Some(s));
+  }
+
+  #[test]
+  fn regexp_source_and_flags() {
+    assert_eq!(regexp_source(""), "(?:)");
+    assert_eq!(regexp_source("a/b"), r"a\/b");
+    assert_eq!(regexp_source(r"a\/b[/]"), r"a\/b[/]");
+    assert_eq!(regexp_source("a\nb"), r"a\nb");
+    assert_eq!(regexp_flags("yig").as_deref(), Some("giy"));
+    assert_eq!(regexp_flags("gg").as_deref(), Some("g"));
+    assert_eq!(regexp_flags(""), Some(String::new()));
+    assert_eq!(regexp_flags("x"), None);
+    assert_eq!(regexp_flags("uv"), None);
+  }
+
+  #[test]
+  fn decode_uri_reserved() {
+    assert_eq!(decode_uri("%2F%20", false).as_deref(), Some("%2F "));
+    assert_eq!(decode_uri("%2F%20", true).as_deref(), Some("/ "));
+  }
+
+  #[test]
+  fn decode_uri_malformed() {
+    assert_eq!(decode_uri("%", true), None);
+    assert_eq!(decode_uri("%zz", true), None);
+    assert_eq!(decode_uri("%C3", true), None);
+    assert_eq!(decode_uri("%C3%28", true), None);
+    assert_eq!(decode_uri("%80", true), None);
+  }
+}
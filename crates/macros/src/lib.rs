@@ -1,28 +1,66 @@
 use indexmap::IndexMap;
+use num_bigint::BigInt as BigIntValue;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 use swc_core::common::util::take::Take;
-use swc_core::common::{SourceMap, Span, DUMMY_SP};
+use swc_core::common::{Mark, SourceMap, Span, Spanned, SyntaxContext, DUMMY_SP};
 use swc_core::ecma::ast::*;
 use swc_core::ecma::atoms::{js_word, JsWord};
 use swc_core::ecma::parser::lexer::Lexer;
 use swc_core::ecma::parser::{error::Error, Parser, StringInput};
-use swc_core::ecma::visit::{Fold, FoldWith};
+use swc_core::ecma::visit::{Fold, FoldWith, Visit, VisitMut, VisitMutWith, VisitWith};
 
+mod eval;
+mod globals;
 #[cfg(feature = "napi")]
 pub mod napi;
+mod value;
 
-#[derive(PartialEq)]
+pub use eval::eval_expr;
+use eval::{iterate, unwrap_expr, Evaluator};
+use value::js_number_to_string;
+pub use value::ConversionError;
+
+#[derive(Debug, PartialEq)]
 pub enum MacroError {
-  /// Could not statically evaluate macro argument.
-  EvaluationError(Span),
+  /// Could not statically evaluate macro argument, with an optional hint for how to fix it.
+  EvaluationError(Span, Option<String>),
+  /// A constant used in a macro argument was mutated. Contains the location of
+  /// the mutation, the location where the constant is used by the macro, and
+  /// a description of the mutation if known (e.g. a mutating method call).
+  MutationError(Span, Span, Option<String>),
   /// An error occurred loading a macro (e.g. resolution or syntax error).
   LoadError(String, Span),
+  /// A property accessed in a macro argument does not exist on a constant object.
+  /// Contains the name of the property.
+  MissingProperty(String, Span),
   /// An error was thrown when executing a macro.
   ExecutionError(String, Span),
+  /// A non-fatal error was reported when executing a macro. The original call is left in place.
+  ExecutionWarning(String, Span),
   /// Could not parse the result of a function returned by a macro.
   ParseError(Error),
+  /// The value a macro argument evaluated to, formatted as JavaScript. This is not an error,
+  /// and is only reported when `debug_arguments` is enabled.
+  ArgumentValue(String, Span),
+}
+
+impl Spanned for MacroError {
+  fn span(&self) -> Span {
+    match self {
+      MacroError::EvaluationError(span, _)
+      | MacroError::MutationError(span, ..)
+      | MacroError::LoadError(_, span)
+      | MacroError::MissingProperty(_, span)
+      | MacroError::ExecutionError(_, span)
+      | MacroError::ExecutionWarning(_, span)
+      | MacroError::ArgumentValue(_, span) => *span,
+      MacroError::ParseError(err) => err.span(),
+    }
+  }
 }
 
 #[derive(serde::Serialize)]
@@ -31,19 +69,111 @@ pub struct Location {
   pub col: u32,
 }
 
-pub type MacroCallback =
-  Arc<dyn Fn(String, String, Vec<JsValue>, Location) -> Result<JsValue, MacroError> + Send + Sync>;
+/// The documentation URL attached to macro diagnostics by default.
+pub const DOCUMENTATION_URL: &str = "https://parceljs.org/features/macros/";
+
+/// The default maximum length, in bytes, of strings produced when evaluating macro arguments.
+pub const DEFAULT_MAX_STRING_LENGTH: usize = 4 * 1024 * 1024;
+
+/// The default maximum number of elements in arrays created by `new Array(len)` when
+/// evaluating macro arguments.
+pub const DEFAULT_MAX_ARRAY_LENGTH: usize = 1024 * 1024;
+
+/// Calls a macro, given the import specifier, exported name, evaluated arguments, and location
+/// of the call. Macros used as tagged templates, e.g. css`...`, receive an array of the template
+/// strings followed by the value of each interpolation, like a JS tag function. Trailing
+/// undefined arguments are omitted.
+pub type MacroCallback = Arc<
+  dyn Fn(String, String, Vec<JsValue>, Location) -> Result<MacroOutput, MacroError> + Send + Sync,
+>;
+
+/// The result of a successful macro call: the value to replace the call with, and any
+/// diagnostics reported by the macro, e.g. deprecation warnings. Diagnostics without a
+/// location are reported at the call.
+#[derive(Debug, PartialEq)]
+pub struct MacroOutput {
+  pub value: JsValue,
+  pub diagnostics: Vec<MacroError>,
+}
+
+impl From<JsValue> for MacroOutput {
+  fn from(value: JsValue) -> Self {
+    MacroOutput {
+      value,
+      diagnostics: Vec::new(),
+    }
+  }
+}
+
+/// Counters describing the work done by a [`Macros`] pass, e.g. for build profiling.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MacroStats {
+  /// The number of macro calls that were replaced with their result.
+  pub expanded_macros: usize,
+  /// The number of macro arguments that were statically evaluated.
+  pub folded_args: usize,
+  /// The number of expression evaluations answered from the cache.
+  pub eval_cache_hits: usize,
+}
+
+/// Resolves the value of a constant exported from another module, given the
+/// import specifier and the exported name. Returns None if it is not a constant.
+pub type ConstantResolver = Arc<dyn Fn(&str, &str) -> Option<JsValue> + Send + Sync>;
 
 pub struct Macros<'a> {
   /// Mapping of imported identifiers to import metadata.
   macros: HashMap<Id, MacroImport>,
   constants: HashMap<Id, Result<JsValue, Span>>,
+  /// Cache of evaluated expressions, keyed by span. Cleared whenever constants change.
+  eval_cache: RefCell<HashMap<Span, Result<JsValue, Span>>>,
+  /// Spans of nodes in functions returned by macros, which are all remapped to the macro call.
+  shared_spans: RefCell<HashSet<Span>>,
+  /// Bindings declared with `let` or `var` that cannot be treated as constants.
+  non_const_bindings: HashSet<Id>,
+  /// Bindings that are written to after their declaration.
+  reassigned_bindings: HashSet<Id>,
+  /// Constants declared with `as const`, which are assumed not to be mutated when
+  /// passed to other functions.
+  frozen_constants: HashSet<Id>,
+  /// Descriptions of known mutations of constants, keyed by the span of the mutation.
+  mutation_descriptions: HashMap<Span, String>,
   callback: MacroCallback,
+  constant_resolver: Option<ConstantResolver>,
+  /// Mapping of non-macro imported identifiers to their specifier and exported name.
+  imports: HashMap<Id, (JsWord, JsWord)>,
+  /// The syntax context of unresolved references, used to identify globals.
+  unresolved_ctxt: Option<SyntaxContext>,
+  /// The value of `Date.now()` in macro arguments, in milliseconds since the epoch.
+  build_time: Option<f64>,
+  /// Environment variables that `process.env.KEY` and `import.meta.env.KEY` evaluate to
+  /// in macro arguments.
+  env: Option<HashMap<String, JsValue>>,
+  /// Whether environment variables missing from `env` evaluate to undefined rather than an error.
+  missing_env_undefined: bool,
+  /// The result of `typeof` for globals in the target environment.
+  global_types: Option<HashMap<String, String>>,
+  /// The maximum length of strings produced when evaluating macro arguments.
+  max_string_length: usize,
+  /// Spans of expressions that produced strings longer than `max_string_length`.
+  oversized_strings: RefCell<HashSet<Span>>,
+  /// The maximum number of elements in arrays created by `new Array(len)` in macro arguments.
+  max_array_length: usize,
+  /// Spans of expressions that created arrays longer than `max_array_length`.
+  oversized_arrays: RefCell<HashSet<Span>>,
+  /// Patterns of import specifiers that macros may be imported from. All are allowed if None.
+  allowed_sources: Option<Vec<String>>,
+  /// Whether to report the values of evaluated macro arguments.
+  debug_arguments: bool,
+  /// Counters for [`Macros::stats`]. Cache hits are counted separately since evaluation
+  /// only has shared access.
+  stats: MacroStats,
+  eval_cache_hits: Cell<usize>,
   source_map: &'a SourceMap,
   errors: &'a mut Vec<MacroError>,
   load_errors: HashSet<String>,
   assignment_span: Option<Span>,
   in_call: bool,
+  documentation_url: String,
 }
 
 struct MacroImport {
@@ -55,25 +185,203 @@ struct MacroImport {
   span: Span,
 }
 
-impl<'a> Macros<'a> {
-  pub fn new(
-    callback: MacroCallback,
-    source_map: &'a SourceMap,
-    errors: &'a mut Vec<MacroError>,
-  ) -> Self {
+/// Configures and creates a `Macros` pass.
+pub struct MacrosBuilder<'a> {
+  callback: MacroCallback,
+  source_map: &'a SourceMap,
+  constant_resolver: Option<ConstantResolver>,
+  unresolved_mark: Option<Mark>,
+  build_time: Option<f64>,
+  env: Option<HashMap<String, JsValue>>,
+  missing_env_undefined: bool,
+  global_types: Option<HashMap<String, String>>,
+  max_string_length: usize,
+  max_array_length: usize,
+  allowed_sources: Option<Vec<String>>,
+  debug_arguments: bool,
+  documentation_url: String,
+}
+
+impl<'a> MacrosBuilder<'a> {
+  pub fn new(callback: MacroCallback, source_map: &'a SourceMap) -> Self {
+    MacrosBuilder {
+      callback,
+      source_map,
+      constant_resolver: None,
+      unresolved_mark: None,
+      build_time: None,
+      env: None,
+      missing_env_undefined: false,
+      global_types: None,
+      max_string_length: DEFAULT_MAX_STRING_LENGTH,
+      max_array_length: DEFAULT_MAX_ARRAY_LENGTH,
+      allowed_sources: None,
+      debug_arguments: false,
+      documentation_url: DOCUMENTATION_URL.into(),
+    }
+  }
+
+  /// Overrides the documentation URL linked from macro diagnostics.
+  pub fn documentation_url(mut self, url: impl Into<String>) -> Self {
+    self.documentation_url = url.into();
+    self
+  }
+
+  /// Sets the mark the resolver applied to unresolved references, so that global
+  /// functions (e.g. `encodeURIComponent`) are not confused with local bindings.
+  pub fn unresolved_mark(mut self, mark: Mark) -> Self {
+    self.unresolved_mark = Some(mark);
+    self
+  }
+
+  /// Sets the time, in milliseconds since the epoch, that `Date.now()` and `new Date()`
+  /// evaluate to in macro arguments. These cannot be evaluated otherwise, so that builds are
+  /// reproducible by default.
+  pub fn build_time(mut self, build_time: f64) -> Self {
+    self.build_time = Some(build_time);
+    self
+  }
+
+  /// Sets the environment variables that `process.env.KEY` and `import.meta.env.KEY` evaluate
+  /// to in macro arguments. This only affects evaluation, and does not replace them in the output.
+  pub fn env(mut self, env: HashMap<String, JsValue>) -> Self {
+    self.env = Some(env);
+    self
+  }
+
+  /// Evaluates environment variables that are not set to undefined, as at runtime,
+  /// rather than reporting an error.
+  pub fn missing_env_undefined(mut self, missing_env_undefined: bool) -> Self {
+    self.missing_env_undefined = missing_env_undefined;
+    self
+  }
+
+  /// Sets the result of `typeof` for globals in the target environment, e.g. `"object"` for
+  /// `window` in browsers, or `"undefined"` elsewhere. This allows feature detection such as
+  /// `typeof window !== "undefined"` to be evaluated in macro arguments.
+  pub fn global_types(mut self, global_types: HashMap<String, String>) -> Self {
+    self.global_types = Some(global_types);
+    self
+  }
+
+  /// Sets the maximum length, in bytes, of strings produced when evaluating macro arguments,
+  /// so that pathological inputs such as `"x".repeat(1e9)` cannot hang the build.
+  pub fn max_string_length(mut self, max_string_length: usize) -> Self {
+    self.max_string_length = max_string_length;
+    self
+  }
+
+  /// Sets the maximum number of elements in arrays created by `new Array(len)` when evaluating
+  /// macro arguments, so that inputs such as `new Array(1e9)` cannot exhaust memory.
+  pub fn max_array_length(mut self, max_array_length: usize) -> Self {
+    self.max_array_length = max_array_length;
+    self
+  }
+
+  /// Restricts the import specifiers that macros may be imported from, so that untrusted
+  /// dependencies cannot run code at build time. Patterns match exactly, except that `*`
+  /// matches any sequence of characters, e.g. `@company/*`.
+  pub fn allowed_sources(mut self, allowed_sources: Vec<String>) -> Self {
+    self.allowed_sources = Some(allowed_sources);
+    self
+  }
+
+  /// Reports the value each macro argument evaluated to as an `ArgumentValue` diagnostic,
+  /// to help debug why an argument was or was not folded. Disabled by default.
+  pub fn debug_arguments(mut self, debug_arguments: bool) -> Self {
+    self.debug_arguments = debug_arguments;
+    self
+  }
+
+  /// Allows constants imported from other modules to be used in macro arguments.
+  pub fn constant_resolver(mut self, resolver: ConstantResolver) -> Self {
+    self.constant_resolver = Some(resolver);
+    self
+  }
+
+  /// Creates the pass. Errors are pushed to `errors` while folding.
+  pub fn build(self, errors: &'a mut Vec<MacroError>) -> Macros<'a> {
     Macros {
       macros: HashMap::new(),
       constants: HashMap::new(),
+      eval_cache: RefCell::new(HashMap::new()),
+      shared_spans: RefCell::new(HashSet::new()),
+      non_const_bindings: HashSet::new(),
+      reassigned_bindings: HashSet::new(),
+      frozen_constants: HashSet::new(),
+      mutation_descriptions: HashMap::new(),
       load_errors: HashSet::new(),
-      callback,
-      source_map,
+      callback: self.callback,
+      constant_resolver: self.constant_resolver,
+      imports: HashMap::new(),
+      unresolved_ctxt: self
+        .unresolved_mark
+        .map(|mark| SyntaxContext::empty().apply_mark(mark)),
+      build_time: self.build_time,
+      env: self.env,
+      missing_env_undefined: self.missing_env_undefined,
+      global_types: self.global_types,
+      max_string_length: self.max_string_length,
+      max_array_length: self.max_array_length,
+      allowed_sources: self.allowed_sources,
+      debug_arguments: self.debug_arguments,
+      oversized_strings: RefCell::new(HashSet::new()),
+      oversized_arrays: RefCell::new(HashSet::new()),
+      stats: MacroStats::default(),
+      eval_cache_hits: Cell::new(0),
+      source_map: self.source_map,
       errors,
       assignment_span: None,
       in_call: false,
+      documentation_url: self.documentation_url,
+    }
+  }
+}
+
+impl<'a> Macros<'a> {
+  /// Creates a pass with the default configuration. Use `MacrosBuilder` to configure it.
+  pub fn new(
+    callback: MacroCallback,
+    source_map: &'a SourceMap,
+    errors: &'a mut Vec<MacroError>,
+  ) -> Self {
+    MacrosBuilder::new(callback, source_map).build(errors)
+  }
+
+  /// The documentation URL to link from diagnostics produced by this pass.
+  pub fn documentation_url(&self) -> &str {
+    &self.documentation_url
+  }
+
+  /// Counters describing the macros expanded and arguments evaluated so far.
+  pub fn stats(&self) -> MacroStats {
+    MacroStats {
+      eval_cache_hits: self.eval_cache_hits.get(),
+      ..self.stats
+    }
+  }
+
+  /// Whether macros may be imported from the given specifier. If not, reports an error.
+  fn check_allowed_source(&mut self, src: &str, span: Span) -> bool {
+    let allowed = match &self.allowed_sources {
+      Some(patterns) => patterns.iter().any(|pattern| matches_pattern(pattern, src)),
+      None => true,
+    };
+    if !allowed {
+      push_error(
+        self.errors,
+        MacroError::LoadError(format!("`{}` is not an allowed macro source", src), span),
+      );
     }
+    allowed
   }
 
   fn add_macro(&mut self, import: &ImportDecl) {
+    // The import is still removed, since macro imports cannot be loaded at runtime either.
+    if !self.check_allowed_source(&import.src.value, import.src.span) {
+      return;
+    }
+
     for specifier in &import.specifiers {
       match specifier {
         ImportSpecifier::Named(named) => {
@@ -115,55 +423,297 @@ impl<'a> Macros<'a> {
     }
   }
 
+  /// Registers macros destructured from a dynamic import at the top level of a module,
+  /// e.g. `const { fn } = await import("x", { with: { type: "macro" } })`. Returns false
+  /// if the declaration is not in this form.
+  fn add_dynamic_macro(&mut self, var: &VarDecl) -> bool {
+    let decl = match &var.decls[..] {
+      [decl] if var.kind == VarDeclKind::Const => decl,
+      _ => return false,
+    };
+    let src = match decl.init.as_deref().map(unwrap_expr) {
+      Some(Expr::Await(AwaitExpr { arg, .. })) => match dynamic_macro_import(arg) {
+        Some(Some(src)) => src,
+        _ => return false,
+      },
+      _ => return false,
+    };
+    let pat = match &decl.name {
+      Pat::Object(pat) => pat,
+      _ => return false,
+    };
+    if !self.check_allowed_source(&src, var.span) {
+      return true;
+    }
+
+    let mut specifiers = Vec::with_capacity(pat.props.len());
+    for prop in &pat.props {
+      match prop {
+        ObjectPatProp::KeyValue(KeyValuePatProp { key, value }) => match (key, &**value) {
+          (
+            PropName::Ident(Ident { sym, .. }) | PropName::Str(Str { value: sym, .. }),
+            Pat::Ident(local),
+          ) => specifiers.push((local.to_id(), sym.clone())),
+          _ => return false,
+        },
+        ObjectPatProp::Assign(AssignPatProp {
+          key, value: None, ..
+        }) => specifiers.push((key.to_id(), key.sym.clone())),
+        _ => return false,
+      }
+    }
+
+    for (local, imported) in specifiers {
+      self.macros.insert(
+        local,
+        MacroImport {
+          src: src.clone(),
+          imported: Some(imported),
+          span: var.span,
+        },
+      );
+    }
+    true
+  }
+
+  fn add_import(&mut self, import: &ImportDecl) {
+    for specifier in &import.specifiers {
+      match specifier {
+        ImportSpecifier::Named(named) => {
+          let imported = match &named.imported {
+            Some(ModuleExportName::Ident(id)) => id.sym.clone(),
+            Some(ModuleExportName::Str(s)) => s.value.clone(),
+            None => named.local.sym.clone(),
+          };
+          self
+            .imports
+            .insert(named.local.to_id(), (import.src.value.clone(), imported));
+        }
+        ImportSpecifier::Default(default) => {
+          self.imports.insert(
+            default.local.to_id(),
+            (import.src.value.clone(), js_word!("default")),
+          );
+        }
+        ImportSpecifier::Namespace(_) => {}
+      }
+    }
+  }
+
+  /// Resolves an imported constant the first time it is referenced, and stores it
+  /// alongside local constants so it is subject to the same mutation checks.
+  fn resolve_import(&mut self, id: &Ident) {
+    let id = id.to_id();
+    if let (Some(resolver), Some((src, imported))) =
+      (&self.constant_resolver, self.imports.remove(&id))
+    {
+      if let Some(value) = resolver(&src, &imported) {
+        self.set_constant(id, Ok(value));
+      }
+    }
+  }
+
+  /// If the expression refers to a macro, e.g. `macro` or `ns.macro`, returns its
+  /// import specifier, exported name, and the location of the import.
+  fn get_macro(&self, expr: &Expr) -> Option<(String, String, Span)> {
+    match expr {
+      Expr::Ident(ident) => {
+        let specifier = self.macros.get(&ident.to_id())?;
+        let imported = specifier.imported.as_ref()?;
+        Some((
+          specifier.src.to_string(),
+          imported.to_string(),
+          specifier.span,
+        ))
+      }
+      Expr::Member(member) => {
+        // e.g. ns.macro()
+        let ident = match &*member.obj {
+          Expr::Ident(ident) => ident,
+          _ => return None,
+        };
+
+        // Check that this is a namespace import.
+        let specifier = self.macros.get(&ident.to_id())?;
+        if specifier.imported.is_some() {
+          return None;
+        }
+
+        let imported = match &member.prop {
+          MemberProp::Ident(id) => id.sym.to_string(),
+          MemberProp::Computed(s) => match self.eval(&s.expr) {
+            Ok(JsValue::String(s)) => s,
+            _ => return None,
+          },
+          MemberProp::PrivateName(_) => return None,
+        };
+        Some((specifier.src.to_string(), imported, specifier.span))
+      }
+      _ => None,
+    }
+  }
+
+  /// Evaluates the arguments and calls a macro, returning the expression to replace the call
+  /// with. Returns None if the original expression should be kept.
   fn call_macro(
     &mut self,
     src: String,
     export: String,
-    call: CallExpr,
+    span: Span,
+    call_args: &[ExprOrSpread],
     import_span: Span,
-  ) -> Result<Expr, MacroError> {
+  ) -> Result<Option<Expr>, MacroError> {
     // If a macro already errorered during loading, don't try calling it again.
     if self.load_errors.contains(&src) {
-      return Ok(Expr::Lit(Lit::Null(Null::dummy())));
+      return Ok(Some(Expr::Lit(Lit::Null(Null::dummy()))));
     }
 
     // Try to statically evaluate all of the function arguments.
-    let mut args = Vec::with_capacity(call.args.len());
-    for arg in &call.args {
+    let mut args = Vec::with_capacity(call_args.len());
+    for arg in call_args {
       match self.eval(&*arg.expr) {
+        // Functions, e.g. object methods, cannot be passed to the macro.
+        Ok(val) if val.contains_function() => {
+          return Err(MacroError::EvaluationError(
+            arg.expr.span(),
+            Some("Functions cannot be passed as macro arguments.".into()),
+          ));
+        }
         Ok(val) => {
+          self.stats.folded_args += 1;
+          if self.debug_arguments {
+            let value = MacroError::ArgumentValue(val.to_string(), arg.expr.span());
+            push_error(self.errors, value);
+          }
           if arg.spread.is_none() {
             args.push(val);
-          } else if let JsValue::Array(val) = val {
+          } else if let Some(val) = iterate(val) {
             args.extend(val);
           } else {
-            return Err(MacroError::EvaluationError(call.span));
+            return Err(MacroError::EvaluationError(
+              arg.expr.span(),
+              Some(
+                "Only arrays, strings, maps, and sets can be spread into macro arguments.".into(),
+              ),
+            ));
           }
         }
         Err(span) => {
-          return Err(MacroError::EvaluationError(span));
+          if let Some(usage) = self.find_mutated_constant(&arg.expr, span) {
+            let description = self.mutation_descriptions.get(&span).cloned();
+            return Err(MacroError::MutationError(span, usage, description));
+          }
+
+          if let Some(prop) = self.find_missing_property(&arg.expr, span) {
+            return Err(MacroError::MissingProperty(prop, span));
+          }
+
+          if self.oversized_strings.borrow().contains(&span) {
+            return Err(MacroError::EvaluationError(
+              span,
+              Some(format!(
+                "Strings in macro arguments are limited to {} bytes.",
+                self.max_string_length
+              )),
+            ));
+          }
+
+          if self.oversized_arrays.borrow().contains(&span) {
+            return Err(MacroError::EvaluationError(
+              span,
+              Some(format!(
+                "Arrays in macro arguments are limited to {} elements.",
+                self.max_array_length
+              )),
+            ));
+          }
+
+          return Err(MacroError::EvaluationError(
+            span,
+            eval_hint(&arg.expr, span, &self.non_const_bindings, &|id| {
+              self.evaluator().is_global(id)
+            }),
+          ));
         }
       }
     }
 
+    // Like in JS, omitted arguments are undefined. Trailing undefined arguments, including
+    // holes in spread arrays, are dropped so they cannot be distinguished from omitted ones.
+    while matches!(args.last(), Some(JsValue::Undefined)) {
+      args.pop();
+    }
+
     // If that was successful, call the function callback (on the JS thread).
-    let loc = self.source_map.lookup_char_pos(call.span.lo);
+    let loc = self.source_map.lookup_char_pos(span.lo);
     let loc = Location {
       line: loc.line as u32,
       col: loc.col_display as u32,
     };
     match (self.callback)(src.clone(), export, args, loc) {
-      Ok(val) => Ok(self.value_to_expr(val)?),
+      Ok(output) => {
+        for diagnostic in output.diagnostics {
+          let diagnostic = match diagnostic {
+            MacroError::ExecutionError(message, s) if s.is_dummy() => {
+              MacroError::ExecutionError(message, span)
+            }
+            MacroError::ExecutionWarning(message, s) if s.is_dummy() => {
+              MacroError::ExecutionWarning(message, span)
+            }
+            diagnostic => diagnostic,
+          };
+          push_error(self.errors, diagnostic);
+        }
+        let expr = self.value_to_expr(output.value, span)?;
+        self.stats.expanded_macros += 1;
+        Ok(Some(expr))
+      }
       Err(err) => match err {
         MacroError::LoadError(err, _) => {
           self.load_errors.insert(src);
           Err(MacroError::LoadError(err, import_span))
         }
-        MacroError::ExecutionError(err, _) => Err(MacroError::ExecutionError(err, call.span)),
+        MacroError::ExecutionError(err, _) => Err(MacroError::ExecutionError(err, span)),
+        MacroError::ExecutionWarning(err, _) => {
+          // Warnings are non-fatal, so keep the original call rather than replacing it.
+          push_error(self.errors, MacroError::ExecutionWarning(err, span));
+          Ok(None)
+        }
         err => Err(err),
       },
     }
   }
+
+  /// If evaluation failed because a constant referenced by the expression was mutated
+  /// at the given span, returns the location of the reference.
+  fn find_mutated_constant(&self, expr: &Expr, span: Span) -> Option<Span> {
+    let mut finder = FindMutatedConstant {
+      constants: &self.constants,
+      span,
+      found: None,
+    };
+    expr.visit_with(&mut finder);
+    finder.found
+  }
+
+  /// If evaluation failed at the given span because a property does not exist on a constant
+  /// object, returns the name of the property. Other values, e.g. arrays, support few
+  /// properties, so an error there does not mean the property does not exist.
+  fn find_missing_property(&self, expr: &Expr, span: Span) -> Option<String> {
+    let mut finder = FindMember { span, found: None };
+    expr.visit_with(&mut finder);
+    let member = finder.found?;
+    let obj = self.eval_cow(&member.obj).ok()?;
+    let prop = match &member.prop {
+      MemberProp::Ident(id) => id.sym.to_string(),
+      MemberProp::Computed(prop) => self.eval(&prop.expr).ok()?.to_js_string(),
+      _ => return None,
+    };
+    match &*obj {
+      JsValue::Object(obj) if !obj.contains_key(&prop) => Some(prop),
+      _ => None,
+    }
+  }
 }
 
 impl<'a> Fold for Macros<'a> {
@@ -175,71 +725,111 @@ impl<'a> Fold for Macros<'a> {
           if matches!(&import.with, Some(with) if is_macro(with)) {
             self.add_macro(import);
             return false;
+          } else if self.constant_resolver.is_some() && !import.type_only {
+            self.add_import(import);
           }
         }
+      } else if let ModuleItem::Stmt(Stmt::Decl(Decl::Var(var))) = &item {
+        if self.add_dynamic_macro(var) {
+          return false;
+        }
       }
 
       true
     });
 
+    // Other dynamic imports of macros cannot be statically analyzed.
+    let mut dynamic_imports = FindDynamicMacroImports::default();
+    node.visit_with(&mut dynamic_imports);
+    for span in dynamic_imports.spans {
+      push_error(
+        self.errors,
+        MacroError::LoadError(
+          "Macros can only be imported dynamically with `const { fn } = await import(...)` at the top level of a module.".into(),
+          span,
+        ),
+      );
+    }
+
     // Only process the rest of the AST if we found any macro imports.
     if !self.macros.is_empty() {
+      // Find let/var bindings that are reassigned so the rest can be treated as constants.
+      let mut reassigned = ReassignedBindings::default();
+      node.visit_with(&mut reassigned);
+      if reassigned.has_eval {
+        self.reassigned_bindings = reassigned.declared;
+      } else {
+        self.reassigned_bindings = reassigned.reassigned;
+      }
+
       node = node.fold_children_with(self);
+
+      // Errors are pushed in traversal order (e.g. nested calls first), so sort them
+      // by location to keep the output deterministic.
+      self.errors.sort_by_key(|err| err.span().lo);
     }
 
     node
   }
 
   fn fold_expr(&mut self, node: Expr) -> Expr {
+    if let Expr::TaggedTpl(tagged) = node {
+      // e.g. css`...`
+      let (src, imported, import_span) = match self.get_macro(&tagged.tag) {
+        Some(m) => m,
+        None => return Expr::TaggedTpl(tagged).fold_children_with(self),
+      };
+
+      let in_call = std::mem::take(&mut self.in_call);
+      let tagged = tagged.fold_with(self);
+      self.in_call = in_call;
+      let args = tagged_template_args(&tagged.tpl);
+      let result = self.call_macro(src, imported, tagged.span, &args, import_span);
+      return handle_error(
+        result.map(|expr| expr.unwrap_or(Expr::TaggedTpl(tagged))),
+        &mut self.errors,
+      );
+    }
+
     if let Expr::Call(call) = node {
       if let Callee::Expr(expr) = &call.callee {
-        match &**expr {
-          Expr::Ident(ident) => {
-            if let Some(specifier) = self.macros.get(&ident.to_id()) {
-              if let Some(imported) = &specifier.imported {
-                let src = specifier.src.to_string();
-                let imported = imported.to_string();
-                let span = specifier.span;
-                let in_call = std::mem::take(&mut self.in_call);
-                let call = call.fold_with(self);
-                self.in_call = in_call;
-                return handle_error(self.call_macro(src, imported, call, span), &mut self.errors);
-              }
-            }
-          }
-          Expr::Member(member) => 'block: {
-            // e.g. ns.macro()
-            if let Expr::Ident(ident) = &*member.obj {
-              // Check that this is a namespace import.
-              if let Some(specifier @ MacroImport { imported: None, .. }) =
-                self.macros.get(&ident.to_id())
-              {
-                let imported = match &member.prop {
-                  MemberProp::Ident(id) => id.sym.to_string(),
-                  MemberProp::Computed(s) => {
-                    if let Ok(JsValue::String(s)) = self.eval(&s.expr) {
-                      s
-                    } else {
-                      break 'block;
-                    }
-                  }
-                  MemberProp::PrivateName(_) => break 'block,
-                };
+        if let Some((src, imported, import_span)) = self.get_macro(expr) {
+          let in_call = std::mem::take(&mut self.in_call);
+          let call = call.fold_with(self);
+          self.in_call = in_call;
+          let result = self.call_macro(src, imported, call.span, &call.args, import_span);
+          return handle_error(
+            result.map(|expr| expr.unwrap_or(Expr::Call(call))),
+            &mut self.errors,
+          );
+        }
+      }
 
-                let src = specifier.src.to_string();
-                let span = specifier.span;
-                let in_call = std::mem::take(&mut self.in_call);
-                let call = call.fold_with(self);
-                self.in_call = in_call;
-                return handle_error(self.call_macro(src, imported, call, span), &mut self.errors);
+      // Not a macro. If this calls a mutating method on a constant array, mark it
+      // as mutated with a more specific description, e.g. `arr.push(x)`.
+      if let Callee::Expr(expr) = &call.callee {
+        if let Expr::Member(MemberExpr {
+          obj,
+          prop: MemberProp::Ident(method),
+          ..
+        }) = &**expr
+        {
+          if let Expr::Ident(id) = unwrap_expr(obj) {
+            if MUTATING_ARRAY_METHODS.contains(&&*method.sym) {
+              if let Some(constant @ Ok(JsValue::Array(..))) = self.constants.get_mut(&id.to_id()) {
+                *constant = Err(call.span);
+                self.eval_cache.get_mut().clear();
+                self.mutation_descriptions.insert(
+                  call.span,
+                  format!("constant array mutated via `{}`", method.sym),
+                );
               }
             }
           }
-          _ => {}
         }
       }
 
-      // Not a macro. Track if we're in a call so we can error if constant
+      // Track if we're in a call so we can error if constant
       // objects are referenced that might be mutated.
       self.in_call = true;
       let call = call.fold_with(self);
@@ -253,18 +843,93 @@ impl<'a> Fold for Macros<'a> {
   fn fold_var_decl(&mut self, mut node: VarDecl) -> VarDecl {
     node = node.fold_children_with(self);
 
-    if node.kind == VarDeclKind::Const {
-      for decl in &node.decls {
-        if let Some(expr) = &decl.init {
+    for decl in &node.decls {
+      // let and var bindings that are never reassigned are treated the same as const.
+      let mut ids = HashSet::new();
+      if node.kind != VarDeclKind::Const {
+        collect_binding_ids(&decl.name, &mut ids);
+      }
+
+      match &decl.init {
+        Some(expr) if ids.is_disjoint(&self.reassigned_bindings) => {
+          if is_const_assertion(expr) {
+            collect_binding_ids(&decl.name, &mut self.frozen_constants);
+          }
           let val = self.eval(&*expr);
           self.eval_pat(val, &decl.name);
         }
+        _ => self.non_const_bindings.extend(ids),
+      }
+    }
+
+    node
+  }
+
+  /// Enums are constant objects mapping member names to their values, so members such as
+  /// `Color.Red` can be used in macro arguments. Reverse mappings of numeric members, e.g.
+  /// `Direction[0]`, are not included.
+  fn fold_ts_enum_decl(&mut self, node: TsEnumDecl) -> TsEnumDecl {
+    let node = node.fold_children_with(self);
+    if node.declare {
+      return node;
+    }
+
+    // Declarations with the same name are merged into one enum, which is not supported.
+    let id = node.id.to_id();
+    if let Some(constant) = self.constants.get_mut(&id) {
+      *constant = Err(node.span);
+      self.eval_cache.get_mut().clear();
+      return node;
+    }
+
+    // Members are added as they are evaluated, so later initializers can reference
+    // earlier members, e.g. `AB = Flags.A | Flags.B`.
+    self.set_constant(id.clone(), Ok(JsValue::Object(IndexMap::new())));
+    // Members without an initializer increment the previous numeric value.
+    let mut next = Some(0.0);
+    for member in &node.members {
+      let name = match &member.id {
+        TsEnumMemberId::Ident(ident) => ident.sym.to_string(),
+        TsEnumMemberId::Str(s) => s.value.to_string(),
+      };
+      let value = match &member.init {
+        Some(init) => self.eval(init),
+        None => next.map(JsValue::Number).ok_or(member.span),
+      };
+      next = match &value {
+        Ok(JsValue::Number(n)) => Some(n + 1.0),
+        _ => None,
+      };
+      match (value, self.constants.get_mut(&id)) {
+        (Ok(value @ (JsValue::Number(_) | JsValue::String(_))), Some(Ok(JsValue::Object(obj)))) => {
+          obj.insert(name, value);
+        }
+        (Ok(_), _) => {
+          self.set_constant(id, Err(member.span));
+          break;
+        }
+        (Err(span), _) => {
+          self.set_constant(id, Err(span));
+          break;
+        }
       }
     }
 
     node
   }
 
+  fn fold_unary_expr(&mut self, mut node: UnaryExpr) -> UnaryExpr {
+    if node.op != UnaryOp::Delete {
+      return node.fold_children_with(self);
+    }
+
+    // Deleting a property mutates the object, the same as assigning to it.
+    self.assignment_span = Some(node.span);
+    node.arg = node.arg.fold_with(self);
+    self.assignment_span = None;
+    node
+  }
+
   fn fold_assign_expr(&mut self, mut node: AssignExpr) -> AssignExpr {
     self.assignment_span = Some(node.span.clone());
     node.left = node.left.fold_with(self);
@@ -278,10 +943,11 @@ impl<'a> Fold for Macros<'a> {
     if let Some(assignment_span) = self.assignment_span {
       // Error when re-assigning a property of a constant that's used in a macro.
       let node = node.fold_children_with(self);
-      if let Expr::Ident(id) = &*node.obj {
+      if let Expr::Ident(id) = unwrap_expr(&node.obj) {
         if let Some(constant) = self.constants.get_mut(&id.to_id()) {
           if constant.is_ok() {
             *constant = Err(assignment_span.clone());
+            self.eval_cache.get_mut().clear();
           }
         }
       }
@@ -292,10 +958,10 @@ impl<'a> Fold for Macros<'a> {
       // If the member expression evaluates to an object, continue traversing so we error in fold_ident.
       // Otherwise, return early to allow other properties to be accessed without error.
       let value = self
-        .eval(&*node.obj)
-        .and_then(|obj| self.eval_member_prop(obj, &node));
+        .eval_cow(&node.obj)
+        .and_then(|obj| self.eval_member_cow(obj, &node));
       if !matches!(
-        value,
+        value.as_deref(),
         Err(..) | Ok(JsValue::Object(..) | JsValue::Array(..))
       ) {
         return node;
@@ -306,11 +972,16 @@ impl<'a> Fold for Macros<'a> {
   }
 
   fn fold_ident(&mut self, node: Ident) -> Ident {
-    if self.in_call {
+    if !self.imports.is_empty() {
+      self.resolve_import(&node);
+    }
+
+    if self.in_call && !self.frozen_constants.contains(&node.to_id()) {
       if let Some(constant) = self.constants.get_mut(&node.to_id()) {
         if matches!(constant, Ok(JsValue::Object(..) | JsValue::Array(..))) {
           // Mark access to constant object inside a call as an error since it could potentially be mutated.
           *constant = Err(node.span.clone());
+          self.eval_cache.get_mut().clear();
         }
       }
     }
@@ -319,6 +990,71 @@ impl<'a> Fold for Macros<'a> {
   }
 }
 
+/// Array methods that mutate the array they are called on.
+const MUTATING_ARRAY_METHODS: &[&str] = &[
+  "copyWithin",
+  "fill",
+  "pop",
+  "push",
+  "reverse",
+  "shift",
+  "sort",
+  "splice",
+  "unshift",
+];
+
+/// Checks if an expression has an `as const` assertion, possibly wrapped in other type assertions.
+fn is_const_assertion(mut expr: &Expr) -> bool {
+  loop {
+    expr = match expr {
+      Expr::TsConstAssertion(_) => return true,
+      Expr::Paren(ParenExpr { expr, .. })
+      | Expr::TsAs(TsAsExpr { expr, .. })
+      | Expr::TsNonNull(TsNonNullExpr { expr, .. })
+      | Expr::TsTypeAssertion(TsTypeAssertion { expr, .. })
+      | Expr::TsSatisfies(TsSatisfiesExpr { expr, .. }) => expr,
+      _ => return false,
+    }
+  }
+}
+
+/// Returns the arguments a macro called as a tagged template receives, as if it were a
+/// JS tag function: an array of the cooked template strings, followed by the values of
+/// each interpolation. Strings with invalid escape sequences are undefined.
+fn tagged_template_args(tpl: &Tpl) -> Vec<ExprOrSpread> {
+  let strings = tpl
+    .quasis
+    .iter()
+    .map(|quasi| {
+      let expr = match &quasi.cooked {
+        Some(cooked) => Expr::Lit(Lit::Str(Str {
+          span: quasi.span,
+          value: cooked.as_str().into(),
+          raw: None,
+        })),
+        None => Expr::Ident(Ident::new(js_word!("undefined"), quasi.span)),
+      };
+      Some(ExprOrSpread {
+        spread: None,
+        expr: Box::new(expr),
+      })
+    })
+    .collect();
+
+  let mut args = vec![ExprOrSpread {
+    spread: None,
+    expr: Box::new(Expr::Array(ArrayLit {
+      span: tpl.span,
+      elems: strings,
+    })),
+  }];
+  args.extend(tpl.exprs.iter().map(|expr| ExprOrSpread {
+    spread: None,
+    expr: expr.clone(),
+  }));
+  args
+}
+
 /// Checks if an object literal (from import attributes) has type: 'macro'.
 fn is_macro(with: &ObjectLit) -> bool {
   for prop in &with.props {
@@ -340,339 +1076,417 @@ fn is_macro(with: &ObjectLit) -> bool {
   false
 }
 
-fn handle_error(result: Result<Expr, MacroError>, errors: &mut Vec<MacroError>) -> Expr {
-  match result {
-    Ok(expr) => expr,
-    Err(err) => {
-      if !errors.iter().any(|d| *d == err) {
-        errors.push(err);
-      }
-      Expr::Lit(Lit::Null(Null::dummy()))
-    }
+/// If the expression is a dynamic import of a macro, e.g. `import("x", { with: { type: "macro" } })`,
+/// returns the import specifier, if it is a string literal.
+fn dynamic_macro_import(expr: &Expr) -> Option<Option<JsWord>> {
+  let call = match unwrap_expr(expr) {
+    Expr::Call(
+      call @ CallExpr {
+        callee: Callee::Import(_),
+        ..
+      },
+    ) => call,
+    _ => return None,
+  };
+  let options = match call.args.get(1).map(|arg| &*arg.expr) {
+    Some(Expr::Object(options)) => options,
+    _ => return None,
+  };
+  let is_macro_import = options.props.iter().any(|prop| match prop {
+    PropOrSpread::Prop(prop) => match &**prop {
+      Prop::KeyValue(KeyValueProp {
+        key: PropName::Ident(Ident { sym, .. }) | PropName::Str(Str { value: sym, .. }),
+        value,
+      }) if sym == "with" => matches!(&**value, Expr::Object(with) if is_macro(with)),
+      _ => false,
+    },
+    _ => false,
+  });
+  if !is_macro_import {
+    return None;
+  }
+
+  match call.args.first().map(|arg| &*arg.expr) {
+    Some(Expr::Lit(Lit::Str(src))) => Some(Some(src.value.clone())),
+    _ => Some(None),
   }
 }
 
-/// A type that represents a basic JS value.
-#[derive(Clone, Debug)]
-pub enum JsValue {
-  Undefined,
-  Null,
-  Bool(bool),
-  Number(f64),
-  String(String),
-  Regex { source: String, flags: String },
-  Array(Vec<JsValue>),
-  Object(IndexMap<String, JsValue>),
-  Function(String),
+/// Finds dynamic imports of macros.
+#[derive(Default)]
+struct FindDynamicMacroImports {
+  spans: Vec<Span>,
 }
 
-impl<'a> Macros<'a> {
-  /// Statically evaluate a JS expression to a value, if possible.
-  fn eval(&self, expr: &Expr) -> Result<JsValue, Span> {
-    match expr.unwrap_parens() {
-      Expr::Lit(lit) => match lit {
-        Lit::Null(_) => Ok(JsValue::Null),
-        Lit::Bool(v) => Ok(JsValue::Bool(v.value)),
-        Lit::Num(v) => Ok(JsValue::Number(v.value)),
-        Lit::Str(v) => Ok(JsValue::String(v.value.to_string())),
-        Lit::JSXText(v) => Ok(JsValue::String(v.value.to_string())),
-        Lit::Regex(v) => Ok(JsValue::Regex {
-          source: v.exp.to_string(),
-          flags: v.flags.to_string(),
-        }),
-        Lit::BigInt(v) => Err(v.span),
-      },
-      Expr::Tpl(tpl) => {
-        let exprs: Vec<_> = tpl
-          .exprs
-          .iter()
-          .filter_map(|expr| self.eval(&*expr).ok())
-          .collect();
-        if exprs.len() == tpl.exprs.len() {
-          let mut res = String::new();
-          let mut expr_iter = exprs.iter();
-          for quasi in &tpl.quasis {
-            res.push_str(&quasi.raw);
-            match expr_iter.next() {
-              None => {}
-              Some(JsValue::String(s)) => res.push_str(s),
-              Some(JsValue::Number(n)) => res.push_str(&n.to_string()),
-              Some(JsValue::Bool(b)) => res.push_str(&b.to_string()),
-              _ => return Err(tpl.span),
-            }
-          }
+impl Visit for FindDynamicMacroImports {
+  fn visit_expr(&mut self, node: &Expr) {
+    if dynamic_macro_import(node).is_some() {
+      self.spans.push(node.span());
+    }
+    node.visit_children_with(self);
+  }
+}
 
-          Ok(JsValue::String(res))
-        } else {
-          Err(tpl.span)
-        }
+/// Collects the identifiers bound by a declaration pattern.
+fn collect_binding_ids(pat: &Pat, ids: &mut HashSet<Id>) {
+  match pat {
+    Pat::Ident(name) => {
+      ids.insert(name.to_id());
+    }
+    Pat::Array(arr) => {
+      for elem in arr.elems.iter().flatten() {
+        collect_binding_ids(elem, ids);
       }
-      Expr::Array(arr) => {
-        let mut res = Vec::with_capacity(arr.elems.len());
-        for elem in &arr.elems {
-          if let Some(elem) = elem {
-            let val = self.eval(&*elem.expr)?;
-            if elem.spread.is_some() {
-              match val {
-                JsValue::Array(arr) => {
-                  res.extend(arr);
-                }
-                _ => return Err(arr.span),
-              }
-            } else {
-              res.push(val);
-            }
-          } else {
-            res.push(JsValue::Undefined);
+    }
+    Pat::Object(obj) => {
+      for prop in &obj.props {
+        match prop {
+          ObjectPatProp::KeyValue(kv) => collect_binding_ids(&kv.value, ids),
+          ObjectPatProp::Assign(assign) => {
+            ids.insert(assign.key.to_id());
           }
+          ObjectPatProp::Rest(rest) => collect_binding_ids(&rest.arg, ids),
         }
-        Ok(JsValue::Array(res))
       }
-      Expr::Object(obj) => {
-        let mut res = IndexMap::with_capacity(obj.props.len());
-        for prop in &obj.props {
-          match prop {
-            PropOrSpread::Prop(prop) => match &**prop {
-              Prop::KeyValue(kv) => {
-                let v = self.eval(&*kv.value)?;
-                let k = match &kv.key {
-                  PropName::Ident(Ident { sym, .. }) | PropName::Str(Str { value: sym, .. }) => {
-                    sym.to_string()
-                  }
-                  PropName::Num(n) => n.value.to_string(),
-                  PropName::Computed(c) => match self.eval(&*c.expr) {
-                    Err(e) => return Err(e),
-                    Ok(JsValue::String(s)) => s,
-                    Ok(JsValue::Number(n)) => n.to_string(),
-                    Ok(JsValue::Bool(b)) => b.to_string(),
-                    _ => return Err(c.span),
-                  },
-                  PropName::BigInt(v) => return Err(v.span),
-                };
+    }
+    Pat::Rest(rest) => collect_binding_ids(&rest.arg, ids),
+    Pat::Assign(assign) => collect_binding_ids(&assign.left, ids),
+    Pat::Invalid(_) | Pat::Expr(_) => {}
+  }
+}
 
-                res.insert(k.to_string(), v);
-              }
-              Prop::Shorthand(s) => {
-                if let Some(val) = self.constants.get(&s.to_id()) {
-                  res.insert(s.sym.to_string(), val.clone()?);
-                } else {
-                  return Err(s.span);
-                }
-              }
-              _ => return Err(obj.span),
-            },
-            PropOrSpread::Spread(spread) => {
-              let v = self.eval(&*spread.expr)?;
-              match v {
-                JsValue::Object(o) => res.extend(o),
-                _ => return Err(obj.span),
-              }
-            }
-          }
+/// Collects let/var bindings that may be written to after their declaration. This includes
+/// assignments and updates anywhere in the module (including within closures), loop heads,
+/// and var redeclarations.
+#[derive(Default)]
+struct ReassignedBindings {
+  /// All declared bindings, used to treat everything as reassigned when eval is called.
+  declared: HashSet<Id>,
+  reassigned: HashSet<Id>,
+  /// Whether the module calls eval, which could reassign any binding.
+  has_eval: bool,
+}
+
+impl Visit for ReassignedBindings {
+  fn visit_var_decl(&mut self, node: &VarDecl) {
+    for decl in &node.decls {
+      let mut ids = HashSet::new();
+      collect_binding_ids(&decl.name, &mut ids);
+      for id in ids {
+        if !self.declared.insert(id.clone()) && node.kind == VarDeclKind::Var {
+          self.reassigned.insert(id);
         }
-        Ok(JsValue::Object(res))
       }
-      Expr::Bin(bin) => match (bin.op, self.eval(&*bin.left), self.eval(&*bin.right)) {
-        (BinaryOp::Add, Ok(JsValue::String(a)), Ok(JsValue::String(b))) => {
-          Ok(JsValue::String(format!("{}{}", a, b)))
-        }
-        (BinaryOp::Add, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
-          Ok(JsValue::Number(a + b))
-        }
-        (BinaryOp::Add, Ok(JsValue::String(a)), Ok(JsValue::Number(b))) => {
-          Ok(JsValue::String(format!("{}{}", a, b)))
-        }
-        (BinaryOp::Add, Ok(JsValue::Number(a)), Ok(JsValue::String(b))) => {
-          Ok(JsValue::String(format!("{}{}", a, b)))
-        }
-        (BinaryOp::BitAnd, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
-          Ok(JsValue::Number(((a as i32) & (b as i32)) as f64))
-        }
-        (BinaryOp::BitOr, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
-          Ok(JsValue::Number(((a as i32) | (b as i32)) as f64))
-        }
-        (BinaryOp::BitXor, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
-          Ok(JsValue::Number(((a as i32) ^ (b as i32)) as f64))
-        }
-        (BinaryOp::LShift, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
-          Ok(JsValue::Number(((a as i32) << (b as i32)) as f64))
-        }
-        (BinaryOp::RShift, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
-          Ok(JsValue::Number(((a as i32) >> (b as i32)) as f64))
-        }
-        (BinaryOp::ZeroFillRShift, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
-          Ok(JsValue::Number(((a as i32) >> (b as u32)) as f64))
-        }
-        (BinaryOp::Sub, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
-          Ok(JsValue::Number(a - b))
-        }
-        (BinaryOp::Div, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
-          Ok(JsValue::Number(a / b))
-        }
-        (BinaryOp::Mul, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
-          Ok(JsValue::Number(a * b))
-        }
-        (BinaryOp::Mod, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
-          Ok(JsValue::Number(a % b))
-        }
-        (BinaryOp::Exp, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
-          Ok(JsValue::Number(a.powf(b)))
-        }
-        (BinaryOp::EqEq, Ok(JsValue::Bool(a)), Ok(JsValue::Bool(b))) => Ok(JsValue::Bool(a == b)),
-        (BinaryOp::EqEqEq, Ok(JsValue::Bool(a)), Ok(JsValue::Bool(b))) => Ok(JsValue::Bool(a == b)),
-        (BinaryOp::NotEq, Ok(JsValue::Bool(a)), Ok(JsValue::Bool(b))) => Ok(JsValue::Bool(a != b)),
-        (BinaryOp::NotEqEq, Ok(JsValue::Bool(a)), Ok(JsValue::Bool(b))) => {
-          Ok(JsValue::Bool(a != b))
-        }
-        (BinaryOp::EqEq, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
-          Ok(JsValue::Bool(a == b))
-        }
-        (BinaryOp::EqEqEq, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
-          Ok(JsValue::Bool(a == b))
-        }
-        (BinaryOp::NotEq, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
-          Ok(JsValue::Bool(a != b))
-        }
-        (BinaryOp::NotEqEq, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
-          Ok(JsValue::Bool(a != b))
-        }
-        (BinaryOp::EqEq, Ok(JsValue::String(a)), Ok(JsValue::String(b))) => {
-          Ok(JsValue::Bool(a == b))
-        }
-        (BinaryOp::EqEqEq, Ok(JsValue::String(a)), Ok(JsValue::String(b))) => {
-          Ok(JsValue::Bool(a == b))
-        }
-        (BinaryOp::NotEq, Ok(JsValue::String(a)), Ok(JsValue::String(b))) => {
-          Ok(JsValue::Bool(a != b))
-        }
-        (BinaryOp::NotEqEq, Ok(JsValue::String(a)), Ok(JsValue::String(b))) => {
-          Ok(JsValue::Bool(a != b))
-        }
-        (BinaryOp::Gt, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => Ok(JsValue::Bool(a > b)),
-        (BinaryOp::GtEq, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
-          Ok(JsValue::Bool(a >= b))
-        }
-        (BinaryOp::Lt, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => Ok(JsValue::Bool(a < b)),
-        (BinaryOp::LtEq, Ok(JsValue::Number(a)), Ok(JsValue::Number(b))) => {
-          Ok(JsValue::Bool(a <= b))
-        }
-        (BinaryOp::LogicalAnd, Ok(JsValue::Bool(a)), Ok(JsValue::Bool(b))) => {
-          Ok(JsValue::Bool(a && b))
-        }
-        (BinaryOp::LogicalOr, Ok(JsValue::Bool(a)), Ok(JsValue::Bool(b))) => {
-          Ok(JsValue::Bool(a || b))
-        }
-        (BinaryOp::NullishCoalescing, Ok(JsValue::Null | JsValue::Undefined), Ok(b)) => Ok(b),
-        (BinaryOp::NullishCoalescing, Ok(a), Ok(_)) => Ok(a),
-        _ => Err(bin.span),
-      },
-      Expr::Unary(unary) => match (unary.op, self.eval(&*unary.arg)) {
-        (UnaryOp::Bang, Ok(JsValue::Bool(v))) => Ok(JsValue::Bool(!v)),
-        (UnaryOp::Minus, Ok(JsValue::Number(v))) => Ok(JsValue::Number(-v)),
-        (UnaryOp::Plus, Ok(JsValue::Number(v))) => Ok(JsValue::Number(v)),
-        (UnaryOp::Plus, Ok(JsValue::String(v))) => {
-          if let Ok(v) = v.parse() {
-            Ok(JsValue::Number(v))
-          } else {
-            Err(unary.span)
-          }
-        }
-        (UnaryOp::Tilde, Ok(JsValue::Number(v))) => Ok(JsValue::Number((!(v as i32)) as f64)),
-        (UnaryOp::Void, Ok(_)) => Ok(JsValue::Undefined),
-        (UnaryOp::TypeOf, Ok(JsValue::Bool(_))) => Ok(JsValue::String("boolean".to_string())),
-        (UnaryOp::TypeOf, Ok(JsValue::Number(_))) => Ok(JsValue::String("number".to_string())),
-        (UnaryOp::TypeOf, Ok(JsValue::String(_))) => Ok(JsValue::String("string".to_string())),
-        (UnaryOp::TypeOf, Ok(JsValue::Object(_))) => Ok(JsValue::String("object".to_string())),
-        (UnaryOp::TypeOf, Ok(JsValue::Array(_))) => Ok(JsValue::String("object".to_string())),
-        (UnaryOp::TypeOf, Ok(JsValue::Regex { .. })) => Ok(JsValue::String("object".to_string())),
-        (UnaryOp::TypeOf, Ok(JsValue::Null)) => Ok(JsValue::String("object".to_string())),
-        (UnaryOp::TypeOf, Ok(JsValue::Undefined)) => Ok(JsValue::String("undefined".to_string())),
-        _ => Err(unary.span),
-      },
-      Expr::Cond(cond) => match self.eval(&*&cond.test) {
-        Ok(JsValue::Bool(v)) => {
-          if v {
-            self.eval(&*&cond.cons)
-          } else {
-            self.eval(&*cond.alt)
-          }
-        }
-        Ok(JsValue::Null) | Ok(JsValue::Undefined) => self.eval(&*cond.alt),
-        Ok(JsValue::Object(_))
-        | Ok(JsValue::Array(_))
-        | Ok(JsValue::Function(_))
-        | Ok(JsValue::Regex { .. }) => self.eval(&*cond.cons),
-        Ok(JsValue::String(s)) => {
-          if s.is_empty() {
-            self.eval(&*cond.alt)
-          } else {
-            self.eval(&*cond.cons)
-          }
-        }
-        Ok(JsValue::Number(n)) => {
-          if n == 0.0 {
-            self.eval(&*cond.alt)
-          } else {
-            self.eval(&*cond.cons)
+    }
+
+    node.visit_children_with(self);
+  }
+
+  fn visit_assign_expr(&mut self, node: &AssignExpr) {
+    match &node.left {
+      PatOrExpr::Pat(pat) => match &**pat {
+        Pat::Expr(expr) => {
+          if let Expr::Ident(id) = unwrap_expr(expr) {
+            self.reassigned.insert(id.to_id());
           }
         }
-        Err(e) => Err(e),
+        pat => collect_binding_ids(pat, &mut self.reassigned),
       },
-      Expr::Ident(id) if &id.sym == "undefined" => Ok(JsValue::Undefined),
-      Expr::Ident(id) => {
-        if let Some(val) = self.constants.get(&id.to_id()) {
-          val.clone()
-        } else {
-          Err(id.span)
+      PatOrExpr::Expr(expr) => {
+        if let Expr::Ident(id) = unwrap_expr(expr) {
+          self.reassigned.insert(id.to_id());
         }
       }
-      Expr::Member(member) => {
-        let obj = self.eval(&*member.obj)?;
-        self.eval_member_prop(obj, &member)
-      }
-      Expr::OptChain(opt) => {
-        if let OptChainBase::Member(member) = &*opt.base {
-          let obj = self.eval(&*member.obj)?;
-          match obj {
-            JsValue::Undefined | JsValue::Null => Ok(JsValue::Undefined),
-            _ => self.eval_member_prop(obj, &member),
-          }
-        } else {
-          Err(opt.span)
-        }
+    }
+
+    node.visit_children_with(self);
+  }
+
+  fn visit_update_expr(&mut self, node: &UpdateExpr) {
+    if let Expr::Ident(id) = unwrap_expr(&node.arg) {
+      self.reassigned.insert(id.to_id());
+    }
+
+    node.visit_children_with(self);
+  }
+
+  fn visit_for_in_stmt(&mut self, node: &ForInStmt) {
+    if let ForHead::Pat(pat) = &node.left {
+      collect_binding_ids(pat, &mut self.reassigned);
+    }
+
+    node.visit_children_with(self);
+  }
+
+  fn visit_for_of_stmt(&mut self, node: &ForOfStmt) {
+    if let ForHead::Pat(pat) = &node.left {
+      collect_binding_ids(pat, &mut self.reassigned);
+    }
+
+    node.visit_children_with(self);
+  }
+
+  fn visit_call_expr(&mut self, node: &CallExpr) {
+    if let Callee::Expr(callee) = &node.callee {
+      if matches!(&**callee, Expr::Ident(id) if &id.sym == "eval") {
+        self.has_eval = true;
       }
-      Expr::Fn(FnExpr { function, .. }) => Err(function.span),
-      Expr::Class(ClassExpr { class, .. }) => Err(class.span),
-      Expr::JSXElement(el) => Err(el.span),
-      Expr::This(ThisExpr { span, .. })
-      | Expr::Update(UpdateExpr { span, .. })
-      | Expr::Assign(AssignExpr { span, .. })
-      | Expr::Call(CallExpr { span, .. })
-      | Expr::New(NewExpr { span, .. })
-      | Expr::Seq(SeqExpr { span, .. })
-      | Expr::TaggedTpl(TaggedTpl { span, .. })
-      | Expr::Arrow(ArrowExpr { span, .. })
-      | Expr::Yield(YieldExpr { span, .. })
-      | Expr::Await(AwaitExpr { span, .. })
-      | Expr::JSXFragment(JSXFragment { span, .. })
-      | Expr::PrivateName(PrivateName { span, .. }) => Err(*span),
-      _ => Err(DUMMY_SP),
     }
+
+    node.visit_children_with(self);
   }
+}
 
-  fn eval_member_prop(&self, obj: JsValue, member: &MemberExpr) -> Result<JsValue, Span> {
-    match &member.prop {
-      MemberProp::Ident(id) => obj.get_id(id.as_ref()).ok_or(member.span),
-      MemberProp::Computed(prop) => {
-        let k = self.eval(&*prop.expr)?;
-        obj.get(&k).ok_or(prop.span)
+/// Returns a hint describing how to fix an expression that could not be evaluated,
+/// based on the kind of the sub-expression at the error location.
+fn eval_hint(
+  expr: &Expr,
+  span: Span,
+  non_const_bindings: &HashSet<Id>,
+  is_global: &dyn Fn(&Ident) -> bool,
+) -> Option<String> {
+  let mut finder = FindExpr { span, found: None };
+  expr.visit_with(&mut finder);
+  match finder.found? {
+    Expr::Ident(id) if non_const_bindings.contains(&id.to_id()) => Some(format!(
+      "Declare `{}` with `const` so it can be evaluated at build time.",
+      id.sym
+    )),
+    Expr::Ident(id) => Some(format!(
+      "`{}` is not a constant that can be evaluated at build time.",
+      id.sym
+    )),
+    Expr::Unary(UnaryExpr {
+      op: UnaryOp::TypeOf,
+      arg,
+      ..
+    }) => match &*arg {
+      Expr::Ident(id) if is_global(id) => Some(
+        "`typeof` of a global cannot be evaluated at build time, since it may only be defined at runtime."
+          .into(),
+      ),
+      // Local bindings get the same hint as when they are used directly.
+      Expr::Ident(id) => eval_hint(&arg, id.span, non_const_bindings, is_global),
+      _ => None,
+    },
+    Expr::OptChain(OptChainExpr { base, .. }) if matches!(&*base, OptChainBase::Call(_)) => Some(
+      "Only functions without parameters that return a value can be called in macro arguments."
+        .into(),
+    ),
+    Expr::Call(CallExpr {
+      callee: Callee::Expr(callee),
+      ..
+    }) => match &*callee {
+      Expr::Member(MemberExpr {
+        obj,
+        prop: MemberProp::Ident(prop),
+        ..
+      }) => {
+        let hint = match &**obj {
+          Expr::Ident(obj) => globals::call_hint(&format!("{}.{}", obj.sym, prop.sym)),
+          _ => None,
+        };
+        Some(hint.unwrap_or_else(|| {
+          format!(
+            "The `{}` method is not supported in macro arguments.",
+            prop.sym
+          )
+        }))
       }
-      _ => Err(member.span),
+      Expr::Ident(id) => Some(
+        globals::call_hint(&id.sym)
+          .unwrap_or_else(|| "Function calls cannot be evaluated at build time.".into()),
+      ),
+      _ => Some("Function calls cannot be evaluated at build time.".into()),
+    },
+    _ => None,
+  }
+}
+
+/// Replaces all spans with the given span, preserving their syntax contexts. The resulting
+/// spans are recorded, since they no longer identify a single node.
+struct SpanRemapper<'a> {
+  span: Span,
+  remapped: &'a mut HashSet<Span>,
+}
+
+impl<'a> VisitMut for SpanRemapper<'a> {
+  fn visit_mut_span(&mut self, span: &mut Span) {
+    *span = self.span.with_ctxt(span.ctxt);
+    self.remapped.insert(*span);
+  }
+}
+
+/// Finds a reference to a constant that was marked as mutated at the given span.
+struct FindMutatedConstant<'a> {
+  constants: &'a HashMap<Id, Result<JsValue, Span>>,
+  span: Span,
+  found: Option<Span>,
+}
+
+impl<'a> Visit for FindMutatedConstant<'a> {
+  fn visit_ident(&mut self, node: &Ident) {
+    if self.found.is_none()
+      && matches!(self.constants.get(&node.to_id()), Some(Err(span)) if *span == self.span)
+    {
+      self.found = Some(node.span);
+    }
+  }
+}
+
+/// Finds the outermost expression with the given span.
+struct FindExpr {
+  span: Span,
+  found: Option<Expr>,
+}
+
+impl Visit for FindExpr {
+  fn visit_expr(&mut self, node: &Expr) {
+    if self.found.is_some() {
+      return;
+    }
+
+    if node.span() == self.span {
+      self.found = Some(node.clone());
+    } else {
+      node.visit_children_with(self);
+    }
+  }
+}
+
+/// Finds the member expression at the given span, or with a computed property at the span.
+struct FindMember {
+  span: Span,
+  found: Option<MemberExpr>,
+}
+
+impl Visit for FindMember {
+  fn visit_member_expr(&mut self, node: &MemberExpr) {
+    if self.found.is_some() {
+      return;
+    }
+
+    if node.span == self.span
+      || matches!(&node.prop, MemberProp::Computed(prop) if prop.span == self.span)
+    {
+      self.found = Some(node.clone());
+    } else {
+      node.visit_children_with(self);
     }
   }
+}
 
-  /// Convert JS value to AST.
-  fn value_to_expr(&self, value: JsValue) -> Result<Expr, MacroError> {
+fn handle_error(result: Result<Expr, MacroError>, errors: &mut Vec<MacroError>) -> Expr {
+  match result {
+    Ok(expr) => expr,
+    Err(err) => {
+      push_error(errors, err);
+      Expr::Lit(Lit::Null(Null::dummy()))
+    }
+  }
+}
+
+/// Matches a string against a pattern in which `*` matches any sequence of characters.
+fn matches_pattern(pattern: &str, s: &str) -> bool {
+  let mut parts = pattern.split('*');
+  let first = parts.next().unwrap_or_default();
+  let mut rest = match s.strip_prefix(first) {
+    Some(rest) => rest,
+    None => return false,
+  };
+  let mut parts: Vec<_> = parts.collect();
+  let last = match parts.pop() {
+    Some(last) => last,
+    // No wildcards, so the pattern must match exactly.
+    None => return rest.is_empty(),
+  };
+  for part in parts {
+    match rest.find(part) {
+      Some(i) => rest = &rest[i + part.len()..],
+      None => return false,
+    }
+  }
+  rest.ends_with(last)
+}
+
+fn push_error(errors: &mut Vec<MacroError>, err: MacroError) {
+  if !errors.contains(&err) {
+    errors.push(err);
+  }
+}
+
+/// A type that represents a basic JS value.
+#[derive(Clone, Debug)]
+pub enum JsValue {
+  Undefined,
+  Null,
+  Bool(bool),
+  Number(f64),
+  String(String),
+  Regex {
+    source: String,
+    flags: String,
+  },
+  Array(Vec<JsValue>),
+  Object(IndexMap<String, JsValue>),
+  /// A `Map`, with entries in insertion order. Keys are unique, as compared by SameValueZero.
+  Map(Vec<(JsValue, JsValue)>),
+  /// A `Set`, with values in insertion order. Values are unique, as compared by SameValueZero.
+  Set(Vec<JsValue>),
+  /// A `Date`, as milliseconds since the epoch. Invalid dates are NaN.
+  Date(f64),
+  /// A `BigInt`, e.g. `1n`.
+  BigInt(BigIntValue),
+  /// The source code of a function returned by a macro. It is inserted as is, and macro
+  /// calls within it are not expanded, since identifiers in the source refer to the scope
+  /// of the macro's module rather than the module it is returned to.
+  Function(String),
+}
+
+impl<'a> Macros<'a> {
+  fn set_constant(&mut self, id: Id, value: Result<JsValue, Span>) {
+    self.constants.insert(id, value);
+    self.eval_cache.get_mut().clear();
+  }
+
+  fn evaluator(&self) -> Evaluator<'_, HashMap<Id, Result<JsValue, Span>>> {
+    Evaluator {
+      constants: &self.constants,
+      cache: Some(&self.eval_cache),
+      cache_hits: Some(&self.eval_cache_hits),
+      shared_spans: Some(&self.shared_spans),
+      unresolved_ctxt: self.unresolved_ctxt,
+      build_time: self.build_time,
+      source_map: Some(self.source_map),
+      env: self.env.as_ref(),
+      missing_env_undefined: self.missing_env_undefined,
+      global_types: self.global_types.as_ref(),
+      max_string_length: self.max_string_length,
+      oversized_strings: Some(&self.oversized_strings),
+      max_array_length: self.max_array_length,
+      oversized_arrays: Some(&self.oversized_arrays),
+    }
+  }
+
+  fn eval(&self, expr: &Expr) -> Result<JsValue, Span> {
+    self.evaluator().eval(expr)
+  }
+
+  fn eval_cow(&self, expr: &Expr) -> Result<Cow<'_, JsValue>, Span> {
+    self.evaluator().eval_cow(expr)
+  }
+
+  fn eval_member_cow<'v>(
+    &self,
+    obj: Cow<'v, JsValue>,
+    member: &MemberExpr,
+  ) -> Result<Cow<'v, JsValue>, Span> {
+    self.evaluator().eval_member_cow(obj, member)
+  }
+
+  /// Convert JS value to AST. Code parsed from functions returned by the macro
+  /// is mapped to the span of the macro call.
+  fn value_to_expr(&self, value: JsValue, span: Span) -> Result<Expr, MacroError> {
     Ok(match value {
       JsValue::Null => Expr::Lit(Lit::Null(Null::dummy())),
       JsValue::Undefined => Expr::Ident(Ident::new(js_word!("undefined"), DUMMY_SP)),
@@ -683,18 +1497,37 @@ impl<'a> Macros<'a> {
       JsValue::Number(n) => Expr::Lit(Lit::Num(Number {
         value: n,
         span: DUMMY_SP,
-        raw: None,
+        // Emit the shortest representation that round trips, as JS would print it, rather
+        // than relying on the code generator to format the f64. Negative numbers,
+        // NaN and infinities are not literals, so leave those to the code generator.
+        raw: if n.is_finite() && n.is_sign_positive() {
+          Some(js_number_to_string(n).into())
+        } else {
+          None
+        },
       })),
       JsValue::String(s) => Expr::Lit(Lit::Str(Str {
         span: DUMMY_SP,
         value: s.into(),
         raw: None,
       })),
-      JsValue::Regex { source, flags } => Expr::Lit(Lit::Regex(Regex {
-        span: DUMMY_SP,
-        exp: source.into(),
-        flags: flags.into(),
-      })),
+      JsValue::Regex { source, flags } => {
+        // Macros could return any flags, so make sure they produce a valid literal.
+        let normalized = globals::regexp_flags(&flags).ok_or_else(|| {
+          MacroError::ExecutionError(
+            format!(
+              "Macro returned a regular expression with invalid flags \"{}\".",
+              flags
+            ),
+            span,
+          )
+        })?;
+        Expr::Lit(Lit::Regex(Regex {
+          span: DUMMY_SP,
+          exp: globals::regexp_source(&source).into(),
+          flags: normalized.into(),
+        }))
+      }
       JsValue::Array(arr) => Expr::Array(ArrayLit {
         span: DUMMY_SP,
         elems: arr
@@ -702,7 +1535,7 @@ impl<'a> Macros<'a> {
           .map(|elem| -> Result<_, MacroError> {
             Ok(Some(ExprOrSpread {
               spread: None,
-              expr: Box::new(self.value_to_expr(elem)?),
+              expr: Box::new(self.value_to_expr(elem, span)?),
             }))
           })
           .collect::<Result<Vec<_>, MacroError>>()?,
@@ -722,11 +1555,33 @@ impl<'a> Macros<'a> {
                   raw: None,
                 })
               },
-              value: Box::new(self.value_to_expr(v)?),
+              value: Box::new(self.value_to_expr(v, span)?),
             }))))
           })
           .collect::<Result<Vec<_>, MacroError>>()?,
       }),
+      JsValue::Map(entries) => {
+        let entries = entries
+          .into_iter()
+          .map(|(k, v)| JsValue::Array(vec![k, v]))
+          .collect();
+        new_instance("Map", self.value_to_expr(JsValue::Array(entries), span)?)
+      }
+      JsValue::Set(values) => {
+        new_instance("Set", self.value_to_expr(JsValue::Array(values), span)?)
+      }
+      JsValue::Date(time) => new_instance("Date", self.value_to_expr(JsValue::Number(time), span)?),
+      // Negative BigInts are not literals, so are emitted as a negated literal.
+      JsValue::BigInt(n) if n < BigIntValue::from(0) => Expr::Unary(UnaryExpr {
+        span: DUMMY_SP,
+        op: UnaryOp::Minus,
+        arg: Box::new(self.value_to_expr(JsValue::BigInt(-n), span)?),
+      }),
+      JsValue::BigInt(n) => Expr::Lit(Lit::BigInt(BigInt {
+        span: DUMMY_SP,
+        value: Box::new(n),
+        raw: None,
+      })),
       JsValue::Function(source) => {
         let source_file = self
           .source_map
@@ -740,7 +1595,15 @@ impl<'a> Macros<'a> {
 
         let mut parser = Parser::new_from(lexer);
         match parser.parse_expr() {
-          Ok(expr) => *expr,
+          Ok(mut expr) => {
+            // The parsed spans point into the synthetic macro expansion file,
+            // so remap them to the macro call instead.
+            expr.visit_mut_with(&mut SpanRemapper {
+              span,
+              remapped: &mut self.shared_spans.borrow_mut(),
+            });
+            *expr
+          }
           Err(err) => return Err(MacroError::ParseError(err)),
         }
       }
@@ -750,7 +1613,15 @@ impl<'a> Macros<'a> {
   fn eval_pat(&mut self, value: Result<JsValue, Span>, pat: &Pat) {
     match pat {
       Pat::Ident(name) => {
-        self.constants.insert(name.to_id(), value);
+        self.set_constant(name.to_id(), value);
+      }
+      // The default value is only evaluated if the value is undefined, as in JS.
+      Pat::Assign(assign) => {
+        let value = match value {
+          Ok(JsValue::Undefined) => self.eval(&assign.right),
+          value => value,
+        };
+        self.eval_pat(value, &assign.left)
       }
       Pat::Array(arr) => {
         for (index, elem) in arr.elems.iter().enumerate() {
@@ -764,7 +1635,7 @@ impl<'a> Macros<'a> {
               }) => self.eval_pat(
                 value
                   .as_ref()
-                  .and_then(|v| v.get_index(index).ok_or(span))
+                  .and_then(|v| v.get_index(index).cloned().ok_or(span))
                   .map_err(|s| *s),
                 elem,
               ),
@@ -775,13 +1646,13 @@ impl<'a> Macros<'a> {
                   .map_err(|s| *s),
                 &*rest.arg,
               ),
+              // Missing elements are undefined, so the default value applies.
               Pat::Assign(assign) => self.eval_pat(
-                value.as_ref().map_err(|e| *e).and_then(|v| {
-                  v.get_index(index)
-                    .ok_or(assign.span)
-                    .or_else(|_| self.eval(&*assign.right))
+                value.as_ref().map_err(|e| *e).and_then(|v| match v {
+                  JsValue::Array(arr) => Ok(arr.get(index).cloned().unwrap_or(JsValue::Undefined)),
+                  _ => Err(assign.span),
                 }),
-                &*assign.left,
+                elem,
               ),
               _ => {}
             }
@@ -793,52 +1664,70 @@ impl<'a> Macros<'a> {
         for prop in &obj.props {
           match prop {
             ObjectPatProp::KeyValue(kv) => {
-              let val = value
-                .as_ref()
-                .map_err(|e| *e)
-                .and_then(|value| match &kv.key {
+              let val = value.as_ref().map_err(|e| *e).and_then(|value| {
+                let (prop, span) = match &kv.key {
                   PropName::Ident(id) => {
                     consumed.insert(id.sym.clone());
-                    value.get_id(id.sym.as_str()).ok_or(id.span)
+                    (value.get_id(id.sym.as_str()), id.span)
                   }
                   PropName::Str(s) => {
                     consumed.insert(s.value.clone());
-                    value.get_id(s.value.as_str()).ok_or(s.span)
+                    (value.get_id(s.value.as_str()), s.span)
                   }
                   PropName::Num(n) => {
-                    consumed.insert(n.value.to_string().into());
-                    value.get_index(n.value as usize).ok_or(n.span)
+                    consumed.insert(js_number_to_string(n.value).into());
+                    (value.get(&JsValue::Number(n.value)), n.span)
                   }
                   PropName::Computed(c) => {
-                    let k = &self.eval(&*c.expr)?;
-                    match k {
-                      JsValue::String(s) => {
-                        consumed.insert(s.clone().into());
-                      }
-                      JsValue::Number(n) => {
-                        consumed.insert(n.to_string().into());
+                    // Keys are normalized to the same strings as other property names
+                    // so they are excluded from a rest element, e.g. `[0]` and `"0"`.
+                    let k = match self.eval(&c.expr)? {
+                      JsValue::String(s) => JsValue::String(s),
+                      JsValue::Number(n) => JsValue::Number(n),
+                      k @ (JsValue::Bool(_) | JsValue::Null | JsValue::Undefined) => {
+                        JsValue::String(k.to_js_string())
                       }
-                      _ => {}
-                    }
-                    value.get(&k).ok_or(c.span)
+                      _ => return Err(c.span),
+                    };
+                    consumed.insert(k.to_js_string().into());
+                    (value.get(&k), c.span)
                   }
-                  PropName::BigInt(v) => Err(v.span),
-                });
+                  PropName::BigInt(v) => {
+                    let k = v.value.to_string();
+                    consumed.insert(k.as_str().into());
+                    (value.get(&JsValue::String(k)), v.span)
+                  }
+                };
+                match prop {
+                  Some(prop) => Ok(prop.into_owned()),
+                  // Missing properties are undefined, so the default value applies.
+                  None
+                    if matches!(&*kv.value, Pat::Assign(_))
+                      && matches!(value, JsValue::Object(_)) =>
+                  {
+                    Ok(JsValue::Undefined)
+                  }
+                  None => Err(span),
+                }
+              });
               self.eval_pat(val, &*kv.value)
             }
             ObjectPatProp::Assign(assign) => {
-              let val = value.as_ref().map_err(|e| *e).and_then(|value| {
-                value
-                  .get_id(assign.key.sym.as_str())
-                  .ok_or(assign.span)
-                  .or_else(|_| {
-                    assign
-                      .value
-                      .as_ref()
-                      .map_or(Err(assign.span), |v| self.eval(&*v))
-                  })
-              });
-              self.constants.insert(assign.key.to_id(), val);
+              let val = value
+                .as_ref()
+                .map_err(|e| *e)
+                .and_then(|value| match value.get_id(assign.key.sym.as_str()) {
+                  Some(prop) => Ok(prop.into_owned()),
+                  None if assign.value.is_some() && matches!(value, JsValue::Object(_)) => {
+                    Ok(JsValue::Undefined)
+                  }
+                  None => Err(assign.span),
+                })
+                .and_then(|value| match (value, &assign.value) {
+                  (JsValue::Undefined, Some(default)) => self.eval(default),
+                  (value, _) => Ok(value),
+                });
+              self.set_constant(assign.key.to_id(), val);
               consumed.insert(assign.key.sym.clone());
             }
             ObjectPatProp::Rest(rest) => {
@@ -864,19 +1753,45 @@ impl<'a> Macros<'a> {
   }
 }
 
+/// Creates a `new` expression with a single argument, e.g. `new Map(entries)`.
+fn new_instance(name: &str, arg: Expr) -> Expr {
+  Expr::New(NewExpr {
+    span: DUMMY_SP,
+    callee: Box::new(Expr::Ident(Ident::new(name.into(), DUMMY_SP))),
+    args: Some(vec![ExprOrSpread {
+      spread: None,
+      expr: Box::new(arg),
+    }]),
+    type_args: None,
+  })
+}
+
 impl JsValue {
-  fn get(&self, prop: &JsValue) -> Option<JsValue> {
+  /// Whether the value is or contains a function, which cannot be passed to JS.
+  fn contains_function(&self) -> bool {
     match self {
-      JsValue::Array(arr) => {
-        if let JsValue::Number(n) = prop {
-          arr.get(*n as usize).cloned()
-        } else {
-          None
+      JsValue::Function(_) => true,
+      JsValue::Array(arr) | JsValue::Set(arr) => arr.iter().any(JsValue::contains_function),
+      JsValue::Object(obj) => obj.values().any(JsValue::contains_function),
+      JsValue::Map(entries) => entries
+        .iter()
+        .any(|(k, v)| k.contains_function() || v.contains_function()),
+      _ => false,
+    }
+  }
+
+  fn get(&self, prop: &JsValue) -> Option<Cow<'_, JsValue>> {
+    match self {
+      JsValue::Array(arr) => match prop {
+        // Only non-negative integers are array indices.
+        JsValue::Number(n) if *n >= 0.0 && n.fract() == 0.0 => {
+          arr.get(*n as usize).map(Cow::Borrowed)
         }
-      }
+        _ => None,
+      },
       JsValue::Object(_) => match prop {
         JsValue::Number(n) => {
-          let index = n.to_string();
+          let index = js_number_to_string(*n);
           self.get_id(&index)
         }
         JsValue::String(s) => self.get_id(s),
@@ -884,28 +1799,51 @@ impl JsValue {
       },
       JsValue::String(s) => match prop {
         JsValue::String(prop) => self.get_id(prop),
-        JsValue::Number(n) => s
-          .get(*n as usize..=*n as usize)
-          .map(|c| JsValue::String(c.to_owned())),
+        // Strings are indexed by UTF-16 code unit. Half of a surrogate pair cannot be
+        // represented in a Rust string, so it becomes the replacement character.
+        JsValue::Number(n) => {
+          let unit = if *n >= 0.0 && n.fract() == 0.0 {
+            s.encode_utf16().nth(*n as usize)
+          } else {
+            None
+          };
+          Some(Cow::Owned(match unit {
+            Some(c) => JsValue::String(String::from_utf16_lossy(&[c])),
+            None => JsValue::Undefined,
+          }))
+        }
         _ => None,
       },
       _ => None,
     }
   }
 
-  fn get_index(&self, index: usize) -> Option<JsValue> {
+  fn get_index(&self, index: usize) -> Option<&JsValue> {
     if let JsValue::Array(arr) = self {
-      arr.get(index).cloned()
+      arr.get(index)
     } else {
       None
     }
   }
 
-  fn get_id(&self, prop: &str) -> Option<JsValue> {
+  fn get_id(&self, prop: &str) -> Option<Cow<'_, JsValue>> {
     match self {
-      JsValue::Object(obj) => obj.get(prop).cloned(),
+      JsValue::Object(obj) => obj.get(prop).map(Cow::Borrowed),
       JsValue::String(s) => match prop {
-        "length" => Some(JsValue::Number(s.len() as f64)),
+        // Like indexing, the length is in UTF-16 code units.
+        "length" => Some(Cow::Owned(JsValue::Number(s.encode_utf16().count() as f64))),
+        _ => None,
+      },
+      JsValue::Array(arr) => match prop {
+        "length" => Some(Cow::Owned(JsValue::Number(arr.len() as f64))),
+        _ => None,
+      },
+      JsValue::Map(entries) => match prop {
+        "size" => Some(Cow::Owned(JsValue::Number(entries.len() as f64))),
+        _ => None,
+      },
+      JsValue::Set(values) => match prop {
+        "size" => Some(Cow::Owned(JsValue::Number(values.len() as f64))),
         _ => None,
       },
       _ => None,
@@ -920,3 +1858,1599 @@ impl JsValue {
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+  use swc_core::common::{sync::Lrc, BytePos, FileName, Globals, GLOBALS};
+  use swc_core::ecma::codegen::{text_writer::JsWriter, Emitter};
+  use swc_core::ecma::parser::{EsConfig, Syntax};
+  use swc_core::ecma::transforms::base::resolver;
+
+  /// A macro callback that returns its first argument, so tests can observe evaluated values.
+  fn identity() -> MacroCallback {
+    Arc::new(|_src, _export, args, _loc| {
+      Ok(args.into_iter().next().unwrap_or(JsValue::Undefined).into())
+    })
+  }
+
+  fn transform(code: &str, callback: MacroCallback) -> (String, Vec<MacroError>) {
+    transform_with(code, callback, |macros| macros)
+  }
+
+  fn transform_with(
+    code: &str,
+    callback: MacroCallback,
+    configure: impl FnOnce(MacrosBuilder<'_>) -> MacrosBuilder<'_>,
+  ) -> (String, Vec<MacroError>) {
+    let syntax = Syntax::Es(EsConfig {
+      jsx: true,
+      import_attributes: true,
+      ..Default::default()
+    });
+    transform_syntax(code, syntax, callback, false, configure)
+  }
+
+  /// Like `transform`, but runs the resolver first and passes its unresolved mark to the macros.
+  fn transform_resolved(code: &str, callback: MacroCallback) -> (String, Vec<MacroError>) {
+    let syntax = Syntax::Es(EsConfig {
+      import_attributes: true,
+      ..Default::default()
+    });
+    transform_syntax(code, syntax, callback, true, |macros| macros)
+  }
+
+  fn transform_ts(code: &str, callback: MacroCallback) -> (String, Vec<MacroError>) {
+    transform_syntax(
+      code,
+      Syntax::Typescript(Default::default()),
+      callback,
+      false,
+      |macros| macros,
+    )
+  }
+
+  fn transform_syntax(
+    code: &str,
+    syntax: Syntax,
+    callback: MacroCallback,
+    resolve: bool,
+    configure: impl FnOnce(MacrosBuilder<'_>) -> MacrosBuilder<'_>,
+  ) -> (String, Vec<MacroError>) {
+    let source_map = Lrc::new(SourceMap::default());
+    let source_file = source_map.new_source_file(FileName::Anon, code.into());
+    let lexer = Lexer::new(
+      syntax,
+      Default::default(),
+      StringInput::from(&*source_file),
+      None,
+    );
+
+    let mut parser = Parser::new_from(lexer);
+    let mut module = parser.parse_module().expect("failed to parse");
+    let mut errors = Vec::new();
+    let module = GLOBALS.set(&Globals::new(), || {
+      let mut macros = configure(MacrosBuilder::new(callback, &source_map));
+      if resolve {
+        let unresolved_mark = Mark::new();
+        module.visit_mut_with(&mut resolver(unresolved_mark, Mark::new(), false));
+        macros = macros.unresolved_mark(unresolved_mark);
+      }
+      module.fold_with(&mut macros.build(&mut errors))
+    });
+
+    let mut buf = vec![];
+    let mut emitter = Emitter {
+      cfg: Default::default(),
+      cm: source_map.clone(),
+      comments: None,
+      wr: JsWriter::new(source_map.clone(), "\n", &mut buf, None),
+    };
+    emitter.emit_module(&module).unwrap();
+    (String::from_utf8(buf).unwrap(), errors)
+  }
+
+  fn hint(errors: &[MacroError]) -> Option<&str> {
+    match errors {
+      [MacroError::EvaluationError(_, hint)] => hint.as_deref(),
+      _ => panic!("expected a single evaluation error, got {:?}", errors),
+    }
+  }
+
+  #[test]
+  fn output_diagnostics() {
+    let source = r#"
+      import { old } from "macro" with { type: "macro" };
+      const a = old(1);
+    "#;
+    let (code, errors) = transform(
+      source,
+      Arc::new(|_src, _export, args, _loc| {
+        Ok(MacroOutput {
+          value: args[0].clone(),
+          diagnostics: vec![MacroError::ExecutionWarning(
+            "`old` is deprecated".into(),
+            DUMMY_SP,
+          )],
+        })
+      }),
+    );
+
+    // The call is still replaced, and the warning points at it.
+    assert!(code.contains("const a = 1;"), "{}", code);
+    match &errors[..] {
+      [MacroError::ExecutionWarning(message, span)] => {
+        assert_eq!(message, "`old` is deprecated");
+        // Byte positions start at 1.
+        assert_eq!(span.lo.0 as usize, source.find("old(1)").unwrap() + 1);
+      }
+      _ => panic!("expected a single warning, got {:?}", errors),
+    }
+  }
+
+  #[test]
+  fn debug_arguments() {
+    let code = r#"
+      import { id } from "macro" with { type: "macro" };
+      const config = { name: "a", list: [1, 2] };
+      const a = id(config.name + "b", ...config.list);
+    "#;
+    let (_, errors) = transform(code, identity());
+    assert!(errors.is_empty(), "{:?}", errors);
+
+    let (output, errors) = transform_with(code, identity(), |macros| macros.debug_arguments(true));
+    assert!(output.contains(r#"const a = "ab";"#), "{}", output);
+    let values: Vec<_> = errors
+      .iter()
+      .map(|error| match error {
+        MacroError::ArgumentValue(value, span) => (
+          value.as_str(),
+          &code[span.lo.0 as usize - 1..span.hi.0 as usize - 1],
+        ),
+        _ => panic!("expected argument values, got {:?}", errors),
+      })
+      .collect();
+    assert_eq!(
+      values,
+      [
+        (r#""ab""#, r#"config.name + "b""#),
+        ("[1, 2]", "config.list")
+      ]
+    );
+  }
+
+  #[test]
+  fn trailing_undefined_arguments() {
+    let (code, errors) = transform(
+      r#"
+      import { count } from "macro" with { type: "macro" };
+      const a = count(1);
+      const b = count(1, undefined);
+      const c = count(1, void 0, ...[, ,]);
+      const d = count(undefined, 2);
+      const e = count(...[1, , 3]);
+      "#,
+      Arc::new(|_src, _export, args, _loc| {
+        assert!(!matches!(args.last(), Some(JsValue::Undefined)));
+        Ok(JsValue::Number(args.len() as f64).into())
+      }),
+    );
+
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(code.contains("const a = 1;"), "{}", code);
+    assert!(code.contains("const b = 1;"), "{}", code);
+    assert!(code.contains("const c = 1;"), "{}", code);
+    assert!(code.contains("const d = 2;"), "{}", code);
+    assert!(code.contains("const e = 3;"), "{}", code);
+  }
+
+  #[test]
+  fn spread_arguments() {
+    let (code, errors) = transform(
+      r#"
+      import { count } from "macro" with { type: "macro" };
+      const a = count(...'ab');
+      const b = count(...new Set([1, 1]));
+      const c = count(...new Map([[1, 2]]), 3);
+      "#,
+      Arc::new(|_src, _export, args, _loc| Ok(JsValue::Number(args.len() as f64).into())),
+    );
+
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(code.contains("const a = 2;"), "{}", code);
+    assert!(code.contains("const b = 1;"), "{}", code);
+    assert!(code.contains("const c = 2;"), "{}", code);
+
+    let (_, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      id(...{ a: 1 });
+      "#,
+      identity(),
+    );
+    assert_eq!(
+      hint(&errors),
+      Some("Only arrays, strings, maps, and sets can be spread into macro arguments.")
+    );
+  }
+
+  #[test]
+  fn allowed_sources() {
+    let code = r#"
+      import { id } from "@company/macros" with { type: "macro" };
+      import { other } from "untrusted" with { type: "macro" };
+      const { dynamic } = await import("untrusted/dynamic", { with: { type: "macro" } });
+      const a = id(1);
+      const b = other(2);
+      const c = dynamic(3);
+    "#;
+    let (code, errors) = transform_with(code, identity(), |macros| {
+      macros.allowed_sources(vec!["@company/*".into(), "trusted".into()])
+    });
+
+    assert!(code.contains("const a = 1;"), "{}", code);
+    assert!(code.contains("const b = other(2);"), "{}", code);
+    assert!(code.contains("const c = dynamic(3);"), "{}", code);
+    assert!(!code.contains("untrusted"), "{}", code);
+    assert!(
+      matches!(
+        &errors[..],
+        [MacroError::LoadError(a, _), MacroError::LoadError(b, _)]
+          if a == "`untrusted` is not an allowed macro source"
+            && b == "`untrusted/dynamic` is not an allowed macro source"
+      ),
+      "{:?}",
+      errors
+    );
+  }
+
+  #[test]
+  fn matches_pattern() {
+    assert!(super::matches_pattern("macros", "macros"));
+    assert!(!super::matches_pattern("macros", "macros/a"));
+    assert!(super::matches_pattern("@company/*", "@company/macros"));
+    assert!(!super::matches_pattern("@company/*", "@other/macros"));
+    assert!(super::matches_pattern("*.macro.js", "./a/b.macro.js"));
+    assert!(super::matches_pattern("a*b*c", "abbc"));
+    assert!(!super::matches_pattern("a*b*c", "acb"));
+    assert!(super::matches_pattern("*", ""));
+  }
+
+  #[test]
+  fn stats() {
+    let code = r#"
+      import { id } from "macro" with { type: "macro" };
+      const config = { nested: { name: "a" } };
+      id(config.nested.name, 2);
+      id(config.nested.name);
+      console.log(config.nested.name, config.nested.name);
+      id(config["missing"]);
+    "#;
+    let source_map = Lrc::new(SourceMap::default());
+    let source_file = source_map.new_source_file(FileName::Anon, code.into());
+    let lexer = Lexer::new(
+      Syntax::Es(EsConfig {
+        import_attributes: true,
+        ..Default::default()
+      }),
+      Default::default(),
+      StringInput::from(&*source_file),
+      None,
+    );
+    let module = Parser::new_from(lexer)
+      .parse_module()
+      .expect("failed to parse");
+    let mut errors = Vec::new();
+    let mut macros = MacrosBuilder::new(identity(), &source_map).build(&mut errors);
+    module.fold_with(&mut macros);
+    let stats = macros.stats();
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert_eq!(stats.expanded_macros, 2);
+    assert_eq!(stats.folded_args, 3);
+    // The computed property is evaluated again when reporting the missing property.
+    assert_eq!(stats.eval_cache_hits, 1);
+  }
+
+  #[test]
+  fn builder() {
+    let source_map = SourceMap::default();
+    let mut errors = Vec::new();
+    let macros = MacrosBuilder::new(identity(), &source_map)
+      .documentation_url("https://example.com/macros")
+      .build_time(1000.0)
+      .build(&mut errors);
+
+    assert_eq!(macros.documentation_url(), "https://example.com/macros");
+    assert_eq!(
+      macros.eval(&parse_expr(&source_map, "Date.now() + 1")),
+      Ok(JsValue::Number(1001.0))
+    );
+  }
+
+  #[test]
+  fn process_env() {
+    let code = r#"
+      import { id } from "macro" with { type: "macro" };
+      id(process.env.NODE_ENV);
+      id(process.env["NODE_" + "ENV"] === "production");
+      id(process.env.MISSING);
+    "#;
+    let env = || HashMap::from([("NODE_ENV".to_string(), JsValue::String("production".into()))]);
+
+    let (output, errors) = transform_with(code, identity(), |macros| macros.env(env()));
+    assert!(output.contains(r#""production";"#));
+    assert!(output.contains("true;"));
+    assert!(matches!(&errors[..], [MacroError::EvaluationError(..)]));
+
+    let (output, errors) = transform_with(code, identity(), |macros| {
+      macros.env(env()).missing_env_undefined(true)
+    });
+    assert!(errors.is_empty());
+    assert!(output.contains("undefined;"));
+
+    // Without an env, process.env is not evaluated.
+    let (_, errors) = transform(code, identity());
+    assert_eq!(errors.len(), 3);
+  }
+
+  #[test]
+  fn import_meta_env() {
+    let code = r#"
+      import { id } from "macro" with { type: "macro" };
+      id(import.meta.env.MODE);
+      id(`mode: ${import.meta.env["MODE"]}`);
+    "#;
+    let (output, errors) = transform_with(code, identity(), |macros| {
+      macros.env(HashMap::from([(
+        "MODE".to_string(),
+        JsValue::String("development".into()),
+      )]))
+    });
+    assert!(errors.is_empty());
+    assert!(output.contains(r#""development";"#));
+    assert!(output.contains(r#""mode: development";"#));
+
+    let (_, errors) = transform(code, identity());
+    assert_eq!(errors.len(), 2);
+  }
+
+  #[test]
+  fn typeof_function_constant() {
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const obj = { fn() {} };
+      id(typeof obj.fn);
+      id(typeof window);
+      "#,
+      identity(),
+    );
+
+    assert!(code.contains(r#""function";"#));
+    assert!(hint(&errors)
+      .unwrap()
+      .contains("may only be defined at runtime"));
+  }
+
+  #[test]
+  fn optional_call() {
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const obj = { get() { return 42; }, add(a) { return a + 1; } };
+      const nothing = null;
+      const a = id(nothing?.());
+      const b = id(obj.get?.());
+      const c = id((() => "c")?.());
+      id(obj.add?.(1));
+      "#,
+      identity(),
+    );
+
+    assert!(code.contains("const a = undefined;"), "{}", code);
+    assert!(code.contains("const b = 42;"), "{}", code);
+    assert!(code.contains(r#"const c = "c";"#), "{}", code);
+    assert_eq!(
+      hint(&errors),
+      Some(
+        "Only functions without parameters that return a value can be called in macro arguments."
+      )
+    );
+  }
+
+  #[test]
+  fn bigint_comparison() {
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      id([2n > 1, 1n == 1, 1n === 1]);
+      id(-12345678901234567890n);
+      "#,
+      identity(),
+    );
+
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(
+      code.contains("[\n    true,\n    true,\n    false\n]"),
+      "{}",
+      code
+    );
+    assert!(code.contains("-12345678901234567890n"), "{}", code);
+  }
+
+  #[test]
+  fn max_string_length() {
+    let (code, errors) = transform_with(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      id("ab".repeat(3));
+      id("x".repeat(1e9));
+      "#,
+      identity(),
+      |macros| macros.max_string_length(100),
+    );
+
+    assert!(code.contains(r#""ababab";"#), "{}", code);
+    assert_eq!(
+      hint(&errors),
+      Some("Strings in macro arguments are limited to 100 bytes.")
+    );
+
+    let (_, errors) = transform_with(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const a = "x".repeat(60);
+      id(`${a}${a}`);
+      "#,
+      identity(),
+      |macros| macros.max_string_length(100),
+    );
+    assert!(
+      matches!(&errors[..], [MacroError::EvaluationError(_, Some(hint))] if hint.contains("limited to 100"))
+    );
+  }
+
+  #[test]
+  fn max_array_length() {
+    let (code, errors) = transform_with(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      id(new Array(2));
+      id(new Array(4294967294));
+      "#,
+      identity(),
+      |macros| macros.max_array_length(100),
+    );
+
+    assert!(
+      code.contains("[\n    undefined,\n    undefined\n]"),
+      "{}",
+      code
+    );
+    assert_eq!(
+      hint(&errors),
+      Some("Arrays in macro arguments are limited to 100 elements.")
+    );
+  }
+
+  #[test]
+  fn typeof_undeclared() {
+    let (code, errors) = transform_resolved(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      id(typeof UNDECLARED);
+      id(typeof UNDECLARED === "undefined" ? "a" : "b");
+      "#,
+      identity(),
+    );
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(code.contains(r#""undefined";"#), "{}", code);
+    assert!(code.contains(r#""a";"#), "{}", code);
+
+    let (_, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      id(typeof UNDECLARED);
+      "#,
+      identity(),
+    );
+    assert_eq!(
+      hint(&errors),
+      Some("`typeof` of a global cannot be evaluated at build time, since it may only be defined at runtime.")
+    );
+  }
+
+  #[test]
+  fn typeof_local() {
+    let code = r#"
+      import { id } from "macro" with { type: "macro" };
+      function f(x) {
+        return id(typeof x);
+      }
+    "#;
+    let (_, errors) = transform(code, identity());
+    assert!(
+      matches!(&errors[..], [MacroError::EvaluationError(..)]),
+      "{:?}",
+      errors
+    );
+    let (_, errors) = transform_resolved(code, identity());
+    assert_eq!(
+      hint(&errors),
+      Some("`x` is not a constant that can be evaluated at build time.")
+    );
+
+    let (_, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      let x = foo();
+      id(typeof x);
+      "#,
+      identity(),
+    );
+    assert_eq!(
+      hint(&errors),
+      Some("`x` is not a constant that can be evaluated at build time.")
+    );
+  }
+
+  #[test]
+  fn global_types() {
+    let code = r#"
+      import { id } from "macro" with { type: "macro" };
+      id(typeof window !== "undefined" ? "browser" : "server");
+      id(typeof document);
+    "#;
+    let global_types = |window: &str| {
+      HashMap::from([
+        ("window".to_string(), window.to_string()),
+        ("document".to_string(), window.to_string()),
+      ])
+    };
+
+    let (code_browser, errors) = transform_with(code, identity(), |macros| {
+      macros.global_types(global_types("object"))
+    });
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(code_browser.contains(r#""browser";"#), "{}", code_browser);
+    assert!(code_browser.contains(r#""object";"#), "{}", code_browser);
+
+    let (code_server, errors) = transform_with(code, identity(), |macros| {
+      macros.global_types(global_types("undefined"))
+    });
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(code_server.contains(r#""server";"#), "{}", code_server);
+    assert!(code_server.contains(r#""undefined";"#), "{}", code_server);
+
+    // A local binding shadowing the global is not affected.
+    let (_, errors) = transform_with(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      let window = getWindow();
+      id(typeof window);
+      "#,
+      identity(),
+      |macros| macros.global_types(global_types("object")),
+    );
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+  }
+
+  #[test]
+  fn untaken_branches_not_evaluated() {
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const DEBUG = false;
+      id(DEBUG ? undeclared.value : "release");
+      id(!DEBUG || undeclared());
+      id(DEBUG && undeclared);
+      "#,
+      identity(),
+    );
+
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(code.contains(r#""release";"#));
+    assert!(code.contains("true;"));
+    assert!(code.contains("false;"));
+  }
+
+  #[test]
+  fn delete_constant_property() {
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const obj = { a: 1, b: 2 };
+      delete obj.a;
+      id(obj);
+      id((delete { a: 1 }.a, "ok"));
+      "#,
+      identity(),
+    );
+
+    assert!(code.contains(r#""ok";"#));
+    assert!(matches!(&errors[..], [MacroError::MutationError(..)]));
+  }
+
+  #[test]
+  fn tagged_template_macro() {
+    let (code, errors) = transform(
+      r#"
+      import { css } from "macro" with { type: "macro" };
+      import * as ns from "macro" with { type: "macro" };
+      const COLOR = "red";
+      css`.foo { color: ${COLOR}; }`;
+      ns.css`plain`;
+      other`.foo { color: ${COLOR}; }`;
+      "#,
+      Arc::new(|_src, export, args, _loc| {
+        assert_eq!(export, "css");
+        match &args[..] {
+          [JsValue::Array(strings), values @ ..] => {
+            let mut res = String::new();
+            for (i, s) in strings.iter().enumerate() {
+              res.push_str(&s.to_js_string());
+              if let Some(v) = values.get(i) {
+                res.push_str(&v.to_js_string());
+              }
+            }
+            Ok(JsValue::String(res).into())
+          }
+          _ => panic!("unexpected arguments {:?}", args),
+        }
+      }),
+    );
+
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(code.contains(r#"".foo { color: red; }";"#));
+    assert!(code.contains(r#""plain";"#));
+    assert!(code.contains("other`.foo { color: ${COLOR}; }`;"));
+  }
+
+  #[test]
+  fn macro_in_jsx() {
+    let (code, errors) = transform(
+      r#"
+      import { asset } from "macro" with { type: "macro" };
+      import * as ns from "macro" with { type: "macro" };
+      <div>
+        <img src={asset("./x.png")} />
+        {ns.asset("child")}
+      </div>;
+      "#,
+      Arc::new(|_src, _export, args, _loc| match &args[..] {
+        [JsValue::String(s)] => Ok(JsValue::String(format!("/dist/{}", s)).into()),
+        _ => panic!("unexpected arguments {:?}", args),
+      }),
+    );
+
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(code.contains(r#"src={"/dist/./x.png"}"#), "{}", code);
+    assert!(code.contains(r#"{"/dist/child"}"#), "{}", code);
+    assert!(!code.contains("asset("));
+  }
+
+  #[test]
+  fn return_regex() {
+    let (code, errors) = transform(
+      r#"
+      import { re } from "macro" with { type: "macro" };
+      re("a/b", "yig");
+      re("a\\/b", "g");
+      re("", "");
+      re("a", "gx");
+      "#,
+      Arc::new(|_src, _export, args, _loc| match &args[..] {
+        [JsValue::String(source), JsValue::String(flags)] => Ok(
+          JsValue::Regex {
+            source: source.clone(),
+            flags: flags.clone(),
+          }
+          .into(),
+        ),
+        _ => panic!("unexpected arguments {:?}", args),
+      }),
+    );
+
+    assert!(code.contains(r"/a\/b/giy;"), "{}", code);
+    assert!(code.contains(r"/a\/b/g;"), "{}", code);
+    assert!(code.contains("/(?:)/;"), "{}", code);
+    assert!(matches!(
+      &errors[..],
+      [MacroError::ExecutionError(message, _)] if message.contains(r#"invalid flags "gx""#)
+    ));
+  }
+
+  #[test]
+  fn map_argument() {
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const m = new Map([["a", 1], [{ b: true }, [2]]]);
+      id(m);
+      id(m.get("a"), m.size);
+      "#,
+      identity(),
+    );
+
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(
+      code.contains(
+        r#"new Map([
+    [
+        "a",
+        1
+    ],
+    [
+        {
+            b: true
+        },
+        [
+            2
+        ]
+    ]
+]);"#
+      ),
+      "{}",
+      code
+    );
+    assert!(code.contains("\n1;"), "{}", code);
+  }
+
+  #[test]
+  fn returned_function_result() {
+    let (code, errors) = transform(
+      r#"
+      import { gen, iife, id } from "macro" with { type: "macro" };
+      const a = id(gen()());
+      const b = id(iife());
+      "#,
+      Arc::new(|_src, export, args, _loc| {
+        Ok(
+          match export.as_str() {
+            "gen" => JsValue::Function("function () { return { x: 1, y: 2 }; }".into()),
+            "iife" => JsValue::Function("(() => [1, 2, 3])()".into()),
+            _ => args[0].clone(),
+          }
+          .into(),
+        )
+      }),
+    );
+
+    // Every node of the returned source has the span of the macro call, so results must
+    // not be shared between them.
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(code.contains("x: 1,\n    y: 2"), "{}", code);
+    assert!(code.contains("1,\n    2,\n    3"), "{}", code);
+  }
+
+  #[test]
+  fn date_result() {
+    let (code, errors) = transform(
+      r#"
+      import { buildDate, id } from "macro" with { type: "macro" };
+      const a = buildDate();
+      const b = id(new Date(0));
+      "#,
+      Arc::new(|_src, export, args, _loc| {
+        if export == "buildDate" {
+          return Ok(JsValue::Date(1700000000000.0).into());
+        }
+        assert_eq!(args, [JsValue::Date(0.0)]);
+        Ok(args[0].clone().into())
+      }),
+    );
+
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(
+      code.contains("const a = new Date(1700000000000);"),
+      "{}",
+      code
+    );
+    assert!(code.contains("const b = new Date(0);"), "{}", code);
+  }
+
+  #[test]
+  fn set_argument() {
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const s = new Set(["a", "b", "a"]);
+      id(s);
+      id(s.has("b"));
+      id(s.size);
+      "#,
+      identity(),
+    );
+
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(
+      code.contains(
+        r#"new Set([
+    "a",
+    "b"
+]);"#
+      ),
+      "{}",
+      code
+    );
+    assert!(code.contains("true;"), "{}", code);
+    assert!(code.contains("2;"), "{}", code);
+  }
+
+  #[test]
+  fn boolean_predicate_constant() {
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const isTruthy = Boolean;
+      id([1, "a"].every(isTruthy));
+      id([1, "a"].some((x) => x));
+      "#,
+      identity(),
+    );
+
+    assert!(code.contains("true;"), "{}", code);
+    assert!(
+      matches!(&errors[..], [MacroError::EvaluationError(..)]),
+      "{:?}",
+      errors
+    );
+  }
+
+  #[test]
+  fn returned_function_not_expanded() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let callback_calls = calls.clone();
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      id(1);
+      "#,
+      Arc::new(move |_src, _export, _args, _loc| {
+        callback_calls.fetch_add(1, Ordering::SeqCst);
+        Ok(JsValue::Array(vec![JsValue::Function("() => id(2)".into())]).into())
+      }),
+    );
+
+    assert!(errors.is_empty());
+    assert!(code.contains("()=>id(2)"), "{}", code);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+  }
+
+  #[test]
+  fn execution_warning() {
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      id(1);
+      "#,
+      Arc::new(|_src, _export, _args, _loc| {
+        Err(MacroError::ExecutionWarning("deprecated".into(), DUMMY_SP))
+      }),
+    );
+
+    assert!(code.contains("id(1)"));
+    assert!(matches!(
+      &errors[..],
+      [MacroError::ExecutionWarning(message, _)] if message == "deprecated"
+    ));
+  }
+
+  #[test]
+  fn let_never_reassigned() {
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      let x = 1;
+      var y = x + 1;
+      id(y);
+      "#,
+      identity(),
+    );
+
+    assert!(errors.is_empty());
+    assert!(code.contains("2;"));
+  }
+
+  #[test]
+  fn let_reassigned() {
+    let (_, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      let x = 1;
+      function update() {
+        x = 2;
+      }
+      id(x);
+      "#,
+      identity(),
+    );
+
+    assert!(matches!(&errors[..], [MacroError::EvaluationError(..)]));
+  }
+
+  #[test]
+  fn function_spans_remapped() {
+    struct Spans(Vec<Span>);
+    impl Visit for Spans {
+      fn visit_span(&mut self, span: &Span) {
+        self.0.push(*span);
+      }
+    }
+
+    let source_map = SourceMap::default();
+    let mut errors = Vec::new();
+    let macros = Macros::new(identity(), &source_map, &mut errors);
+    let span = Span::new(BytePos(10), BytePos(20), Default::default());
+    let expr = macros
+      .value_to_expr(JsValue::Function("(a) => a + 1".into()), span)
+      .unwrap();
+
+    let mut spans = Spans(Vec::new());
+    expr.visit_with(&mut spans);
+    assert!(!spans.0.is_empty());
+    assert!(spans.0.iter().all(|s| s.lo == span.lo && s.hi == span.hi));
+  }
+
+  #[test]
+  fn number_raw() {
+    let source_map = SourceMap::default();
+    let mut errors = Vec::new();
+    let macros = Macros::new(identity(), &source_map, &mut errors);
+    let raw = |n: f64| match macros.value_to_expr(JsValue::Number(n), DUMMY_SP) {
+      Ok(Expr::Lit(Lit::Num(num))) => num.raw.map(|raw| raw.to_string()),
+      expr => panic!("expected a number, got {:?}", expr),
+    };
+
+    assert_eq!(raw(0.1 + 0.2).as_deref(), Some("0.30000000000000004"));
+    assert_eq!(raw(1e21).as_deref(), Some("1e+21"));
+    assert_eq!(
+      raw(2f64.powi(53) + 2.0).as_deref(),
+      Some("9007199254740994")
+    );
+    assert_eq!(raw(1.5e-7).as_deref(), Some("1.5e-7"));
+    assert_eq!(raw(-1.0), None);
+    assert_eq!(raw(f64::NAN), None);
+
+    let (code, _) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      id(0.1 + 0.2);
+      "#,
+      identity(),
+    );
+    assert!(code.contains("0.30000000000000004;"), "{}", code);
+  }
+
+  #[test]
+  fn object_key_order() {
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const obj = id({ zebra: 1, "a-b": 2, apple: 3, "1x": 4, $ok: 5, "": 6 });
+      "#,
+      identity(),
+    );
+
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(
+      code.contains(
+        r#"const obj = {
+    zebra: 1,
+    "a-b": 2,
+    apple: 3,
+    "1x": 4,
+    $ok: 5,
+    "": 6
+};"#
+      ),
+      "{}",
+      code
+    );
+  }
+
+  #[test]
+  fn missing_property() {
+    let (_, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const config = { a: { b: 1 } };
+      id(config.a.c);
+      id(config["x" + "y"]);
+      id(config.a.b.c);
+      id(unknown.a);
+      "#,
+      identity(),
+    );
+
+    assert_eq!(errors.len(), 4, "{:?}", errors);
+    assert!(matches!(&errors[0], MacroError::MissingProperty(prop, _) if prop == "c"));
+    assert!(matches!(&errors[1], MacroError::MissingProperty(prop, _) if prop == "xy"));
+    assert!(matches!(&errors[2], MacroError::EvaluationError(..)));
+    assert!(matches!(&errors[3], MacroError::EvaluationError(..)));
+  }
+
+  #[test]
+  fn dynamic_import() {
+    let (code, errors) = transform(
+      r#"
+      const { hash, default: named } = await import("macro", { with: { type: "macro" } });
+      hash("a");
+      named(1);
+      "#,
+      Arc::new(|src, export, args, _loc| {
+        assert_eq!(src, "macro");
+        Ok(JsValue::String(format!("{}:{}", export, args[0].to_js_string())).into())
+      }),
+    );
+
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(!code.contains("import("), "{}", code);
+    assert!(code.contains(r#""hash:a";"#), "{}", code);
+    assert!(code.contains(r#""default:1";"#), "{}", code);
+
+    let (code, errors) = transform(
+      r#"
+      async function load() {
+        const { hash } = await import("macro", { with: { type: "macro" } });
+        return hash("a");
+      }
+      let m = import("other", { with: { type: "macro" } });
+      import("other");
+      "#,
+      identity(),
+    );
+
+    assert_eq!(errors.len(), 2, "{:?}", errors);
+    assert!(errors.iter().all(|err| matches!(
+      err,
+      MacroError::LoadError(message, _) if message.contains("at the top level of a module")
+    )));
+    assert!(code.contains(r#"import("other");"#), "{}", code);
+  }
+
+  #[test]
+  fn mutated_constant() {
+    let (_, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const obj = { a: 1 };
+      obj.a = 2;
+      id(obj);
+      "#,
+      identity(),
+    );
+
+    match &errors[..] {
+      [MacroError::MutationError(mutation, usage, None)] => assert!(mutation.hi < usage.lo),
+      _ => panic!("expected a mutation error, got {:?}", errors),
+    }
+  }
+
+  #[test]
+  fn mutated_constant_compound_assignment() {
+    for op in ["+=", "??=", "||=", "&&=", "**="] {
+      let (_, errors) = transform(
+        &format!(
+          r#"
+          import {{ id }} from "macro" with {{ type: "macro" }};
+          const obj = {{ a: 1 }};
+          obj.a {} 2;
+          id(obj);
+          "#,
+          op
+        ),
+        identity(),
+      );
+
+      assert!(
+        matches!(&errors[..], [MacroError::MutationError(..)]),
+        "expected a mutation error for {}, got {:?}",
+        op,
+        errors
+      );
+    }
+  }
+
+  #[test]
+  fn errors_sorted_by_location() {
+    let (_, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      id(foo(), id(bar));
+      "#,
+      identity(),
+    );
+
+    assert_eq!(errors.len(), 2);
+    assert!(errors[0].span().lo < errors[1].span().lo);
+  }
+
+  #[test]
+  fn imported_constant() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let resolver_calls = calls.clone();
+    let (code, errors) = transform_with(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      import { COLOR, OTHER } from "./theme";
+      id(COLOR);
+      id(COLOR + "!");
+      id(OTHER);
+      id(OTHER);
+      "#,
+      identity(),
+      |macros| {
+        macros.constant_resolver(Arc::new(move |src, name| {
+          resolver_calls.fetch_add(1, Ordering::SeqCst);
+          match (src, name) {
+            ("./theme", "COLOR") => Some(JsValue::String("red".into())),
+            _ => None,
+          }
+        }))
+      },
+    );
+
+    assert_eq!(errors.len(), 2);
+    assert!(code.contains(r#""red";"#));
+    assert!(code.contains(r#""red!";"#));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+  }
+
+  #[test]
+  fn computed_member_constant_index() {
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const ARR = [10, 20, 30];
+      const OBJ = { prefix: "yes" };
+      const I = 1;
+      id(ARR[I + 1]);
+      id(OBJ["pre" + "fix"]);
+      id(ARR[I - 2]);
+      "#,
+      identity(),
+    );
+
+    assert!(code.contains("30;"));
+    assert!(code.contains(r#""yes";"#));
+    assert!(!code.contains("10;"));
+    assert_eq!(errors.len(), 1);
+  }
+
+  #[test]
+  fn destructure_number_keys() {
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const { 1.5: a, 1e21: b, ...rest } = { "1.5": "x", "1e+21": "y", 2: "z" };
+      id(a + b);
+      id(rest);
+      "#,
+      identity(),
+    );
+
+    assert!(errors.is_empty());
+    assert!(code.contains(r#""xy";"#));
+    assert!(code.contains("{\n    \"2\": \"z\"\n};"));
+  }
+
+  #[test]
+  fn object_with_method() {
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const OBJ = { name: "test", greet(a) { return a; } };
+      id(OBJ.name);
+      id(OBJ.greet);
+      id(OBJ);
+      id(OBJ.greet("x"));
+      "#,
+      identity(),
+    );
+
+    assert!(code.contains(r#""test";"#));
+    assert_eq!(errors.len(), 3);
+    assert!(matches!(
+      &errors[0],
+      MacroError::EvaluationError(_, Some(hint)) if hint.contains("Functions cannot be passed")
+    ));
+    assert!(matches!(
+      &errors[1],
+      MacroError::EvaluationError(_, Some(_))
+    ));
+    assert!(matches!(&errors[2], MacroError::EvaluationError(_, _)));
+  }
+
+  #[test]
+  fn destructure_computed_constant_key() {
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const KEY = "a";
+      const { [KEY]: v, [KEY + "b"]: w } = { a: 1, ab: 2 };
+      id(v + w);
+      "#,
+      identity(),
+    );
+
+    assert!(errors.is_empty());
+    assert!(code.contains("3;"));
+  }
+
+  #[test]
+  fn destructure_default_values() {
+    let source = r#"
+      import { id } from "macro" with { type: "macro" };
+      const [a = "default a", b = "default b", c = "default c"] = ["present a", undefined];
+      id(a);
+      id(b);
+      id(c);
+      const { d = "default d", e: f = "default f", g = "default g" } = { d: "present d", e: undefined };
+      id(d);
+      id(f);
+      id(g);
+      const [h = 1] = [foo];
+      id(h);
+      const { i = 1 } = { i: foo };
+      id(i);
+      const { j: k = 1 } = { j: foo };
+      id(k);
+      "#;
+    let (code, errors) = transform(source, identity());
+
+    assert!(code.contains(r#""present a";"#));
+    assert!(code.contains(r#""default b";"#));
+    assert!(code.contains(r#""default c";"#));
+    assert!(code.contains(r#""present d";"#));
+    assert!(code.contains(r#""default f";"#));
+    assert!(code.contains(r#""default g";"#));
+    // Present elements that cannot be evaluated are errors, rather than using the default.
+    assert_eq!(errors.len(), 3);
+    for err in &errors {
+      let span = err.span();
+      assert_eq!(
+        &source[span.lo.0 as usize - 1..span.hi.0 as usize - 1],
+        "foo"
+      );
+    }
+  }
+
+  #[test]
+  fn destructure_rest_after_number_keys() {
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const { 0: a, [1]: b, [true]: c, ...rest } = { "0": "a", "1": "b", "true": "c", 2: "d" };
+      id(a + b + c);
+      id(rest);
+      "#,
+      identity(),
+    );
+
+    assert!(errors.is_empty());
+    assert!(code.contains(r#""abc";"#));
+    assert!(code.contains("{\n    \"2\": \"d\"\n};"));
+  }
+
+  #[test]
+  fn typescript_assertions() {
+    let (code, errors) = transform_ts(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const x = { a: [1, 2] } as const;
+      const y: string | undefined = "y";
+      id((x as const).a);
+      id(y!);
+      id(<number>x.a[0] satisfies number);
+      "#,
+      identity(),
+    );
+
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(code.contains("[\n    1,\n    2\n];"));
+    assert!(code.contains(r#""y";"#));
+    assert!(code.contains("1;"));
+  }
+
+  #[test]
+  fn class_members() {
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const NAME = "a";
+      class A {
+        name = id(NAME + "b");
+        static count = id(1 + 1);
+        #secret = id(3);
+      }
+      "#,
+      identity(),
+    );
+
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(code.contains(r#"name = "ab";"#), "{}", code);
+    assert!(code.contains("static count = 2;"), "{}", code);
+    assert!(code.contains("#secret = 3;"), "{}", code);
+  }
+
+  #[test]
+  fn decorators() {
+    let (code, errors) = transform_syntax(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const TAG = "x-";
+      @register(id(TAG + "element"))
+      class A {
+        @observe(id({ deep: true }))
+        value = 1;
+        @bind(id(2)) method() {}
+      }
+      "#,
+      Syntax::Es(EsConfig {
+        decorators: true,
+        import_attributes: true,
+        ..Default::default()
+      }),
+      identity(),
+      false,
+      |macros| macros,
+    );
+
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(code.contains(r#"@register("x-element")"#), "{}", code);
+    assert!(code.contains("@observe({"), "{}", code);
+    assert!(code.contains("deep: true"), "{}", code);
+    assert!(code.contains("@bind(2)"), "{}", code);
+  }
+
+  #[test]
+  fn typescript_enums() {
+    let (code, errors) = transform_ts(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      enum Direction { Up, Down, Left = 10, Right }
+      const enum Color { Red = "RED", Green = "GREEN" }
+      enum Flags { A = 1 << 0, B = 1 << 1, AB = Flags.A | Flags.B }
+      const a = id(Direction.Down);
+      const b = id(Direction.Right);
+      const c = id(Color.Green);
+      const d = id(Color["Red"]);
+      const e = id(Flags.AB);
+      const f = id(Direction);
+      "#,
+      identity(),
+    );
+
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(code.contains("const a = 1;"), "{}", code);
+    assert!(code.contains("const b = 11;"), "{}", code);
+    assert!(code.contains(r#"const c = "GREEN";"#), "{}", code);
+    assert!(code.contains(r#"const d = "RED";"#), "{}", code);
+    assert!(code.contains("const e = 3;"), "{}", code);
+    assert!(
+      code.contains("const f = {\n    Up: 0,\n    Down: 1,\n    Left: 10,\n    Right: 11\n};"),
+      "{}",
+      code
+    );
+
+    // Members without an initializer cannot follow string members.
+    let (_, errors) = transform_ts(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      enum Mixed { A = "a", B }
+      id(Mixed.A);
+      "#,
+      identity(),
+    );
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+  }
+
+  #[test]
+  fn const_assertion_not_mutated_by_calls() {
+    let (code, errors) = transform_ts(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const X = { a: [1, 2] } as const;
+      const { a } = { a: ["b"] } as const satisfies object;
+      console.log(X, X.a, a);
+      id(X.a[1]);
+      id(a);
+      const Y = { a: 1 } as const;
+      Y.a = 2;
+      id(Y);
+      "#,
+      identity(),
+    );
+
+    assert!(code.contains("2;"));
+    assert!(code.contains("[\n    \"b\"\n];"));
+    // Explicit mutations are still errors.
+    assert!(matches!(&errors[..], [MacroError::MutationError(..)]));
+  }
+
+  fn parse_expr(source_map: &SourceMap, code: &str) -> Box<Expr> {
+    let source_file = source_map.new_source_file(FileName::Anon, code.into());
+    let lexer = Lexer::new(
+      Default::default(),
+      Default::default(),
+      StringInput::from(&*source_file),
+      None,
+    );
+    Parser::new_from(lexer)
+      .parse_expr()
+      .expect("failed to parse")
+  }
+
+  #[test]
+  fn eval_cache_invalidated() {
+    let source_map = SourceMap::default();
+    let mut errors = Vec::new();
+    let mut macros = Macros::new(identity(), &source_map, &mut errors);
+    let expr = parse_expr(&source_map, "obj.a");
+    let id = (JsWord::from("obj"), Default::default());
+    macros.set_constant(
+      id,
+      Ok(JsValue::Object(IndexMap::from([(
+        "a".to_string(),
+        JsValue::Number(1.0),
+      )]))),
+    );
+    assert!(matches!(macros.eval(&expr), Ok(JsValue::Number(n)) if n == 1.0));
+
+    // Mutating the constant must invalidate the cached result.
+    parse_expr(&source_map, "obj.a = 2").fold_with(&mut macros);
+    assert!(macros.eval(&expr).is_err());
+  }
+
+  #[test]
+  fn nested_constant_access() {
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const A = { b: { c: { d: [1, { e: "deep" }] } } };
+      id(A.b.c.d[1].e);
+      id(A?.b["c"].d[0]);
+      id(A.b.c.d[1].missing);
+      "#,
+      identity(),
+    );
+
+    assert!(code.contains(r#""deep";"#));
+    assert!(code.contains("1;"));
+    assert_eq!(errors.len(), 1);
+  }
+
+  fn mutation_description(code: &str) -> Option<String> {
+    let (_, errors) = transform(code, identity());
+    match &errors[..] {
+      [MacroError::MutationError(_, _, description)] => description.clone(),
+      _ => panic!("expected a single mutation error, got {:?}", errors),
+    }
+  }
+
+  #[test]
+  fn mutating_array_methods() {
+    for method in ["push(4)", "splice(0, 1)", "sort()"] {
+      let code = format!(
+        r#"
+        import {{ id }} from "macro" with {{ type: "macro" }};
+        const arr = [3, 1, 2];
+        arr.{};
+        id(arr);
+        "#,
+        method
+      );
+      let name = &method[..method.find('(').unwrap()];
+      assert_eq!(
+        mutation_description(&code),
+        Some(format!("constant array mutated via `{}`", name))
+      );
+    }
+  }
+
+  #[test]
+  fn non_mutating_array_method() {
+    let (code, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const arr = [1, 2];
+      console.log(arr[0]);
+      id(arr);
+      "#,
+      identity(),
+    );
+
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert!(code.contains("console.log(arr[0]);"));
+  }
+
+  #[test]
+  fn spread_constant_into_call() {
+    let (_, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const arr = [1, 2];
+      foo(...arr);
+      id(arr);
+      "#,
+      identity(),
+    );
+
+    assert!(
+      matches!(&errors[..], [MacroError::MutationError(mutation, usage, None)] if mutation.hi < usage.lo),
+      "{:?}",
+      errors
+    );
+  }
+
+  #[test]
+  fn hint_non_const_binding() {
+    let (_, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      let x = 1;
+      x++;
+      id(x);
+      "#,
+      identity(),
+    );
+
+    assert_eq!(
+      hint(&errors),
+      Some("Declare `x` with `const` so it can be evaluated at build time.")
+    );
+  }
+
+  #[test]
+  fn hint_var_binding() {
+    let (_, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      var { x } = { x: 1 };
+      x = 2;
+      id(x);
+      "#,
+      identity(),
+    );
+
+    assert_eq!(
+      hint(&errors),
+      Some("Declare `x` with `const` so it can be evaluated at build time.")
+    );
+  }
+
+  #[test]
+  fn hint_unknown_binding() {
+    let (_, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      id(x);
+      "#,
+      identity(),
+    );
+
+    assert_eq!(
+      hint(&errors),
+      Some("`x` is not a constant that can be evaluated at build time.")
+    );
+  }
+
+  #[test]
+  fn hint_unsupported_method() {
+    let (_, errors) = transform(
+      r#"
+      import { id } from "macro" with { type: "macro" };
+      const x = [1, 2, 3];
+      id(x.flat());
+      "#,
+      identity(),
+    );
+
+    assert_eq!(
+      hint(&errors),
+      Some("The `flat` method is not supported in macro arguments.")
+    );
+  }
+}
@@ -1,13 +1,14 @@
 use std::sync::Arc;
 
-use crate::{JsValue, Location, MacroCallback, MacroError};
+use crate::{JsValue, Location, MacroCallback, MacroError, MacroOutput};
 use crossbeam_channel::{Receiver, Sender};
 use indexmap::IndexMap;
 use napi::{
   threadsafe_function::{ThreadSafeCallContext, ThreadsafeFunctionCallMode},
-  Env, JsBoolean, JsFunction, JsNumber, JsObject, JsString, JsUnknown, ValueType,
+  Env, JsBoolean, JsDate, JsFunction, JsNumber, JsObject, JsString, JsUnknown, ValueType,
 };
 use napi_derive::napi;
+use num_bigint::BigInt as BigIntValue;
 use swc_core::common::DUMMY_SP;
 
 struct CallMacroMessage {
@@ -17,6 +18,8 @@ struct CallMacroMessage {
   loc: Location,
 }
 
+/// An error thrown by the JS callback. The kind is 1 when the macro could not be loaded,
+/// 2 when it threw, and 3 when it threw an error with a `severity` of "warning".
 #[napi(object)]
 struct JsMacroError {
   pub kind: u32,
@@ -25,7 +28,7 @@ struct JsMacroError {
 
 // Allocate a single channel per thread to communicate with the JS thread.
 thread_local! {
-  static CHANNEL: (Sender<Result<JsValue, MacroError>>, Receiver<Result<JsValue, MacroError>>) = crossbeam_channel::unbounded();
+  static CHANNEL: (Sender<Result<MacroOutput, MacroError>>, Receiver<Result<MacroOutput, MacroError>>) = crossbeam_channel::unbounded();
 }
 
 /// Creates a macro callback from a JS function.
@@ -100,6 +103,25 @@ fn js_value_to_napi(value: JsValue, env: Env) -> napi::Result<napi::JsUnknown> {
       }
       Ok(res.into_unknown())
     }
+    JsValue::Map(entries) => {
+      let map_class: JsFunction = env.get_global()?.get_named_property("Map")?;
+      let entries = entries
+        .into_iter()
+        .map(|(k, v)| JsValue::Array(vec![k, v]))
+        .collect();
+      let entries = js_value_to_napi(JsValue::Array(entries), env)?;
+      Ok(map_class.new_instance(&[entries])?.into_unknown())
+    }
+    JsValue::Set(values) => {
+      let set_class: JsFunction = env.get_global()?.get_named_property("Set")?;
+      let values = js_value_to_napi(JsValue::Array(values), env)?;
+      Ok(set_class.new_instance(&[values])?.into_unknown())
+    }
+    JsValue::Date(time) => Ok(env.create_date(time)?.into_unknown()),
+    JsValue::BigInt(n) => {
+      let bigint: JsFunction = env.get_global()?.get_named_property("BigInt")?;
+      bigint.call(None, &[env.create_string(&n.to_string())?])
+    }
     JsValue::Function(_) => {
       // Functions can only be returned from macros, not passed in.
       unreachable!()
@@ -123,6 +145,9 @@ fn napi_to_js_value(value: napi::JsUnknown, env: Env) -> napi::Result<JsValue> {
         .into_utf8()?
         .into_owned()?,
     )),
+    ValueType::Object if value.is_date()? => {
+      Ok(JsValue::Date(unsafe { value.cast::<JsDate>() }.value_of()?))
+    }
     ValueType::Object => {
       let obj = unsafe { value.cast::<JsObject>() };
       if obj.is_array()? {
@@ -144,6 +169,40 @@ fn napi_to_js_value(value: napi::JsUnknown, env: Env) -> napi::Result<JsValue> {
           });
         }
 
+        let map_class: JsFunction = env.get_global()?.get_named_property("Map")?;
+        if obj.instanceof(map_class)? {
+          // Convert to an array of [key, value] entries.
+          let array_class: JsObject = env.get_global()?.get_named_property("Array")?;
+          let from: JsFunction = array_class.get_named_property("from")?;
+          let entries = from.call(Some(&array_class), &[obj])?;
+          return match napi_to_js_value(entries, env)? {
+            JsValue::Array(entries) => Ok(JsValue::Map(
+              entries
+                .into_iter()
+                .map(|entry| match entry {
+                  JsValue::Array(mut entry) if entry.len() == 2 => {
+                    let value = entry.pop().unwrap();
+                    (entry.pop().unwrap(), value)
+                  }
+                  _ => unreachable!(),
+                })
+                .collect(),
+            )),
+            _ => unreachable!(),
+          };
+        }
+
+        let set_class: JsFunction = env.get_global()?.get_named_property("Set")?;
+        if obj.instanceof(set_class)? {
+          let array_class: JsObject = env.get_global()?.get_named_property("Array")?;
+          let from: JsFunction = array_class.get_named_property("from")?;
+          let values = from.call(Some(&array_class), &[obj])?;
+          return match napi_to_js_value(values, env)? {
+            JsValue::Array(values) => Ok(JsValue::Set(values)),
+            _ => unreachable!(),
+          };
+        }
+
         let names = obj.get_property_names()?;
         let len = names.get_array_length()?;
         let mut props = IndexMap::with_capacity(len as usize);
@@ -161,17 +220,62 @@ fn napi_to_js_value(value: napi::JsUnknown, env: Env) -> napi::Result<JsValue> {
       let source = f.coerce_to_string()?.into_utf8()?.into_owned()?;
       Ok(JsValue::Function(source))
     }
-    ValueType::Symbol | ValueType::External | ValueType::Unknown => Err(napi::Error::new(
-      napi::Status::GenericFailure,
-      "Could not convert value returned from macro to AST.",
-    )),
+    ValueType::Unknown => match napi_to_bigint(&value, env)? {
+      Some(n) => Ok(JsValue::BigInt(n)),
+      None => Err(unsupported_value()),
+    },
+    ValueType::Symbol | ValueType::External => Err(unsupported_value()),
   }
 }
 
+fn unsupported_value() -> napi::Error {
+  napi::Error::new(
+    napi::Status::GenericFailure,
+    "Could not convert value returned from macro to AST.",
+  )
+}
+
+/// Converts a napi value to a BigInt, if it is one. Without napi6, BigInts have no value
+/// type of their own, so they are recognized by converting to a decimal string and back.
+fn napi_to_bigint(value: &JsUnknown, env: Env) -> napi::Result<Option<BigIntValue>> {
+  let string = unsafe { value.cast::<JsUnknown>() }
+    .coerce_to_string()?
+    .into_utf8()?
+    .into_owned()?;
+  let n = match string.parse() {
+    Ok(n) => n,
+    Err(_) => return Ok(None),
+  };
+  let bigint: JsFunction = env.get_global()?.get_named_property("BigInt")?;
+  let converted = bigint.call(None, &[env.create_string(&string)?])?;
+  Ok(
+    env
+      .strict_equals(converted, unsafe { value.cast::<JsUnknown>() })?
+      .then_some(n),
+  )
+}
+
+/// Convert the result of a macro to a MacroOutput. The JS callback resolves with the value
+/// returned by the macro, and the warnings it reported via `this.warn`, which are anchored
+/// to the macro call.
+fn napi_to_macro_output(result: JsUnknown, env: Env) -> napi::Result<MacroOutput> {
+  let result: JsObject = result.try_into()?;
+  let value = napi_to_js_value(result.get_named_property("value")?, env)?;
+  let warnings: Vec<String> =
+    env.from_js_value(result.get_named_property::<JsUnknown>("warnings")?)?;
+  Ok(MacroOutput {
+    value,
+    diagnostics: warnings
+      .into_iter()
+      .map(|message| MacroError::ExecutionWarning(message, DUMMY_SP))
+      .collect(),
+  })
+}
+
 fn await_promise(
   env: Env,
   result: JsUnknown,
-  tx: Sender<Result<JsValue, MacroError>>,
+  tx: Sender<Result<MacroOutput, MacroError>>,
 ) -> napi::Result<()> {
   // If the result is a promise, wait for it to resolve, and send the result to the channel.
   // Otherwise, send the result immediately.
@@ -180,7 +284,7 @@ fn await_promise(
     let then: JsFunction = result.get_named_property("then")?;
     let tx2 = tx.clone();
     let cb = env.create_function_from_closure("callback", move |ctx| {
-      let res = napi_to_js_value(ctx.get::<JsUnknown>(0)?, env)?;
+      let res = napi_to_macro_output(ctx.get::<JsUnknown>(0)?, env)?;
       tx.send(Ok(res)).expect("send failure");
       ctx.env.get_undefined()
     })?;
@@ -189,6 +293,7 @@ fn await_promise(
       let err = match res.kind {
         1 => MacroError::LoadError(res.message, DUMMY_SP),
         2 => MacroError::ExecutionError(res.message, DUMMY_SP),
+        3 => MacroError::ExecutionWarning(res.message, DUMMY_SP),
         _ => MacroError::LoadError("Invalid error kind".into(), DUMMY_SP),
       };
       tx2.send(Err(err)).expect("send failure");
@@ -196,7 +301,7 @@ fn await_promise(
     })?;
     then.call(Some(&result), &[cb, eb])?;
   } else {
-    tx.send(Ok(napi_to_js_value(result, env)?))
+    tx.send(Ok(napi_to_macro_output(result, env)?))
       .expect("send failure");
   }
 
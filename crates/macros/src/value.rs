@@ -0,0 +1,1235 @@
+use indexmap::IndexMap;
+use num_bigint::BigInt as BigIntValue;
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeMap, SerializeSeq};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+use crate::JsValue;
+
+/// An error returned when a JsValue cannot be converted to another type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversionError {
+  /// The expected type.
+  pub expected: &'static str,
+  /// The type of the value that was found instead.
+  pub found: &'static str,
+}
+
+impl fmt::Display for ConversionError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "expected {}, found {}", self.expected, self.found)
+  }
+}
+
+impl std::error::Error for ConversionError {}
+
+/// Constructors for building values, e.g. the results of macros implemented in Rust.
+///
+/// ```
+/// use parcel_macros::JsValue;
+///
+/// let value = JsValue::object([
+///   ("name".to_string(), JsValue::string("parcel")),
+///   ("versions".to_string(), JsValue::array([JsValue::number(1.0), JsValue::number(2.0)])),
+/// ]);
+/// assert_eq!(value.to_string(), r#"{ name: "parcel", versions: [1, 2] }"#);
+/// ```
+impl JsValue {
+  pub fn string(s: impl Into<String>) -> JsValue {
+    JsValue::String(s.into())
+  }
+
+  pub fn number(n: f64) -> JsValue {
+    JsValue::Number(n)
+  }
+
+  pub fn array(values: impl IntoIterator<Item = JsValue>) -> JsValue {
+    JsValue::Array(values.into_iter().collect())
+  }
+
+  /// Creates an object with the given entries, in order. Later entries with the same key
+  /// replace earlier ones.
+  pub fn object(entries: impl IntoIterator<Item = (String, JsValue)>) -> JsValue {
+    JsValue::Object(entries.into_iter().collect())
+  }
+}
+
+impl JsValue {
+  /// Converts the value to a string, as `String(value)` does in JavaScript.
+  pub fn to_js_string(&self) -> String {
+    match self {
+      JsValue::Undefined => "undefined".into(),
+      JsValue::Null => "null".into(),
+      JsValue::Bool(b) => b.to_string(),
+      JsValue::Number(n) => js_number_to_string(*n),
+      JsValue::String(s) => s.clone(),
+      JsValue::Regex { source, flags } => format!("/{}/{}", source, flags),
+      // Arrays are joined with commas, with null and undefined elements as empty strings.
+      JsValue::Array(arr) => arr
+        .iter()
+        .map(|val| match val {
+          JsValue::Null | JsValue::Undefined => String::new(),
+          val => val.to_js_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(","),
+      JsValue::Object(_) => "[object Object]".into(),
+      JsValue::Map(_) => "[object Map]".into(),
+      JsValue::Set(_) => "[object Set]".into(),
+      JsValue::Date(time) => date_to_string(*time),
+      JsValue::BigInt(n) => n.to_string(),
+      JsValue::Function(source) => source.clone(),
+    }
+  }
+
+  /// Whether the value is truthy, as in `Boolean(value)`.
+  pub fn is_truthy(&self) -> bool {
+    match self {
+      JsValue::Undefined | JsValue::Null => false,
+      JsValue::Bool(b) => *b,
+      JsValue::Number(n) => *n != 0.0 && !n.is_nan(),
+      JsValue::String(s) => !s.is_empty(),
+      JsValue::BigInt(n) => *n != BigIntValue::from(0),
+      JsValue::Regex { .. }
+      | JsValue::Array(_)
+      | JsValue::Object(_)
+      | JsValue::Map(_)
+      | JsValue::Set(_)
+      | JsValue::Date(_)
+      | JsValue::Function(_) => true,
+    }
+  }
+
+  /// Converts the value to a number, as `Number(value)` does in JavaScript.
+  pub fn to_js_number(&self) -> f64 {
+    match self {
+      JsValue::Undefined => f64::NAN,
+      JsValue::Null => 0.0,
+      JsValue::Bool(b) => *b as u8 as f64,
+      JsValue::Number(n) => *n,
+      JsValue::String(s) => string_to_number(s),
+      JsValue::Date(time) => *time,
+      // Large BigInts round to the nearest number, or to an infinity beyond the range.
+      JsValue::BigInt(n) => n.to_string().parse().unwrap(),
+      // Arrays are converted via their string representation, e.g. [5] is 5.
+      JsValue::Array(_) => string_to_number(&self.to_js_string()),
+      JsValue::Regex { .. }
+      | JsValue::Object(_)
+      | JsValue::Map(_)
+      | JsValue::Set(_)
+      | JsValue::Function(_) => f64::NAN,
+    }
+  }
+
+  /// Compares values using the SameValueZero algorithm, which is how keys of maps and values
+  /// of sets are compared in JavaScript. Unlike `==`, `NaN` is equal to itself.
+  pub(crate) fn same_value_zero(&self, other: &JsValue) -> bool {
+    match (self, other) {
+      (JsValue::Number(a), JsValue::Number(b)) if a.is_nan() && b.is_nan() => true,
+      _ => self == other,
+    }
+  }
+
+  /// A description of the type of the value, used in error messages.
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      JsValue::Undefined => "undefined",
+      JsValue::Null => "null",
+      JsValue::Bool(_) => "boolean",
+      JsValue::Number(_) => "number",
+      JsValue::String(_) => "string",
+      JsValue::Regex { .. } => "regular expression",
+      JsValue::Array(_) => "array",
+      JsValue::Object(_) => "object",
+      JsValue::Map(_) => "map",
+      JsValue::Set(_) => "set",
+      JsValue::Date(_) => "date",
+      JsValue::BigInt(_) => "bigint",
+      JsValue::Function(_) => "function",
+    }
+  }
+}
+
+macro_rules! impl_try_from {
+  ($ty: ty, $variant: ident, $expected: literal) => {
+    impl TryFrom<JsValue> for $ty {
+      type Error = ConversionError;
+
+      fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+        match value {
+          JsValue::$variant(v) => Ok(v),
+          value => Err(ConversionError {
+            expected: $expected,
+            found: value.type_name(),
+          }),
+        }
+      }
+    }
+  };
+}
+
+impl_try_from!(bool, Bool, "boolean");
+impl_try_from!(f64, Number, "number");
+impl_try_from!(String, String, "string");
+impl_try_from!(Vec<JsValue>, Array, "array");
+impl_try_from!(IndexMap<String, JsValue>, Object, "object");
+
+/// Values are compared structurally. Numbers follow JavaScript semantics, so `NaN` is not
+/// equal to itself and `0` is equal to `-0`. Objects are equal when they have the same set
+/// of keys with equal values, regardless of key order. Maps and sets are equal when they
+/// have equal entries in the same order.
+impl PartialEq for JsValue {
+  fn eq(&self, other: &Self) -> bool {
+    match (self, other) {
+      (JsValue::Undefined, JsValue::Undefined) | (JsValue::Null, JsValue::Null) => true,
+      (JsValue::Bool(a), JsValue::Bool(b)) => a == b,
+      (JsValue::Number(a), JsValue::Number(b)) => a == b,
+      (JsValue::String(a), JsValue::String(b)) => a == b,
+      (
+        JsValue::Regex { source, flags },
+        JsValue::Regex {
+          source: other_source,
+          flags: other_flags,
+        },
+      ) => source == other_source && flags == other_flags,
+      (JsValue::Array(a), JsValue::Array(b)) => a == b,
+      (JsValue::Object(a), JsValue::Object(b)) => a == b,
+      (JsValue::Map(a), JsValue::Map(b)) => a == b,
+      (JsValue::Set(a), JsValue::Set(b)) => a == b,
+      (JsValue::Date(a), JsValue::Date(b)) => a == b,
+      (JsValue::BigInt(a), JsValue::BigInt(b)) => a == b,
+      (JsValue::Function(a), JsValue::Function(b)) => a == b,
+      _ => false,
+    }
+  }
+}
+
+/// Hashes are consistent with `PartialEq`, e.g. `0` and `-0` hash the same. `Eq` is not
+/// implemented, since `NaN` is not equal to itself, so values cannot be used as keys of
+/// a `HashMap` or `HashSet`.
+impl Hash for JsValue {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    std::mem::discriminant(self).hash(state);
+    match self {
+      JsValue::Undefined | JsValue::Null => {}
+      JsValue::Bool(b) => b.hash(state),
+      // Normalize -0 so it hashes the same as 0.
+      JsValue::Number(n) | JsValue::Date(n) => {
+        (if *n == 0.0 { 0.0 } else { *n }).to_bits().hash(state)
+      }
+      JsValue::String(s) | JsValue::Function(s) => s.hash(state),
+      JsValue::BigInt(n) => n.hash(state),
+      JsValue::Regex { source, flags } => {
+        source.hash(state);
+        flags.hash(state);
+      }
+      JsValue::Array(arr) | JsValue::Set(arr) => arr.hash(state),
+      JsValue::Map(entries) => entries.hash(state),
+      JsValue::Object(obj) => {
+        // Combine the entry hashes in an order independent way, consistent with PartialEq.
+        let mut sum: u64 = 0;
+        for entry in obj {
+          let mut hasher = DefaultHasher::new();
+          entry.hash(&mut hasher);
+          sum = sum.wrapping_add(hasher.finish());
+        }
+        obj.len().hash(state);
+        sum.hash(state);
+      }
+    }
+  }
+}
+
+/// Parses a string as a number, following the JS StringToNumber algorithm.
+fn string_to_number(s: &str) -> f64 {
+  let s = s.trim();
+  if s.is_empty() {
+    return 0.0;
+  }
+
+  let radix = match s.get(..2) {
+    Some("0x" | "0X") => 16,
+    Some("0o" | "0O") => 8,
+    Some("0b" | "0B") => 2,
+    _ => 10,
+  };
+  if radix != 10 {
+    let digits = &s[2..];
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+      return f64::NAN;
+    }
+    return digits.chars().fold(0.0, |n, c| {
+      n * radix as f64 + c.to_digit(radix).unwrap() as f64
+    });
+  }
+
+  match s.strip_prefix(['+', '-']).unwrap_or(s) {
+    "Infinity" if s.starts_with('-') => f64::NEG_INFINITY,
+    "Infinity" => f64::INFINITY,
+    // Rust also accepts e.g. "inf" and "NaN", which are not valid in JS.
+    digits if digits.starts_with(|c: char| c.is_ascii_digit() || c == '.') => {
+      s.parse().unwrap_or(f64::NAN)
+    }
+    _ => f64::NAN,
+  }
+}
+
+/// Converts a number to an unsigned 32 bit integer, following the JS ToUint32 operation.
+/// Values are truncated and wrap around, and NaN and infinities become 0.
+pub(crate) fn to_uint32(n: f64) -> u32 {
+  if !n.is_finite() {
+    return 0;
+  }
+  n.trunc().rem_euclid(4294967296.0) as u32
+}
+
+/// Converts a number to a signed 32 bit integer, following the JS ToInt32 operation.
+pub(crate) fn to_int32(n: f64) -> i32 {
+  to_uint32(n) as i32
+}
+
+const MS_PER_DAY: i64 = 86_400_000;
+
+/// Converts a number to a date value, following the JS TimeClip operation. Dates more
+/// than 100 million days from the epoch are invalid.
+pub(crate) fn time_clip(time: f64) -> f64 {
+  if !time.is_finite() || time.abs() > 8.64e15 {
+    return f64::NAN;
+  }
+  // Adding 0 converts -0 to 0.
+  time.trunc() + 0.0
+}
+
+/// The UTC components of a valid date value.
+struct DateParts {
+  year: i64,
+  month: i64,
+  day: i64,
+  weekday: i64,
+  ms_in_day: i64,
+}
+
+fn date_parts(time: f64) -> Option<DateParts> {
+  if time.is_nan() {
+    return None;
+  }
+  let time = time as i64;
+  let days = time.div_euclid(MS_PER_DAY);
+
+  // Converts days since the epoch to a proleptic Gregorian date, in eras of 400 years
+  // starting on March 1st. See http://howardhinnant.github.io/date_algorithms.html.
+  let z = days + 719_468;
+  let era = z.div_euclid(146_097);
+  let day_of_era = z.rem_euclid(146_097);
+  let year_of_era =
+    (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let shifted_month = (5 * day_of_year + 2) / 153;
+  let month = if shifted_month < 10 {
+    shifted_month + 3
+  } else {
+    shifted_month - 9
+  };
+
+  Some(DateParts {
+    year: era * 400 + year_of_era + (month <= 2) as i64,
+    month,
+    day: day_of_year - (153 * shifted_month + 2) / 5 + 1,
+    // The epoch was a Thursday.
+    weekday: (days + 4).rem_euclid(7),
+    ms_in_day: time.rem_euclid(MS_PER_DAY),
+  })
+}
+
+/// Implements `Date.prototype.getUTCFullYear`, which is the same as `getFullYear`
+/// when evaluated in UTC.
+pub(crate) fn date_year(time: f64) -> f64 {
+  date_parts(time).map_or(f64::NAN, |parts| parts.year as f64)
+}
+
+/// Implements `Date.prototype.toISOString`. Returns None for invalid dates, which throw
+/// a RangeError. Years outside of 0-9999 use the expanded six digit format.
+pub(crate) fn date_to_iso_string(time: f64) -> Option<String> {
+  let parts = date_parts(time)?;
+  let year = if (0..=9999).contains(&parts.year) {
+    format!("{:04}", parts.year)
+  } else {
+    format!("{:+07}", parts.year)
+  };
+  let ms = parts.ms_in_day;
+  Some(format!(
+    "{}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+    year,
+    parts.month,
+    parts.day,
+    ms / 3_600_000,
+    ms / 60_000 % 60,
+    ms / 1000 % 60,
+    ms % 1000
+  ))
+}
+
+/// Converts a date to a string as `String(date)` does, in UTC rather than the local time
+/// zone so that the result does not depend on the machine running the build.
+fn date_to_string(time: f64) -> String {
+  const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+  const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+  ];
+
+  let parts = match date_parts(time) {
+    Some(parts) => parts,
+    None => return "Invalid Date".into(),
+  };
+  let year = if parts.year < 0 {
+    format!("-{:04}", -parts.year)
+  } else {
+    format!("{:04}", parts.year)
+  };
+  let ms = parts.ms_in_day;
+  format!(
+    "{} {} {:02} {} {:02}:{:02}:{:02} GMT+0000 (Coordinated Universal Time)",
+    WEEKDAYS[parts.weekday as usize],
+    MONTHS[parts.month as usize - 1],
+    parts.day,
+    year,
+    ms / 3_600_000,
+    ms / 60_000 % 60,
+    ms / 1000 % 60
+  )
+}
+
+/// Converts a number to a string following the ECMAScript Number::toString algorithm,
+/// e.g. `1e21` rather than Rust's `1000000000000000000000`, and `0` for `-0`.
+pub(crate) fn js_number_to_string(n: f64) -> String {
+  if n.is_nan() {
+    return "NaN".into();
+  }
+  if n == 0.0 {
+    return "0".into();
+  }
+  if n.is_infinite() {
+    return if n > 0.0 { "Infinity" } else { "-Infinity" }.into();
+  }
+  if n < 0.0 {
+    return format!("-{}", js_number_to_string(-n));
+  }
+
+  // Rust's exponential formatting produces the shortest digits that round trip,
+  // which is what JS uses. `n` is the position of the decimal point relative to the digits.
+  let exp = format!("{:e}", n);
+  let (mantissa, exponent) = exp.split_once('e').unwrap();
+  let digits = mantissa.replace('.', "");
+  let k = digits.len() as i32;
+  let n = exponent.parse::<i32>().unwrap() + 1;
+
+  if k <= n && n <= 21 {
+    format!("{}{}", digits, "0".repeat((n - k) as usize))
+  } else if 0 < n && n <= 21 {
+    format!("{}.{}", &digits[..n as usize], &digits[n as usize..])
+  } else if -6 < n && n <= 0 {
+    format!("0.{}{}", "0".repeat(-n as usize), digits)
+  } else {
+    let sign = if n - 1 < 0 { '-' } else { '+' };
+    let (first, rest) = digits.split_at(1);
+    if rest.is_empty() {
+      format!("{}e{}{}", first, sign, (n - 1).abs())
+    } else {
+      format!("{}.{}e{}{}", first, rest, sign, (n - 1).abs())
+    }
+  }
+}
+
+/// Formats the value as JavaScript source code, e.g. `{ a: [1, "two"], b: /x/g }`.
+impl fmt::Display for JsValue {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      JsValue::Undefined => f.write_str("undefined"),
+      JsValue::Null => f.write_str("null"),
+      JsValue::Bool(b) => write!(f, "{}", b),
+      // Unlike String(-0), keep the sign so the source evaluates to the same value.
+      JsValue::Number(n) if *n == 0.0 && n.is_sign_negative() => f.write_str("-0"),
+      JsValue::Number(n) => f.write_str(&js_number_to_string(*n)),
+      JsValue::String(s) => write_string(f, s),
+      JsValue::Regex { source, flags } => write!(f, "/{}/{}", source, flags),
+      JsValue::Array(arr) => {
+        f.write_str("[")?;
+        for (i, val) in arr.iter().enumerate() {
+          if i > 0 {
+            f.write_str(", ")?;
+          }
+          write!(f, "{}", val)?;
+        }
+        f.write_str("]")
+      }
+      JsValue::Object(obj) if obj.is_empty() => f.write_str("{}"),
+      JsValue::Object(obj) => {
+        f.write_str("{ ")?;
+        for (i, (key, val)) in obj.iter().enumerate() {
+          if i > 0 {
+            f.write_str(", ")?;
+          }
+          if is_identifier(key) {
+            f.write_str(key)?;
+          } else {
+            write_string(f, key)?;
+          }
+          write!(f, ": {}", val)?;
+        }
+        f.write_str(" }")
+      }
+      JsValue::Map(entries) => {
+        f.write_str("new Map([")?;
+        for (i, (key, val)) in entries.iter().enumerate() {
+          if i > 0 {
+            f.write_str(", ")?;
+          }
+          write!(f, "[{}, {}]", key, val)?;
+        }
+        f.write_str("])")
+      }
+      JsValue::Set(values) => {
+        f.write_str("new Set([")?;
+        for (i, val) in values.iter().enumerate() {
+          if i > 0 {
+            f.write_str(", ")?;
+          }
+          write!(f, "{}", val)?;
+        }
+        f.write_str("])")
+      }
+      JsValue::Date(time) => write!(f, "new Date({})", JsValue::Number(*time)),
+      JsValue::BigInt(n) => write!(f, "{}n", n),
+      JsValue::Function(source) => f.write_str(source),
+    }
+  }
+}
+
+/// Writes a double quoted JavaScript string literal.
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+  use fmt::Write;
+
+  f.write_char('"')?;
+  for c in s.chars() {
+    match c {
+      '"' => f.write_str("\\\"")?,
+      '\\' => f.write_str("\\\\")?,
+      '\n' => f.write_str("\\n")?,
+      '\r' => f.write_str("\\r")?,
+      '\t' => f.write_str("\\t")?,
+      // Line and paragraph separators are line terminators in older engines.
+      '\u{2028}' | '\u{2029}' => write!(f, "\\u{:04x}", c as u32)?,
+      c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+      c => f.write_char(c)?,
+    }
+  }
+  f.write_char('"')
+}
+
+/// Whether an object key can be written without quotes.
+fn is_identifier(key: &str) -> bool {
+  let mut chars = key.chars();
+  matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_' || c == '$')
+    && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+}
+
+impl From<serde_json::Value> for JsValue {
+  fn from(value: serde_json::Value) -> Self {
+    match value {
+      serde_json::Value::Null => JsValue::Null,
+      serde_json::Value::Bool(b) => JsValue::Bool(b),
+      serde_json::Value::Number(n) => JsValue::Number(n.as_f64().unwrap_or(f64::NAN)),
+      serde_json::Value::String(s) => JsValue::String(s),
+      serde_json::Value::Array(arr) => JsValue::Array(arr.into_iter().map(JsValue::from).collect()),
+      serde_json::Value::Object(obj) => JsValue::Object(
+        obj
+          .into_iter()
+          .map(|(k, v)| (k, JsValue::from(v)))
+          .collect(),
+      ),
+    }
+  }
+}
+
+/// Converts a JsValue to JSON. This fails for values that have no JSON representation:
+/// `undefined`, functions, regular expressions, and non-finite numbers. Note that unlike
+/// `JSON.stringify`, these are not silently omitted or converted to `null`.
+impl TryFrom<JsValue> for serde_json::Value {
+  type Error = ConversionError;
+
+  fn try_from(value: JsValue) -> Result<Self, Self::Error> {
+    let error = |found| ConversionError {
+      expected: "a JSON compatible value",
+      found,
+    };
+
+    Ok(match value {
+      JsValue::Null => serde_json::Value::Null,
+      JsValue::Bool(b) => serde_json::Value::Bool(b),
+      // Integers are converted as such so they are not serialized with a fractional part.
+      JsValue::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => {
+        serde_json::Value::Number((n as i64).into())
+      }
+      JsValue::Number(n) => serde_json::Number::from_f64(n)
+        .map(serde_json::Value::Number)
+        .ok_or_else(|| error("non-finite number"))?,
+      JsValue::String(s) => serde_json::Value::String(s),
+      JsValue::Array(arr) => serde_json::Value::Array(
+        arr
+          .into_iter()
+          .map(serde_json::Value::try_from)
+          .collect::<Result<_, _>>()?,
+      ),
+      JsValue::Object(obj) => serde_json::Value::Object(
+        obj
+          .into_iter()
+          .map(|(k, v)| Ok((k, serde_json::Value::try_from(v)?)))
+          .collect::<Result<_, _>>()?,
+      ),
+      value @ (JsValue::Undefined
+      | JsValue::Regex { .. }
+      | JsValue::Map(_)
+      | JsValue::Set(_)
+      | JsValue::Date(_)
+      | JsValue::BigInt(_)
+      | JsValue::Function(_)) => return Err(error(value.type_name())),
+    })
+  }
+}
+
+/// The key used to tag values that have no natural JSON representation.
+const TYPE_KEY: &str = "$type";
+
+/// JsValue is serialized as plain JSON where possible. Values that cannot be
+/// represented in JSON are serialized as objects with a `$type` tag:
+///
+/// - `undefined` → `{"$type": "undefined"}`
+/// - `NaN` and infinities → `{"$type": "number", "value": "NaN"}`
+/// - regular expressions → `{"$type": "regex", "source": "...", "flags": "..."}`
+/// - functions → `{"$type": "function", "source": "..."}`
+/// - maps → `{"$type": "map", "entries": [[k, v], ...]}`
+/// - sets → `{"$type": "set", "values": [...]}`
+/// - dates → `{"$type": "date", "value": 0}`, with the value in milliseconds since the epoch
+/// - BigInts → `{"$type": "bigint", "value": "123"}`, with the value as a decimal string
+/// - objects that have their own `$type` key → `{"$type": "object", "entries": [[k, v], ...]}`
+impl Serialize for JsValue {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    match self {
+      JsValue::Undefined => {
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry(TYPE_KEY, "undefined")?;
+        map.end()
+      }
+      JsValue::Null => serializer.serialize_unit(),
+      JsValue::Bool(b) => serializer.serialize_bool(*b),
+      JsValue::Number(n) if n.is_finite() => serializer.serialize_f64(*n),
+      JsValue::Number(n) => {
+        let value = if n.is_nan() {
+          "NaN"
+        } else if *n > 0.0 {
+          "Infinity"
+        } else {
+          "-Infinity"
+        };
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry(TYPE_KEY, "number")?;
+        map.serialize_entry("value", value)?;
+        map.end()
+      }
+      JsValue::String(s) => serializer.serialize_str(s),
+      JsValue::Regex { source, flags } => {
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry(TYPE_KEY, "regex")?;
+        map.serialize_entry("source", source)?;
+        map.serialize_entry("flags", flags)?;
+        map.end()
+      }
+      JsValue::Array(arr) => {
+        let mut seq = serializer.serialize_seq(Some(arr.len()))?;
+        for value in arr {
+          seq.serialize_element(value)?;
+        }
+        seq.end()
+      }
+      JsValue::Object(obj) if obj.contains_key(TYPE_KEY) => {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry(TYPE_KEY, "object")?;
+        map.serialize_entry("entries", &obj.iter().collect::<Vec<_>>())?;
+        map.end()
+      }
+      JsValue::Object(obj) => {
+        let mut map = serializer.serialize_map(Some(obj.len()))?;
+        for (key, value) in obj {
+          map.serialize_entry(key, value)?;
+        }
+        map.end()
+      }
+      JsValue::Map(entries) => {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry(TYPE_KEY, "map")?;
+        map.serialize_entry("entries", entries)?;
+        map.end()
+      }
+      JsValue::Set(values) => {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry(TYPE_KEY, "set")?;
+        map.serialize_entry("values", values)?;
+        map.end()
+      }
+      JsValue::Date(time) => {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry(TYPE_KEY, "date")?;
+        map.serialize_entry("value", &JsValue::Number(*time))?;
+        map.end()
+      }
+      JsValue::BigInt(n) => {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry(TYPE_KEY, "bigint")?;
+        map.serialize_entry("value", &n.to_string())?;
+        map.end()
+      }
+      JsValue::Function(source) => {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry(TYPE_KEY, "function")?;
+        map.serialize_entry("source", source)?;
+        map.end()
+      }
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for JsValue {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserializer.deserialize_any(JsValueVisitor)
+  }
+}
+
+struct JsValueVisitor;
+
+impl<'de> Visitor<'de> for JsValueVisitor {
+  type Value = JsValue;
+
+  fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+    formatter.write_str("a JSON value")
+  }
+
+  fn visit_unit<E>(self) -> Result<JsValue, E> {
+    Ok(JsValue::Null)
+  }
+
+  fn visit_none<E>(self) -> Result<JsValue, E> {
+    Ok(JsValue::Null)
+  }
+
+  fn visit_bool<E>(self, v: bool) -> Result<JsValue, E> {
+    Ok(JsValue::Bool(v))
+  }
+
+  fn visit_i64<E>(self, v: i64) -> Result<JsValue, E> {
+    Ok(JsValue::Number(v as f64))
+  }
+
+  fn visit_u64<E>(self, v: u64) -> Result<JsValue, E> {
+    Ok(JsValue::Number(v as f64))
+  }
+
+  fn visit_f64<E>(self, v: f64) -> Result<JsValue, E> {
+    Ok(JsValue::Number(v))
+  }
+
+  fn visit_str<E>(self, v: &str) -> Result<JsValue, E> {
+    Ok(JsValue::String(v.to_owned()))
+  }
+
+  fn visit_string<E>(self, v: String) -> Result<JsValue, E> {
+    Ok(JsValue::String(v))
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<JsValue, A::Error> {
+    let mut arr = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+    while let Some(value) = seq.next_element()? {
+      arr.push(value);
+    }
+    Ok(JsValue::Array(arr))
+  }
+
+  fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<JsValue, A::Error> {
+    let mut obj = IndexMap::with_capacity(map.size_hint().unwrap_or(0));
+    while let Some((key, value)) = map.next_entry::<String, JsValue>()? {
+      obj.insert(key, value);
+    }
+
+    let tag = match obj.get(TYPE_KEY) {
+      Some(JsValue::String(tag)) => tag.clone(),
+      _ => return Ok(JsValue::Object(obj)),
+    };
+
+    let mut string_field = |name: &'static str| match obj.swap_remove(name) {
+      Some(JsValue::String(s)) => Ok(s),
+      _ => Err(de::Error::missing_field(name)),
+    };
+
+    match tag.as_str() {
+      "undefined" => Ok(JsValue::Undefined),
+      "number" => match string_field("value")?.as_str() {
+        "NaN" => Ok(JsValue::Number(f64::NAN)),
+        "Infinity" => Ok(JsValue::Number(f64::INFINITY)),
+        "-Infinity" => Ok(JsValue::Number(f64::NEG_INFINITY)),
+        value => Err(de::Error::invalid_value(
+          de::Unexpected::Str(value),
+          &"NaN, Infinity, or -Infinity",
+        )),
+      },
+      "regex" => Ok(JsValue::Regex {
+        source: string_field("source")?,
+        flags: string_field("flags")?,
+      }),
+      "function" => Ok(JsValue::Function(string_field("source")?)),
+      "map" => match obj.swap_remove("entries") {
+        Some(JsValue::Array(entries)) => entries
+          .into_iter()
+          .map(|entry| match entry {
+            JsValue::Array(mut entry) if entry.len() == 2 => match (entry.pop(), entry.pop()) {
+              (Some(value), Some(key)) => Ok((key, value)),
+              _ => Err(de::Error::custom("invalid map entry")),
+            },
+            _ => Err(de::Error::custom("invalid map entry")),
+          })
+          .collect::<Result<_, _>>()
+          .map(JsValue::Map),
+        _ => Err(de::Error::missing_field("entries")),
+      },
+      "set" => match obj.swap_remove("values") {
+        Some(JsValue::Array(values)) => Ok(JsValue::Set(values)),
+        _ => Err(de::Error::missing_field("values")),
+      },
+      "date" => match obj.swap_remove("value") {
+        Some(JsValue::Number(time)) => Ok(JsValue::Date(time)),
+        _ => Err(de::Error::missing_field("value")),
+      },
+      "bigint" => {
+        let value = string_field("value")?;
+        value
+          .parse()
+          .map(JsValue::BigInt)
+          .map_err(|_| de::Error::invalid_value(de::Unexpected::Str(&value), &"a decimal integer"))
+      }
+      "object" => match obj.swap_remove("entries") {
+        Some(JsValue::Array(entries)) => entries
+          .into_iter()
+          .map(|entry| match entry {
+            JsValue::Array(mut entry) if entry.len() == 2 => match (entry.pop(), entry.pop()) {
+              (Some(value), Some(JsValue::String(key))) => Ok((key, value)),
+              _ => Err(de::Error::custom("invalid object entry")),
+            },
+            _ => Err(de::Error::custom("invalid object entry")),
+          })
+          .collect::<Result<_, _>>()
+          .map(JsValue::Object),
+        _ => Err(de::Error::missing_field("entries")),
+      },
+      tag => Err(de::Error::unknown_variant(
+        tag,
+        &[
+          "undefined",
+          "number",
+          "regex",
+          "function",
+          "map",
+          "set",
+          "date",
+          "bigint",
+          "object",
+        ],
+      )),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn round_trip(value: &JsValue) -> JsValue {
+    let json = serde_json::to_string(value).unwrap();
+    serde_json::from_str(&json).unwrap()
+  }
+
+  #[test]
+  fn try_from_rust_types() {
+    assert_eq!(bool::try_from(JsValue::Bool(true)), Ok(true));
+    assert_eq!(f64::try_from(JsValue::Number(1.5)), Ok(1.5));
+    assert_eq!(
+      String::try_from(JsValue::String("hi".into())),
+      Ok("hi".to_string())
+    );
+    assert_eq!(
+      Vec::try_from(JsValue::Array(vec![JsValue::Null])),
+      Ok(vec![JsValue::Null])
+    );
+
+    let mut obj = IndexMap::new();
+    obj.insert("a".to_string(), JsValue::Number(1.0));
+    assert_eq!(IndexMap::try_from(JsValue::Object(obj.clone())), Ok(obj));
+  }
+
+  #[test]
+  fn try_from_type_mismatch() {
+    let err = String::try_from(JsValue::Number(1.0)).unwrap_err();
+    assert_eq!(err.to_string(), "expected string, found number");
+
+    let err = f64::try_from(JsValue::String("1".into())).unwrap_err();
+    assert_eq!(err.to_string(), "expected number, found string");
+
+    let err = Vec::<JsValue>::try_from(JsValue::Undefined).unwrap_err();
+    assert_eq!(err.to_string(), "expected array, found undefined");
+  }
+
+  fn hash(value: &JsValue) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+  }
+
+  #[test]
+  fn eq_nan() {
+    assert_ne!(JsValue::Number(f64::NAN), JsValue::Number(f64::NAN));
+    assert_ne!(
+      JsValue::Array(vec![JsValue::Number(f64::NAN)]),
+      JsValue::Array(vec![JsValue::Number(f64::NAN)])
+    );
+    assert_eq!(JsValue::Number(0.0), JsValue::Number(-0.0));
+    assert_eq!(hash(&JsValue::Number(0.0)), hash(&JsValue::Number(-0.0)));
+    assert_ne!(JsValue::Undefined, JsValue::Null);
+    assert_ne!(JsValue::String("1".into()), JsValue::Number(1.0));
+  }
+
+  #[test]
+  fn eq_object_key_order() {
+    let mut a = IndexMap::new();
+    a.insert("x".to_string(), JsValue::Number(1.0));
+    a.insert("y".to_string(), JsValue::Array(vec![JsValue::Bool(true)]));
+    let mut b = IndexMap::new();
+    b.insert("y".to_string(), JsValue::Array(vec![JsValue::Bool(true)]));
+    b.insert("x".to_string(), JsValue::Number(1.0));
+
+    let (a, b) = (JsValue::Object(a), JsValue::Object(b));
+    assert_eq!(a, b);
+    assert_eq!(hash(&a), hash(&b));
+
+    let mut c = IndexMap::new();
+    c.insert("x".to_string(), JsValue::Number(2.0));
+    assert_ne!(a, JsValue::Object(c));
+  }
+
+  #[test]
+  fn number_to_string() {
+    for (n, expected) in [
+      (1.0, "1"),
+      (-1.5, "-1.5"),
+      (0.1, "0.1"),
+      (-0.0, "0"),
+      (0.000001, "0.000001"),
+      (0.0000001, "1e-7"),
+      (1.5e-10, "1.5e-10"),
+      (123456789.0, "123456789"),
+      (100000000000000000000.0, "100000000000000000000"),
+      (1e21, "1e+21"),
+      (1.2345e25, "1.2345e+25"),
+      (0.1 + 0.2, "0.30000000000000004"),
+      (f64::MAX, "1.7976931348623157e+308"),
+      (5e-324, "5e-324"),
+      (f64::NAN, "NaN"),
+      (f64::NEG_INFINITY, "-Infinity"),
+    ] {
+      assert_eq!(js_number_to_string(n), expected);
+    }
+  }
+
+  #[test]
+  fn int32_conversion() {
+    assert_eq!(to_int32(5.9), 5);
+    assert_eq!(to_int32(-5.9), -5);
+    assert_eq!(to_int32(f64::NAN), 0);
+    assert_eq!(to_int32(f64::INFINITY), 0);
+    assert_eq!(to_int32(2147483648.0), -2147483648);
+    assert_eq!(to_int32(4294967301.0), 5);
+    assert_eq!(to_uint32(-1.0), 4294967295);
+    assert_eq!(to_uint32(1e21), 3735027712);
+  }
+
+  #[test]
+  fn to_js_number() {
+    for (s, expected) in [
+      ("", 0.0),
+      (" 12.5\n", 12.5),
+      ("-.5", -0.5),
+      ("1e3", 1000.0),
+      ("0x1F", 31.0),
+      ("0b101", 5.0),
+      ("-Infinity", f64::NEG_INFINITY),
+    ] {
+      assert_eq!(JsValue::String(s.into()).to_js_number(), expected);
+    }
+    for s in ["abc", "inf", "NaN", "0x", "1_000", "--1", "0x-1"] {
+      assert!(JsValue::String(s.into()).to_js_number().is_nan(), "{}", s);
+    }
+    assert_eq!(
+      JsValue::Array(vec![JsValue::Number(5.0)]).to_js_number(),
+      5.0
+    );
+    assert_eq!(JsValue::Array(vec![]).to_js_number(), 0.0);
+    assert_eq!(JsValue::Null.to_js_number(), 0.0);
+    assert!(JsValue::Undefined.to_js_number().is_nan());
+  }
+
+  #[test]
+  fn display_escaping() {
+    let value = JsValue::String("say \"hi\"\\n\nnext\tline\u{2028}\u{1}".into());
+    assert_eq!(
+      value.to_string(),
+      r#""say \"hi\"\\n\nnext\tline\u2028\u0001""#
+    );
+    assert_eq!(JsValue::String("it's".into()).to_string(), r#""it's""#);
+  }
+
+  #[test]
+  fn display_nested() {
+    let mut inner = IndexMap::new();
+    inner.insert(
+      "re".into(),
+      JsValue::Regex {
+        source: r"a\/b".into(),
+        flags: "gi".into(),
+      },
+    );
+    inner.insert("not-ident".into(), JsValue::Undefined);
+
+    let mut obj = IndexMap::new();
+    obj.insert("$name".into(), JsValue::String("x".into()));
+    obj.insert(
+      "list".into(),
+      JsValue::Array(vec![
+        JsValue::Number(1.0),
+        JsValue::Number(-0.5),
+        JsValue::Number(f64::NAN),
+        JsValue::Null,
+        JsValue::Bool(true),
+        JsValue::Object(IndexMap::new()),
+      ]),
+    );
+    obj.insert("inner".into(), JsValue::Object(inner));
+    obj.insert("1a".into(), JsValue::Function("() => 1".into()));
+
+    assert_eq!(
+      JsValue::Object(obj).to_string(),
+      r#"{ $name: "x", list: [1, -0.5, NaN, null, true, {}], inner: { re: /a\/b/gi, "not-ident": undefined }, "1a": () => 1 }"#
+    );
+  }
+
+  #[test]
+  fn json_conversion() {
+    let json = serde_json::json!({
+      "name": "parcel",
+      "version": 2,
+      "nested": { "list": [1.5, true, null, "x"] }
+    });
+
+    let value = JsValue::from(json.clone());
+    match &value {
+      JsValue::Object(obj) => match obj.get("nested") {
+        Some(JsValue::Object(nested)) => {
+          assert!(matches!(nested.get("list"), Some(JsValue::Array(list)) if list.len() == 4))
+        }
+        nested => panic!("expected an object, got {:?}", nested),
+      },
+      value => panic!("expected an object, got {:?}", value),
+    }
+
+    assert_eq!(serde_json::Value::try_from(value).unwrap(), json);
+  }
+
+  #[test]
+  fn json_conversion_error() {
+    let value = JsValue::Array(vec![
+      JsValue::Number(1.0),
+      JsValue::Function("() => {}".into()),
+    ]);
+
+    let err = serde_json::Value::try_from(value).unwrap_err();
+    assert_eq!(err.found, "function");
+    assert_eq!(
+      err.to_string(),
+      "expected a JSON compatible value, found function"
+    );
+  }
+
+  #[test]
+  fn serde_round_trip() {
+    let value = JsValue::Object(IndexMap::from([
+      ("undefined".to_string(), JsValue::Undefined),
+      ("null".to_string(), JsValue::Null),
+      ("bool".to_string(), JsValue::Bool(true)),
+      ("number".to_string(), JsValue::Number(1.5)),
+      ("infinity".to_string(), JsValue::Number(f64::NEG_INFINITY)),
+      ("string".to_string(), JsValue::String("hi".into())),
+      (
+        "regex".to_string(),
+        JsValue::Regex {
+          source: "a+".into(),
+          flags: "gi".into(),
+        },
+      ),
+      (
+        "array".to_string(),
+        JsValue::Array(vec![JsValue::Number(1.0), JsValue::Undefined]),
+      ),
+      ("function".to_string(), JsValue::Function("() => 1".into())),
+    ]));
+
+    let json = serde_json::to_string(&value).unwrap();
+    assert_eq!(
+      json,
+      r#"{"undefined":{"$type":"undefined"},"null":null,"bool":true,"number":1.5,"infinity":{"$type":"number","value":"-Infinity"},"string":"hi","regex":{"$type":"regex","source":"a+","flags":"gi"},"array":[1.0,{"$type":"undefined"}],"function":{"$type":"function","source":"() => 1"}}"#
+    );
+    assert_eq!(serde_json::to_string(&round_trip(&value)).unwrap(), json);
+  }
+
+  #[test]
+  fn constructors() {
+    let value = JsValue::object([
+      ("name".to_string(), JsValue::string("parcel")),
+      (
+        "nested".to_string(),
+        JsValue::object([(
+          "list".to_string(),
+          JsValue::array([JsValue::number(1.0), JsValue::string(String::from("a"))]),
+        )]),
+      ),
+      ("name".to_string(), JsValue::string("macros")),
+    ]);
+
+    assert_eq!(
+      value.to_string(),
+      r#"{ name: "macros", nested: { list: [1, "a"] } }"#
+    );
+  }
+
+  #[test]
+  fn serde_map() {
+    let value = JsValue::Map(vec![
+      (JsValue::String("a".into()), JsValue::Number(1.0)),
+      (JsValue::Number(2.0), JsValue::Undefined),
+    ]);
+
+    let json = serde_json::to_string(&value).unwrap();
+    assert_eq!(
+      json,
+      r#"{"$type":"map","entries":[["a",1.0],[2.0,{"$type":"undefined"}]]}"#
+    );
+    assert_eq!(round_trip(&value), value);
+    assert_eq!(value.to_string(), r#"new Map([["a", 1], [2, undefined]])"#);
+  }
+
+  #[test]
+  fn serde_set() {
+    let value = JsValue::Set(vec![JsValue::String("a".into()), JsValue::Null]);
+
+    let json = serde_json::to_string(&value).unwrap();
+    assert_eq!(json, r#"{"$type":"set","values":["a",null]}"#);
+    assert_eq!(round_trip(&value), value);
+    assert_eq!(value.to_string(), r#"new Set(["a", null])"#);
+  }
+
+  #[test]
+  fn serde_date() {
+    let value = JsValue::Date(1700000000000.0);
+
+    let json = serde_json::to_string(&value).unwrap();
+    assert_eq!(json, r#"{"$type":"date","value":1700000000000.0}"#);
+    assert_eq!(round_trip(&value), value);
+    assert_eq!(value.to_string(), "new Date(1700000000000)");
+    assert_eq!(JsValue::Date(f64::NAN).to_string(), "new Date(NaN)");
+  }
+
+  #[test]
+  fn serde_bigint() {
+    let value = JsValue::BigInt("-123456789012345678901234567890".parse().unwrap());
+
+    let json = serde_json::to_string(&value).unwrap();
+    assert_eq!(
+      json,
+      r#"{"$type":"bigint","value":"-123456789012345678901234567890"}"#
+    );
+    assert_eq!(round_trip(&value), value);
+    assert_eq!(value.to_string(), "-123456789012345678901234567890n");
+    assert_eq!(value.to_js_number(), -1.2345678901234568e29);
+    assert!(serde_json::from_str::<JsValue>(r#"{"$type":"bigint","value":"1.5"}"#).is_err());
+  }
+
+  #[test]
+  fn date_strings() {
+    for (time, iso, string) in [
+      (
+        0.0,
+        "1970-01-01T00:00:00.000Z",
+        "Thu Jan 01 1970 00:00:00 GMT+0000 (Coordinated Universal Time)",
+      ),
+      (
+        -1.0,
+        "1969-12-31T23:59:59.999Z",
+        "Wed Dec 31 1969 23:59:59 GMT+0000 (Coordinated Universal Time)",
+      ),
+      (
+        951782400000.0,
+        "2000-02-29T00:00:00.000Z",
+        "Tue Feb 29 2000 00:00:00 GMT+0000 (Coordinated Universal Time)",
+      ),
+      (
+        -62198755200000.0,
+        "-000001-01-01T00:00:00.000Z",
+        "Fri Jan 01 -0001 00:00:00 GMT+0000 (Coordinated Universal Time)",
+      ),
+      (
+        253402300800000.0,
+        "+010000-01-01T00:00:00.000Z",
+        "Sat Jan 01 10000 00:00:00 GMT+0000 (Coordinated Universal Time)",
+      ),
+    ] {
+      assert_eq!(date_to_iso_string(time).as_deref(), Some(iso));
+      assert_eq!(JsValue::Date(time).to_js_string(), string);
+    }
+    assert_eq!(date_to_iso_string(f64::NAN), None);
+    assert_eq!(JsValue::Date(f64::NAN).to_js_string(), "Invalid Date");
+    assert!(time_clip(8.64e15 + 1.0).is_nan());
+    assert!(time_clip(-0.5).is_sign_positive());
+  }
+
+  #[test]
+  fn serde_null_undefined() {
+    assert!(matches!(round_trip(&JsValue::Null), JsValue::Null));
+    assert!(matches!(
+      round_trip(&JsValue::Undefined),
+      JsValue::Undefined
+    ));
+    assert!(matches!(round_trip(&JsValue::Number(f64::NAN)), JsValue::Number(n) if n.is_nan()));
+  }
+
+  #[test]
+  fn serde_object_with_type_key() {
+    let value = JsValue::Object(IndexMap::from([
+      ("$type".to_string(), JsValue::String("regex".into())),
+      ("source".to_string(), JsValue::String("a".into())),
+    ]));
+
+    match round_trip(&value) {
+      JsValue::Object(obj) => {
+        assert_eq!(obj.keys().collect::<Vec<_>>(), vec!["$type", "source"]);
+      }
+      value => panic!("expected an object, got {:?}", value),
+    }
+  }
+}
@@ -2,6 +2,7 @@
 import assert from 'assert';
 import invariant from 'assert';
 import path from 'path';
+import Logger from '@parcel/logger';
 import {
   bundle,
   bundler,
@@ -383,6 +384,90 @@ describe('macros', function () {
     }
   });
 
+  it('should warn and keep the call when a macro throws a warning', async function () {
+    await fsFixture(overlayFS, dir)`
+      index.js:
+        import { test } from "./macro.js" with { type: "macro" };
+        output = test(1);
+
+      macro.js:
+        export async function test() {
+          let err = new Error('test');
+          err.severity = 'warning';
+          throw err;
+        }
+    `;
+
+    let logs = [];
+    let disposable = Logger.onLog(d => logs.push(d));
+    let b = await bundle(path.join(dir, '/index.js'), {
+      inputFS: overlayFS,
+    });
+    disposable.dispose();
+
+    let warnings = logs.filter(log => log.level === 'warn');
+    assert.equal(warnings.length, 1);
+    assert(
+      warnings[0].diagnostics[0].message.startsWith(
+        'Warning evaluating macro: test',
+      ),
+    );
+
+    // The call is not replaced with the result of the macro.
+    let res = await overlayFS.readFile(b.getBundles()[0].filePath, 'utf8');
+    assert(/test\)?\(1\)/.test(res));
+  });
+
+  it('should report warnings from macros that succeed', async function () {
+    await fsFixture(overlayFS, dir)`
+      index.js:
+        import { test } from "./macro.js" with { type: "macro" };
+        output = test(1);
+
+      macro.js:
+        export function test(value) {
+          this.warn('test is deprecated');
+          return value + 1;
+        }
+    `;
+
+    let logs = [];
+    let disposable = Logger.onLog(d => logs.push(d));
+    let b = await bundle(path.join(dir, '/index.js'), {
+      inputFS: overlayFS,
+      mode: 'production',
+    });
+    disposable.dispose();
+
+    let warnings = logs.filter(log => log.level === 'warn');
+    assert.equal(warnings.length, 1);
+    assert.equal(
+      warnings[0].diagnostics[0].message,
+      'Warning evaluating macro: test is deprecated',
+    );
+    assert.deepEqual(warnings[0].diagnostics[0].codeFrames, [
+      {
+        filePath: path.join(dir, 'index.js'),
+        codeHighlights: [
+          {
+            message: undefined,
+            start: {
+              line: 2,
+              column: 10,
+            },
+            end: {
+              line: 2,
+              column: 16,
+            },
+          },
+        ],
+      },
+    ]);
+
+    let res = await overlayFS.readFile(b.getBundles()[0].filePath, 'utf8');
+    assert(res.includes('output=2'));
+  });
+
   it('should throw a diagnostic when a macro cannot be resolved', async function () {
     await fsFixture(overlayFS, dir)`
       index.js:
@@ -16,7 +16,7 @@ use std::str::FromStr;
 
 use constant_module::ConstantModule;
 use indexmap::IndexMap;
-use parcel_macros::{MacroCallback, MacroError, Macros};
+use parcel_macros::{MacroCallback, MacroError, MacrosBuilder};
 use path_slash::PathExt;
 use serde::{Deserialize, Serialize};
 use swc_core::common::comments::SingleThreadedComments;
@@ -301,9 +301,17 @@ pub fn transform(
               let mut diagnostics = vec![];
               if let Some(call_macro) = call_macro {
                 let mut errors = Vec::new();
-                module = module.fold_with(&mut Macros::new(call_macro, &source_map, &mut errors));
+                let mut macros = MacrosBuilder::new(call_macro, &source_map)
+                  .unresolved_mark(unresolved_mark)
+                  .build(&mut errors);
+                module = module.fold_with(&mut macros);
+                let documentation_url = macros.documentation_url().to_owned();
                 for error in errors {
-                  diagnostics.push(macro_error_to_diagnostic(error, &source_map));
+                  diagnostics.push(macro_error_to_diagnostic(
+                    error,
+                    &source_map,
+                    &documentation_url,
+                  ));
                 }
               }
 
@@ -609,18 +617,54 @@ impl SourceMapGenConfig for SourceMapConfig {
   }
 }
 
-fn macro_error_to_diagnostic(error: MacroError, source_map: &SourceMap) -> Diagnostic {
+fn macro_error_to_diagnostic(
+  error: MacroError,
+  source_map: &SourceMap,
+  documentation_url: &str,
+) -> Diagnostic {
   match error {
-    MacroError::EvaluationError(span) => Diagnostic {
+    MacroError::EvaluationError(span, hint) => Diagnostic {
       message: "Could not statically evaluate macro argument".into(),
       code_highlights: Some(vec![CodeHighlight {
         message: None,
         loc: SourceLocation::from(source_map, span),
       }]),
-      hints: None,
+      hints: hint.map(|hint| vec![hint]),
+      show_environment: false,
+      severity: crate::utils::DiagnosticSeverity::Error,
+      documentation_url: Some(documentation_url.into()),
+    },
+    MacroError::MutationError(mutation, usage, description) => Diagnostic {
+      message: "Could not statically evaluate macro argument".into(),
+      code_highlights: Some(vec![
+        CodeHighlight {
+          message: Some(description.unwrap_or_else(|| "constant mutated here".into())),
+          loc: SourceLocation::from(source_map, mutation),
+        },
+        CodeHighlight {
+          message: Some("used by macro here".into()),
+          loc: SourceLocation::from(source_map, usage),
+        },
+      ]),
+      hints: Some(vec![
+        "Constants passed to macros must not be mutated.".into()
+      ]),
+      show_environment: false,
+      severity: crate::utils::DiagnosticSeverity::Error,
+      documentation_url: Some(documentation_url.into()),
+    },
+    MacroError::MissingProperty(prop, span) => Diagnostic {
+      message: format!("Property `{}` does not exist on the constant object", prop),
+      code_highlights: Some(vec![CodeHighlight {
+        message: None,
+        loc: SourceLocation::from(source_map, span),
+      }]),
+      hints: Some(vec![
+        "Use `??` to provide a default value for a property that may not exist.".into(),
+      ]),
       show_environment: false,
       severity: crate::utils::DiagnosticSeverity::Error,
-      documentation_url: None,
+      documentation_url: Some(documentation_url.into()),
     },
     MacroError::LoadError(err, span) => Diagnostic {
       message: format!("Error loading macro: {}", err),
@@ -631,7 +675,7 @@ fn macro_error_to_diagnostic(error: MacroError, source_map: &SourceMap) -> Diagn
       hints: None,
       show_environment: false,
       severity: crate::utils::DiagnosticSeverity::Error,
-      documentation_url: None,
+      documentation_url: Some(documentation_url.into()),
     },
     MacroError::ExecutionError(err, span) => Diagnostic {
       message: format!("Error evaluating macro: {}", err),
@@ -642,14 +686,101 @@ fn macro_error_to_diagnostic(error: MacroError, source_map: &SourceMap) -> Diagn
       hints: None,
       show_environment: false,
       severity: crate::utils::DiagnosticSeverity::Error,
-      documentation_url: None,
+      documentation_url: Some(documentation_url.into()),
+    },
+    MacroError::ExecutionWarning(err, span) => Diagnostic {
+      message: format!("Warning evaluating macro: {}", err),
+      code_highlights: Some(vec![CodeHighlight {
+        message: None,
+        loc: SourceLocation::from(source_map, span),
+      }]),
+      hints: None,
+      show_environment: false,
+      severity: crate::utils::DiagnosticSeverity::Warning,
+      documentation_url: Some(documentation_url.into()),
+    },
+    // There is no informational severity, so debug output is reported as a warning.
+    MacroError::ArgumentValue(value, span) => Diagnostic {
+      message: format!("Macro argument evaluated to {}", value),
+      code_highlights: Some(vec![CodeHighlight {
+        message: None,
+        loc: SourceLocation::from(source_map, span),
+      }]),
+      hints: None,
+      show_environment: false,
+      severity: crate::utils::DiagnosticSeverity::Warning,
+      documentation_url: Some(documentation_url.into()),
     },
     MacroError::ParseError(err) => {
       let error_buffer = ErrorBuffer::default();
       let handler = Handler::with_emitter(true, false, Box::new(error_buffer.clone()));
       err.into_diagnostic(&handler).emit();
       let mut diagnostics = error_buffer_to_diagnostics(&error_buffer, source_map);
-      return diagnostics.pop().unwrap();
+      let mut diagnostic = diagnostics.pop().unwrap();
+      diagnostic.documentation_url = Some(documentation_url.into());
+      diagnostic
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use swc_core::common::DUMMY_SP;
+
+  #[test]
+  fn macro_diagnostic_documentation_url() {
+    let source_map = SourceMap::default();
+    let diagnostic = macro_error_to_diagnostic(
+      MacroError::ExecutionError("oops".into(), DUMMY_SP),
+      &source_map,
+      parcel_macros::DOCUMENTATION_URL,
+    );
+
+    assert_eq!(
+      diagnostic.documentation_url.as_deref(),
+      Some("https://parceljs.org/features/macros/")
+    );
+  }
+
+  #[test]
+  fn macro_evaluation_diagnostic_messages() {
+    let source_map = SourceMap::default();
+    let message = |error| {
+      macro_error_to_diagnostic(error, &source_map, parcel_macros::DOCUMENTATION_URL).message
+    };
+
+    assert_eq!(
+      message(MacroError::EvaluationError(DUMMY_SP, None)),
+      "Could not statically evaluate macro argument"
+    );
+    assert_eq!(
+      message(MacroError::MissingProperty("x".into(), DUMMY_SP)),
+      "Property `x` does not exist on the constant object"
+    );
+  }
+
+  #[test]
+  fn macro_mutation_diagnostic() {
+    let source_map = SourceMap::default();
+    let diagnostic = macro_error_to_diagnostic(
+      MacroError::MutationError(DUMMY_SP, DUMMY_SP, None),
+      &source_map,
+      parcel_macros::DOCUMENTATION_URL,
+    );
+
+    let messages: Vec<_> = diagnostic
+      .code_highlights
+      .unwrap()
+      .into_iter()
+      .map(|highlight| highlight.message)
+      .collect();
+    assert_eq!(
+      messages,
+      vec![
+        Some("constant mutated here".into()),
+        Some("used by macro here".into())
+      ]
+    );
+  }
+}
@@ -172,6 +172,7 @@ type MacroContext = {|
   invalidateOnEnvChange(string): void,
   invalidateOnStartup(): void,
   invalidateOnBuild(): void,
+  warn(message: string): void,
 |};
 
 export default (new Transformer({
@@ -500,6 +501,7 @@ export default (new Transformer({
 
             try {
               if (typeof mod[exportName] === 'function') {
+                let warnings = [];
                 let ctx: MacroContext = {
                   // Allows macros to emit additional assets to add as dependencies (e.g. css).
                   addAsset(a: MacroAsset) {
@@ -562,9 +564,15 @@ export default (new Transformer({
                   invalidateOnBuild() {
                     asset.invalidateOnBuild();
                   },
+                  // Reports a warning at the macro call without failing it, e.g. for deprecations.
+                  warn(message) {
+                    warnings.push(String(message));
+                  },
                 };
 
-                return mod[exportName].apply(ctx, args);
+                // Await the result so that rejections of async macros are handled below.
+                let value = await mod[exportName].apply(ctx, args);
+                return {value, warnings};
               } else {
                 throw new Error(
                   `"${exportName}" in "${src}" is not a function.`,
@@ -580,8 +588,10 @@ export default (new Transformer({
                 }
                 message += '\n' + line;
               }
+              // Macros can throw an error with a `severity` of "warning" to report a warning
+              // and leave the call in place rather than failing the build.
               throw {
-                kind: 2,
+                kind: err?.severity === 'warning' ? 3 : 2,
                 message,
               };
             }